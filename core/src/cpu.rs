@@ -70,6 +70,7 @@ pub trait CoreData {
     fn set_regs(&mut self, values: &Regs);
 
     fn jump_and_link(emu: &mut Emu<Self::Engine>, addr: u32, lr: u32);
+    fn call_hle_callback(emu: &mut Emu<Self::Engine>, addr: u32, lr: u32, r0_3: [u32; 4]);
     fn return_from_hle_swi(emu: &mut Emu<Self::Engine>, r0_3: [u32; 4]);
 
     cfg_if::cfg_if! {
@@ -1455,6 +1455,24 @@ pub fn write_32<A: AccessType, E: Engine>(emu: &mut Emu<E>, mut addr: u32, value
                     // The KEY2 encryption seeds aren't used
                     0x1B0 | 0x1B4 => {}
 
+                    0x10_0010 => {
+                        if emu.ds_slot.arm7_access() {
+                            emu.ds_slot.write_rom_data_arm7(
+                                value,
+                                &mut emu.arm7.irqs,
+                                &mut emu.arm7.schedule,
+                            );
+                        } else {
+                            #[cfg(feature = "log")]
+                            if !A::IS_DEBUG {
+                                slog::warn!(
+                                    emu.arm7.logger,
+                                    "Tried to write to DS slot ROM data while inaccessible"
+                                );
+                            }
+                        }
+                    }
+
                     0x1C0 => {
                         emu.spi.write_control(spi::Control(value as u16));
                         emu.spi.write_data(
@@ -3,6 +3,7 @@ use crate::{
     cpu::{self, timers, Engine},
     ds_slot::DsSlot,
     emu::{self, Emu},
+    rtc::Rtc,
     utils::{
         schedule::{self, RawTimestamp},
         Savestate,
@@ -71,6 +72,7 @@ pub enum Event {
     DsSlotSpiDataReady, // Max 1
     SpiDataReady,       // Max 1
     AudioSampleReady,   // Max 1
+    RtcTick,            // Max 1
     #[cfg(feature = "xq-audio")]
     XqAudioSampleReady, // Max 1
     Timer(timers::Index), // Max 4
@@ -91,6 +93,7 @@ pub mod event_slots {
         DS_SLOT_SPI,
         SPI,
         AUDIO,
+        RTC,
         #[cfg(feature = "xq-audio")]
         XQ_AUDIO,
         TIMERS_START..TIMERS_END 4,
@@ -157,6 +160,7 @@ impl Schedule {
                 Event::DsSlotSpiDataReady => emu.ds_slot.handle_spi_data_ready(),
                 Event::SpiDataReady => emu.spi.handle_data_ready(&mut emu.arm7.irqs),
                 Event::AudioSampleReady => Audio::handle_sample_ready(emu, time),
+                Event::RtcTick => Rtc::handle_tick(emu, time),
                 #[cfg(feature = "xq-audio")]
                 Event::XqAudioSampleReady => Audio::handle_xq_sample_ready(emu, time),
                 Event::Timer(i) => emu.arm7.timers.handle_scheduled_overflow(
@@ -1514,6 +1514,24 @@ pub fn write_32<A: AccessType, E: Engine>(emu: &mut Emu<E>, mut addr: u32, mut v
                 // The KEY2 encryption seeds aren't used
                 0x1B0 | 0x1B4 => {}
 
+                0x10_0010 => {
+                    if emu.ds_slot.arm9_access() {
+                        emu.ds_slot.write_rom_data_arm9(
+                            value,
+                            &mut emu.arm9.irqs,
+                            &mut emu.arm9.schedule,
+                        );
+                    } else {
+                        #[cfg(feature = "log")]
+                        if !A::IS_DEBUG {
+                            slog::warn!(
+                                emu.arm9.logger,
+                                "Tried to write to DS slot ROM data while inaccessible"
+                            );
+                        }
+                    }
+                }
+
                 0x204 => {
                     emu.arm9
                         .write_local_ex_mem_control(LocalExMemControl(value as u8));
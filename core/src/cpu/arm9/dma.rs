@@ -11,6 +11,8 @@ use crate::{
     utils::{schedule::RawTimestamp, Savestate},
 };
 
+const DISPLAY_FIFO_ADDR: u32 = 0x0400_0068;
+
 #[repr(u8)]
 #[derive(Clone, Copy, Debug, PartialEq, Eq, Savestate)]
 pub enum Timing {
@@ -18,7 +20,7 @@ pub enum Timing {
     VBlank,       // x
     HBlank,       // x
     DisplayStart, // -
-    DisplayFifo,  // -
+    DisplayFifo,  // x
     DsSlot,       // x
     GbaSlot,      // -
     GxFifo,       // x
@@ -225,6 +227,22 @@ impl<E: Engine> Arm9<E> {
                     {
                         channel.remaining_batch_units = channel.remaining_units.min(112);
                         channel.remaining_units -= channel.remaining_batch_units;
+                    } else if channel.timing == Timing::DisplayFifo
+                        && channel.repeat
+                        && !emu.gpu.engine_2d_a.display_fifo_full()
+                    {
+                        // Keep feeding the display FIFO until the whole scanline has been buffered
+                        if channel.control.dst_addr_control() == 3 {
+                            let mask = !(1 | (channel.control.is_32_bit() as u32) << 1);
+                            channel.cur_dst_addr = channel.dst_addr & mask;
+                        }
+                        // Only writes to DISP_MMEM_FIFO fill it up, so stop as soon as the
+                        // destination doesn't point to it anymore
+                        if channel.cur_dst_addr & !3 != DISPLAY_FIFO_ADDR {
+                            emu.arm9.end_or_pause_dma_transfer(i);
+                            break;
+                        }
+                        channel.remaining_batch_units = channel.unit_count;
                     } else {
                         emu.arm9.end_or_pause_dma_transfer(i);
                         break;
@@ -287,6 +305,22 @@ impl<E: Engine> Arm9<E> {
                     {
                         channel.remaining_batch_units = channel.remaining_units.min(224);
                         channel.remaining_units -= channel.remaining_batch_units;
+                    } else if channel.timing == Timing::DisplayFifo
+                        && channel.repeat
+                        && !emu.gpu.engine_2d_a.display_fifo_full()
+                    {
+                        // Keep feeding the display FIFO until the whole scanline has been buffered
+                        if channel.control.dst_addr_control() == 3 {
+                            let mask = !(1 | (channel.control.is_32_bit() as u32) << 1);
+                            channel.cur_dst_addr = channel.dst_addr & mask;
+                        }
+                        // Only writes to DISP_MMEM_FIFO fill it up, so stop as soon as the
+                        // destination doesn't point to it anymore
+                        if channel.cur_dst_addr & !3 != DISPLAY_FIFO_ADDR {
+                            emu.arm9.end_or_pause_dma_transfer(i);
+                            break;
+                        }
+                        channel.remaining_batch_units = channel.unit_count;
                     } else {
                         emu.arm9.end_or_pause_dma_transfer(i);
                         break;
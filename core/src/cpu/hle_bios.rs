@@ -1,3 +1,4 @@
 pub mod arm7;
 pub mod arm9;
 mod common;
+mod decompress;
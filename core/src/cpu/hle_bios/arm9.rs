@@ -1,4 +1,7 @@
-use super::common;
+use super::{
+    common,
+    decompress::{self, CallbackAction, CallbackState, Kind},
+};
 use crate::{
     cpu::{
         arm9::{self, bus},
@@ -37,83 +40,87 @@ pub static BIOS: [u8; arm9::BIOS_SIZE] = {
     write_32!(0x018, BIOS_CALL_INSTR | 1);
     write_32!(0x200, BIOS_CALL_INSTR);
     write_32!(0x204, BIOS_CALL_INSTR | 2);
+    write_32!(0x208, BIOS_CALL_INSTR | 4);
     write_32!(0x298, BIOS_CALL_INSTR | 5);
 
     bytes
 };
 
-fn bit_unpack<E: Engine>(
-    _emu: &mut Emu<E>,
-    _src_addr: u32,
-    _dst_addr: u32,
-    _unpack_data_addr: u32,
-) {
-    // TODO
-    #[cfg(feature = "log")]
-    slog::error!(_emu.arm9.logger, "Unimplemented BitUnPack SWI");
-}
+const DECOMPRESS_CALLBACK_RETURN_ADDR: u32 = 0xFFFF_0208;
 
-fn diff_8_unfilter_write_8<E: Engine>(_emu: &mut Emu<E>) {
-    // TODO
-    #[cfg(feature = "log")]
-    slog::error!(
-        _emu.arm9.logger,
-        "Unimplemented Diff8bitUnFilterWrite8bit SWI"
-    );
-}
+struct CpuBus<'a, E: Engine>(&'a mut Emu<E>);
 
-fn diff_16_unfilter<E: Engine>(_emu: &mut Emu<E>) {
-    // TODO
-    #[cfg(feature = "log")]
-    slog::error!(
-        _emu.arm9.logger,
-        "Unimplemented Diff8bitUnFilterWrite16bit SWI"
-    );
-}
+impl<'a, E: Engine> decompress::Bus for CpuBus<'a, E> {
+    #[inline]
+    fn read_8(&mut self, addr: u32) -> u8 {
+        bus::read_8::<CpuAccess, _>(self.0, addr)
+    }
 
-fn huff_uncomp_read_callback<E: Engine>(_emu: &mut Emu<E>) {
-    // TODO
-    #[cfg(feature = "log")]
-    slog::error!(
-        _emu.arm9.logger,
-        "Unimplemented HuffUnCompReadByCallback SWI"
-    );
-}
+    #[inline]
+    fn read_16(&mut self, addr: u32) -> u16 {
+        bus::read_16::<CpuAccess, _>(self.0, addr)
+    }
 
-fn lz77_uncomp_read_normal_write_8<E: Engine>(_emu: &mut Emu<E>) {
-    // TODO
-    #[cfg(feature = "log")]
-    slog::error!(
-        _emu.arm9.logger,
-        "Unimplemented LZ77UnCompReadNormalWrite8bit SWI"
-    );
+    #[inline]
+    fn read_32(&mut self, addr: u32) -> u32 {
+        bus::read_32::<CpuAccess, _, false>(self.0, addr)
+    }
+
+    #[inline]
+    fn write_8(&mut self, addr: u32, value: u8) {
+        bus::write_8::<CpuAccess, _>(self.0, addr, value);
+    }
+
+    #[inline]
+    fn write_16(&mut self, addr: u32, value: u16) {
+        bus::write_16::<CpuAccess, _>(self.0, addr, value);
+    }
+
+    #[inline]
+    fn write_32(&mut self, addr: u32, value: u32) {
+        bus::write_32::<CpuAccess, _>(self.0, addr, value);
+    }
 }
 
-fn lz77_uncomp_read_callback_write_16<E: Engine>(_emu: &mut Emu<E>) {
-    // TODO
-    #[cfg(feature = "log")]
-    slog::error!(
-        _emu.arm9.logger,
-        "Unimplemented LZ77UnCompReadByCallbackWrite16bit SWI"
-    );
+fn uncomp_read_callback<E: Engine>(emu: &mut Emu<E>, kind: Kind, r0_3: [u32; 4]) {
+    emu.arm9.hle_bios.swi_r0_3 = r0_3;
+    let mut state = CallbackState::new(kind, r0_3);
+    let action = state.start(&mut CpuBus(emu));
+    emu.arm9.hle_bios.decompress_state = Some(state);
+    handle_decompress_callback_action(emu, action);
 }
 
-fn rl_uncomp_read_normal_write_8<E: Engine>(_emu: &mut Emu<E>) {
-    // TODO
-    #[cfg(feature = "log")]
-    slog::error!(
-        _emu.arm9.logger,
-        "Unimplemented RLUnCompReadNormalWrite8bit SWI"
-    );
+fn handle_decompress_callback_action<E: Engine>(emu: &mut Emu<E>, action: CallbackAction) {
+    match action {
+        CallbackAction::Call { addr, r0_3 } => {
+            E::Arm9Data::call_hle_callback(emu, addr, DECOMPRESS_CALLBACK_RETURN_ADDR, r0_3);
+        }
+        CallbackAction::Return(result) => {
+            emu.arm9.hle_bios.decompress_state = None;
+            let mut r0_3 = emu.arm9.hle_bios.swi_r0_3;
+            r0_3[0] = result;
+            E::Arm9Data::return_from_hle_swi(emu, r0_3);
+        }
+    }
 }
 
-fn rl_uncomp_read_callback_write_16<E: Engine>(_emu: &mut Emu<E>) {
-    // TODO
-    #[cfg(feature = "log")]
-    slog::error!(
-        _emu.arm9.logger,
-        "Unimplemented RLUnCompReadByCallbackWrite16bit SWI"
-    );
+pub fn resume_decompress<E: Engine>(emu: &mut Emu<E>) {
+    let mut state = match emu.arm9.hle_bios.decompress_state.take() {
+        Some(state) => state,
+        None => {
+            #[cfg(feature = "log")]
+            slog::warn!(
+                emu.arm9.logger,
+                "Returned to decompression callback handler outside of a decompression SWI"
+            );
+            handle_undefined_instr(emu);
+            return;
+        }
+    };
+    let result = emu.arm9.engine_data.regs().gprs[0];
+    let action = state.handle_result(&mut CpuBus(emu), result);
+    emu.arm9.hle_bios.decompress_state = Some(state);
+    handle_decompress_callback_action(emu, action);
 }
 
 fn cpu_set<E: Engine>(emu: &mut Emu<E>, r0_3: [u32; 4]) -> [u32; 2] {
@@ -245,6 +252,7 @@ fn wait_by_loop<E: Engine>(emu: &mut Emu<E>, iterations: i32) -> u32 {
 pub struct State {
     pub enabled: bool,
     swi_r0_3: [u32; 4],
+    decompress_state: Option<CallbackState>,
     intr_wait_mask: u32,
 }
 
@@ -253,6 +261,7 @@ impl State {
         State {
             enabled,
             swi_r0_3: [0; 4],
+            decompress_state: None,
             intr_wait_mask: 0,
         }
     }
@@ -343,21 +352,30 @@ pub fn handle_swi<E: Engine>(emu: &mut Emu<E>, number: u8, mut r0_3: [u32; 4]) {
 
         0x0F => (r0_3[0], r0_3[1], r0_3[3]) = common::is_debugger::<_, 0x7F_FFF8>(emu),
 
-        0x10 => bit_unpack(emu, r0_3[0], r0_3[1], r0_3[2]),
+        0x10 => decompress::bit_unpack(&mut CpuBus(emu), r0_3[0], r0_3[1], r0_3[2]),
 
-        0x11 => lz77_uncomp_read_normal_write_8(emu),
+        0x11 => decompress::uncomp_read_normal(&mut CpuBus(emu), Kind::Lz77, r0_3[0], r0_3[1]),
 
-        0x12 => lz77_uncomp_read_callback_write_16(emu),
+        0x12 => {
+            uncomp_read_callback(emu, Kind::Lz77, r0_3);
+            return;
+        }
 
-        0x13 => huff_uncomp_read_callback(emu),
+        0x13 => {
+            uncomp_read_callback(emu, Kind::Huffman, r0_3);
+            return;
+        }
 
-        0x14 => rl_uncomp_read_normal_write_8(emu),
+        0x14 => decompress::uncomp_read_normal(&mut CpuBus(emu), Kind::Rl, r0_3[0], r0_3[1]),
 
-        0x15 => rl_uncomp_read_callback_write_16(emu),
+        0x15 => {
+            uncomp_read_callback(emu, Kind::Rl, r0_3);
+            return;
+        }
 
-        0x16 => diff_8_unfilter_write_8(emu),
+        0x16 => decompress::diff_8_unfilter_write_8(&mut CpuBus(emu), r0_3[0], r0_3[1]),
 
-        0x18 => diff_16_unfilter(emu),
+        0x18 => decompress::diff_16_unfilter(&mut CpuBus(emu), r0_3[0], r0_3[1]),
 
         0x1F => {
             bus::write_32::<CpuAccess, _>(emu, 0x0400_0300, r0_3[0]);
@@ -0,0 +1,557 @@
+use crate::utils::Savestate;
+
+pub trait Bus {
+    fn read_8(&mut self, addr: u32) -> u8;
+    fn read_16(&mut self, addr: u32) -> u16;
+    fn read_32(&mut self, addr: u32) -> u32;
+    fn write_8(&mut self, addr: u32, value: u8);
+    fn write_16(&mut self, addr: u32, value: u16);
+    fn write_32(&mut self, addr: u32, value: u32);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SrcUnit {
+    Byte,
+    Word,
+}
+
+impl SrcUnit {
+    #[inline]
+    fn size(self) -> u32 {
+        match self {
+            SrcUnit::Byte => 1,
+            SrcUnit::Word => 4,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Savestate)]
+pub enum Kind {
+    Lz77,
+    Rl,
+    Huffman,
+}
+
+#[derive(Clone, Savestate)]
+struct Output {
+    dst_addr: u32,
+    halfword: u16,
+    write_16: bool,
+}
+
+impl Output {
+    fn new(dst_addr: u32, write_16: bool) -> Self {
+        Output {
+            dst_addr,
+            halfword: 0,
+            write_16,
+        }
+    }
+
+    // In 16-bit mode, bytes are buffered and written out in pairs; an unpaired trailing byte is
+    // never written
+    fn write_byte(&mut self, bus: &mut impl Bus, value: u8) {
+        if self.write_16 {
+            if self.dst_addr & 1 == 0 {
+                self.halfword = value as u16;
+            } else {
+                self.halfword |= (value as u16) << 8;
+                bus.write_16(self.dst_addr & !1, self.halfword);
+            }
+        } else {
+            bus.write_8(self.dst_addr, value);
+        }
+        self.dst_addr = self.dst_addr.wrapping_add(1);
+    }
+}
+
+#[derive(Clone, Savestate)]
+pub struct Lz77 {
+    output: Output,
+    remaining: u32,
+    flags: u8,
+    flags_left: u8,
+    block_high: Option<u8>,
+}
+
+impl Lz77 {
+    fn new(header: u32, dst_addr: u32, write_16: bool) -> Self {
+        Lz77 {
+            output: Output::new(dst_addr, write_16),
+            remaining: header >> 8,
+            flags: 0,
+            flags_left: 0,
+            block_high: None,
+        }
+    }
+
+    fn feed(&mut self, bus: &mut impl Bus, byte: u8) {
+        if self.flags_left == 0 {
+            self.flags = byte;
+            self.flags_left = 8;
+            return;
+        }
+
+        if self.flags & 0x80 == 0 {
+            self.output.write_byte(bus, byte);
+            self.remaining -= 1;
+        } else {
+            let block = match self.block_high.take() {
+                Some(high) => (high as u16) << 8 | byte as u16,
+                None => {
+                    self.block_high = Some(byte);
+                    return;
+                }
+            };
+            let len = (block >> 12) as u32 + 3;
+            let mut src_addr = self
+                .output
+                .dst_addr
+                .wrapping_sub((block & 0xFFF) as u32 + 1);
+            // Blocks that go past the end of the output are still copied in full (which may
+            // overrun the destination buffer)
+            for _ in 0..len {
+                // In 16-bit mode, the source is read back by halfwords, so a displacement of 0
+                // reads the previous (and not yet written) byte from memory rather than from the
+                // output buffer
+                let value = if self.output.write_16 {
+                    (bus.read_16(src_addr & !1) >> ((src_addr & 1) << 3)) as u8
+                } else {
+                    bus.read_8(src_addr)
+                };
+                self.output.write_byte(bus, value);
+                src_addr = src_addr.wrapping_add(1);
+            }
+            self.remaining = self.remaining.saturating_sub(len);
+        }
+
+        self.flags <<= 1;
+        self.flags_left -= 1;
+    }
+}
+
+#[derive(Clone, Copy, Savestate)]
+enum RlBlock {
+    Header,
+    Compressed(u8),
+    Uncompressed(u8),
+}
+
+#[derive(Clone, Savestate)]
+pub struct Rl {
+    output: Output,
+    remaining: u32,
+    padding: u32,
+    block: RlBlock,
+}
+
+impl Rl {
+    fn new(header: u32, dst_addr: u32, write_16: bool) -> Self {
+        let len = header >> 8;
+        Rl {
+            output: Output::new(dst_addr, write_16),
+            remaining: len,
+            padding: len.wrapping_neg() & 3,
+            block: RlBlock::Header,
+        }
+    }
+
+    fn feed(&mut self, bus: &mut impl Bus, byte: u8) {
+        match self.block {
+            RlBlock::Header => {
+                self.block = if byte & 0x80 == 0 {
+                    RlBlock::Uncompressed((byte & 0x7F) + 1)
+                } else {
+                    RlBlock::Compressed((byte & 0x7F) + 3)
+                };
+            }
+            RlBlock::Compressed(len) => {
+                let len = (len as u32).min(self.remaining);
+                for _ in 0..len {
+                    self.output.write_byte(bus, byte);
+                }
+                self.remaining -= len;
+                self.block = RlBlock::Header;
+            }
+            RlBlock::Uncompressed(len) => {
+                self.output.write_byte(bus, byte);
+                self.remaining -= 1;
+                self.block = if len == 1 {
+                    RlBlock::Header
+                } else {
+                    RlBlock::Uncompressed(len - 1)
+                };
+            }
+        }
+    }
+
+    // The output is zero-padded to a multiple of 4 bytes; in 16-bit mode, a pending unpaired byte
+    // is skipped over (and left unwritten) first
+    fn finish(&mut self, bus: &mut impl Bus) {
+        let mut dst_addr = self.output.dst_addr;
+        if self.output.write_16 {
+            let mut padding = self.padding as i32;
+            if dst_addr & 1 != 0 {
+                padding -= 1;
+                dst_addr = dst_addr.wrapping_add(1);
+            }
+            while padding > 0 {
+                bus.write_16(dst_addr, 0);
+                dst_addr = dst_addr.wrapping_add(2);
+                padding -= 2;
+            }
+        } else {
+            for _ in 0..self.padding {
+                bus.write_8(dst_addr, 0);
+                dst_addr = dst_addr.wrapping_add(1);
+            }
+        }
+        self.output.dst_addr = dst_addr;
+        self.padding = 0;
+    }
+}
+
+// The tree is copied byte by byte (size byte included) into a temporary buffer, since it needs to
+// be randomly accessed while the source can only be read sequentially; the bitstream is then read
+// by words.
+#[derive(Clone, Savestate)]
+pub struct Huffman {
+    dst_addr: u32,
+    remaining: u32,
+    data_bits: u8,
+    tree_addr: u32,
+    tree_write_addr: u32,
+    tree_bytes_left: u16,
+    node_addr: u32,
+    node: u8,
+    block: u32,
+    block_bits: u8,
+}
+
+impl Huffman {
+    fn new(header: u32, dst_addr: u32, tree_addr: u32) -> Self {
+        let data_bits = (header & 0xF) as u8;
+        Huffman {
+            dst_addr,
+            // Only data sizes that evenly divide a word are supported by the BIOS; anything else
+            // produces no output
+            remaining: if matches!(data_bits, 2 | 4 | 8) {
+                header >> 8
+            } else {
+                0
+            },
+            data_bits,
+            tree_addr,
+            tree_write_addr: tree_addr,
+            tree_bytes_left: 1,
+            node_addr: 0,
+            node: 0,
+            block: 0,
+            block_bits: 0,
+        }
+    }
+
+    fn src_unit(&self) -> SrcUnit {
+        if self.tree_bytes_left == 0 {
+            SrcUnit::Word
+        } else {
+            SrcUnit::Byte
+        }
+    }
+
+    fn feed(&mut self, bus: &mut impl Bus, value: u32) {
+        if self.tree_bytes_left != 0 {
+            let byte = value as u8;
+            if self.tree_write_addr == self.tree_addr {
+                self.tree_bytes_left = ((byte as u16) << 1) + 2;
+            }
+            bus.write_8(self.tree_write_addr, byte);
+            self.tree_write_addr = self.tree_write_addr.wrapping_add(1);
+            self.tree_bytes_left -= 1;
+            if self.tree_bytes_left == 0 {
+                self.node_addr = self.tree_addr.wrapping_add(1);
+                self.node = bus.read_8(self.node_addr);
+            }
+            return;
+        }
+
+        let mut bitstream = value;
+        for _ in 0..32 {
+            if self.remaining == 0 {
+                break;
+            }
+            let next_addr =
+                (self.node_addr & !1).wrapping_add(((self.node & 0x3F) as u32 + 1) << 1);
+            let (child_addr, is_data) = if bitstream & 1 << 31 == 0 {
+                (next_addr, self.node & 0x80 != 0)
+            } else {
+                (next_addr.wrapping_add(1), self.node & 0x40 != 0)
+            };
+            bitstream <<= 1;
+
+            if !is_data {
+                self.node_addr = child_addr;
+                self.node = bus.read_8(child_addr);
+                continue;
+            }
+
+            let data = bus.read_8(child_addr) as u32 & ((1 << self.data_bits) - 1);
+            self.block |= data << self.block_bits;
+            self.block_bits += self.data_bits;
+            self.node_addr = self.tree_addr.wrapping_add(1);
+            self.node = bus.read_8(self.node_addr);
+            if self.block_bits == 32 {
+                bus.write_32(self.dst_addr, self.block);
+                self.dst_addr = self.dst_addr.wrapping_add(4);
+                self.remaining = self.remaining.saturating_sub(4);
+                self.block = 0;
+                self.block_bits = 0;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Savestate)]
+pub enum Decompressor {
+    Lz77(Lz77),
+    Rl(Rl),
+    Huffman(Huffman),
+}
+
+impl Decompressor {
+    pub fn new(kind: Kind, header: u32, dst_addr: u32, write_16: bool, tree_addr: u32) -> Self {
+        match kind {
+            Kind::Lz77 => Decompressor::Lz77(Lz77::new(header, dst_addr, write_16)),
+            Kind::Rl => Decompressor::Rl(Rl::new(header, dst_addr, write_16)),
+            Kind::Huffman => Decompressor::Huffman(Huffman::new(header, dst_addr, tree_addr)),
+        }
+    }
+
+    pub fn src_unit(&self) -> SrcUnit {
+        match self {
+            Decompressor::Lz77(_) | Decompressor::Rl(_) => SrcUnit::Byte,
+            Decompressor::Huffman(huffman) => huffman.src_unit(),
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        match self {
+            Decompressor::Lz77(lz77) => lz77.remaining == 0,
+            Decompressor::Rl(rl) => rl.remaining == 0,
+            Decompressor::Huffman(huffman) => huffman.remaining == 0,
+        }
+    }
+
+    pub fn feed(&mut self, bus: &mut impl Bus, value: u32) {
+        match self {
+            Decompressor::Lz77(lz77) => lz77.feed(bus, value as u8),
+            Decompressor::Rl(rl) => rl.feed(bus, value as u8),
+            Decompressor::Huffman(huffman) => huffman.feed(bus, value),
+        }
+    }
+
+    pub fn finish(&mut self, bus: &mut impl Bus) {
+        if let Decompressor::Rl(rl) = self {
+            rl.finish(bus);
+        }
+    }
+}
+
+pub fn uncomp_read_normal(bus: &mut impl Bus, kind: Kind, mut src_addr: u32, dst_addr: u32) {
+    let header = bus.read_32(src_addr);
+    src_addr = src_addr.wrapping_add(4);
+    let mut decompressor = Decompressor::new(kind, header, dst_addr, false, 0);
+    while !decompressor.is_finished() {
+        let unit = decompressor.src_unit();
+        let value = match unit {
+            SrcUnit::Byte => bus.read_8(src_addr) as u32,
+            SrcUnit::Word => bus.read_32(src_addr),
+        };
+        src_addr = src_addr.wrapping_add(unit.size());
+        decompressor.feed(bus, value);
+    }
+    decompressor.finish(bus);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Savestate)]
+enum CallbackStage {
+    Open,
+    Read,
+    Close,
+}
+
+pub enum CallbackAction {
+    Call { addr: u32, r0_3: [u32; 4] },
+    Return(u32),
+}
+
+// Callback structure layout: open (and read header), close (optional), get 8-bit, get 16-bit
+// (unused), get 32-bit (only used by Huffman)
+#[derive(Clone, Savestate)]
+pub struct CallbackState {
+    kind: Kind,
+    stage: CallbackStage,
+    src_addr: u32,
+    dst_addr: u32,
+    param: u32,
+    callbacks_addr: u32,
+    len: u32,
+    decompressor: Option<Decompressor>,
+}
+
+impl CallbackState {
+    pub fn new(kind: Kind, r0_3: [u32; 4]) -> Self {
+        CallbackState {
+            kind,
+            stage: CallbackStage::Open,
+            src_addr: r0_3[0],
+            dst_addr: r0_3[1],
+            param: r0_3[2],
+            callbacks_addr: r0_3[3],
+            len: 0,
+            decompressor: None,
+        }
+    }
+
+    fn callback_args(&self) -> [u32; 4] {
+        [
+            self.src_addr,
+            self.dst_addr,
+            self.param,
+            self.callbacks_addr,
+        ]
+    }
+
+    pub fn start(&mut self, bus: &mut impl Bus) -> CallbackAction {
+        self.stage = CallbackStage::Open;
+        CallbackAction::Call {
+            addr: bus.read_32(self.callbacks_addr),
+            r0_3: self.callback_args(),
+        }
+    }
+
+    pub fn handle_result(&mut self, bus: &mut impl Bus, result: u32) -> CallbackAction {
+        match self.stage {
+            CallbackStage::Open => {
+                if (result as i32) < 0 {
+                    return CallbackAction::Return(result);
+                }
+                self.src_addr = self.src_addr.wrapping_add(4);
+                self.len = result >> 8;
+                // The parameter register holds the tree buffer address for Huffman
+                self.decompressor = Some(Decompressor::new(
+                    self.kind,
+                    result,
+                    self.dst_addr,
+                    self.kind != Kind::Huffman,
+                    self.param,
+                ));
+            }
+            CallbackStage::Read => {
+                let decompressor = self.decompressor.as_mut().unwrap();
+                self.src_addr = self.src_addr.wrapping_add(decompressor.src_unit().size());
+                decompressor.feed(bus, result);
+            }
+            CallbackStage::Close => {
+                return CallbackAction::Return(if (result as i32) < 0 {
+                    result
+                } else {
+                    self.len
+                });
+            }
+        }
+
+        let decompressor = self.decompressor.as_mut().unwrap();
+        if decompressor.is_finished() {
+            decompressor.finish(bus);
+            let close_addr = bus.read_32(self.callbacks_addr.wrapping_add(4));
+            if close_addr == 0 {
+                return CallbackAction::Return(self.len);
+            }
+            self.stage = CallbackStage::Close;
+            return CallbackAction::Call {
+                addr: close_addr,
+                r0_3: self.callback_args(),
+            };
+        }
+
+        self.stage = CallbackStage::Read;
+        let callback_offset = match decompressor.src_unit() {
+            SrcUnit::Byte => 8,
+            SrcUnit::Word => 0x10,
+        };
+        CallbackAction::Call {
+            addr: bus.read_32(self.callbacks_addr.wrapping_add(callback_offset)),
+            r0_3: self.callback_args(),
+        }
+    }
+}
+
+pub fn bit_unpack(bus: &mut impl Bus, mut src_addr: u32, mut dst_addr: u32, unpack_data_addr: u32) {
+    let mut src_len = bus.read_16(unpack_data_addr);
+    let src_width = bus.read_8(unpack_data_addr.wrapping_add(2)) as u32;
+    let dst_width = bus.read_8(unpack_data_addr.wrapping_add(3)) as u32;
+    if !matches!(src_width, 1 | 2 | 4 | 8) || !matches!(dst_width, 1 | 2 | 4 | 8 | 16 | 32) {
+        return;
+    }
+    let offset = bus.read_32(unpack_data_addr.wrapping_add(4));
+    let src_mask = (1 << src_width) - 1;
+    let dst_mask = if dst_width == 32 {
+        u32::MAX
+    } else {
+        (1 << dst_width) - 1
+    };
+
+    let mut src_byte = 0;
+    let mut src_bits_left = 0;
+    let mut dst_word = 0;
+    let mut dst_bits = 0;
+    while src_len != 0 || src_bits_left != 0 {
+        if src_bits_left == 0 {
+            src_byte = bus.read_8(src_addr) as u32;
+            src_addr = src_addr.wrapping_add(1);
+            src_len -= 1;
+            src_bits_left = 8;
+        }
+        let mut value = src_byte & src_mask;
+        src_byte >>= src_width;
+        src_bits_left -= src_width;
+        // Bit 31 of the offset word selects whether zero values get the offset added too
+        if value != 0 || offset & 1 << 31 != 0 {
+            value = value.wrapping_add(offset & 0x7FFF_FFFF);
+        }
+        dst_word |= (value & dst_mask) << dst_bits;
+        dst_bits += dst_width;
+        if dst_bits == 32 {
+            bus.write_32(dst_addr, dst_word);
+            dst_addr = dst_addr.wrapping_add(4);
+            dst_word = 0;
+            dst_bits = 0;
+        }
+    }
+}
+
+pub fn diff_8_unfilter_write_8(bus: &mut impl Bus, mut src_addr: u32, mut dst_addr: u32) {
+    let len = bus.read_32(src_addr) >> 8;
+    src_addr = src_addr.wrapping_add(4);
+    let mut value = 0_u8;
+    for _ in 0..len {
+        value = value.wrapping_add(bus.read_8(src_addr));
+        bus.write_8(dst_addr, value);
+        src_addr = src_addr.wrapping_add(1);
+        dst_addr = dst_addr.wrapping_add(1);
+    }
+}
+
+pub fn diff_16_unfilter(bus: &mut impl Bus, mut src_addr: u32, mut dst_addr: u32) {
+    let mut remaining = bus.read_32(src_addr) >> 8;
+    src_addr = src_addr.wrapping_add(4);
+    let mut value = 0_u16;
+    while remaining != 0 {
+        value = value.wrapping_add(bus.read_16(src_addr));
+        bus.write_16(dst_addr, value);
+        src_addr = src_addr.wrapping_add(2);
+        dst_addr = dst_addr.wrapping_add(2);
+        remaining = remaining.saturating_sub(2);
+    }
+}
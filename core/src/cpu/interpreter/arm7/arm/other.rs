@@ -84,6 +84,10 @@ pub fn undefined<const MAYBE_HLE_BIOS_CALL: bool>(emu: &mut Emu<Interpreter>, in
                 hle_bios::arm7::handle_undefined_instr(emu);
                 return;
             }
+            4 => {
+                hle_bios::arm7::resume_decompress(emu);
+                return;
+            }
             5 => {
                 let return_addr = reg!(emu.arm7, 14);
                 let number = bus::read_8::<CpuAccess, _>(emu, return_addr.wrapping_sub(2));
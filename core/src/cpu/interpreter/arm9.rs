@@ -952,6 +952,12 @@ impl CoreData for EngineData {
         reload_pipeline::<{ StateSource::R15Bit0 }>(emu);
     }
 
+    #[inline]
+    fn call_hle_callback(emu: &mut Emu<Interpreter>, addr: u32, lr: u32, r0_3: [u32; 4]) {
+        emu.arm9.engine_data.regs.set_r0_3(r0_3);
+        Self::jump_and_link(emu, addr, lr);
+    }
+
     #[inline]
     fn return_from_hle_swi(emu: &mut Emu<Self::Engine>, r0_3: [u32; 4]) {
         emu.arm9.engine_data.regs.set_r0_3(r0_3);
@@ -93,6 +93,10 @@ pub fn undefined<const MAYBE_HLE_BIOS_SWI: bool>(emu: &mut Emu<Interpreter>, ins
                 hle_bios::arm9::handle_undefined_instr(emu);
                 return;
             }
+            4 => {
+                hle_bios::arm9::resume_decompress(emu);
+                return;
+            }
             5 => {
                 let return_addr = reg!(emu.arm9, 14);
                 let number = bus::read_8::<CpuAccess, _>(emu, return_addr.wrapping_sub(2));
@@ -27,7 +27,7 @@ pub mod spi;
 use crate::{
     cpu::{arm7, arm9, Engine, Schedule as _},
     emu::{Emu, Timestamp},
-    utils::{schedule::RawTimestamp, zeroed_box, Bytes, Savestate},
+    utils::{schedule::RawTimestamp, zeroed_box, ByteMutSlice, ByteSlice, Bytes, Savestate},
 };
 
 proc_bitfield::bitfield! {
@@ -287,6 +287,13 @@ impl DsSlot {
                 schedule.schedule_event(arm7::event_slots::DS_SLOT_ROM, target);
             } else {
                 self.rom_control.set_busy(false);
+                if self.rom_control.write_enabled() {
+                    self.rom.handle_rom_data_write(
+                        self.rom_cmd.clone(),
+                        &self.rom_output_buffer,
+                        self.rom_output_len,
+                    );
+                }
                 if self.spi_control.rom_transfer_complete_irq_enabled() {
                     irqs.write_requested(
                         irqs.requested().with_ds_slot_transfer_complete(true),
@@ -298,6 +305,21 @@ impl DsSlot {
         self.rom_data_out
     }
 
+    pub(crate) fn write_rom_data_arm7(
+        &mut self,
+        value: u32,
+        irqs: &mut arm7::Irqs,
+        schedule: &mut arm7::Schedule,
+    ) {
+        if !self.rom_control.write_enabled() || !self.rom_control.data_ready() {
+            return;
+        }
+        self.rom_output_buffer
+            .write_le(self.rom_output_pos.get() as usize, value);
+        // Writes advance the transfer in the same way reads do
+        self.read_rom_data_arm7(irqs, schedule);
+    }
+
     pub(crate) fn read_rom_data_arm9(
         &mut self,
         irqs: &mut arm9::Irqs,
@@ -319,6 +341,13 @@ impl DsSlot {
                 schedule.schedule_event(arm9::event_slots::DS_SLOT_ROM, target);
             } else {
                 self.rom_control.set_busy(false);
+                if self.rom_control.write_enabled() {
+                    self.rom.handle_rom_data_write(
+                        self.rom_cmd.clone(),
+                        &self.rom_output_buffer,
+                        self.rom_output_len,
+                    );
+                }
                 if self.spi_control.rom_transfer_complete_irq_enabled() {
                     irqs.write_requested(
                         irqs.requested().with_ds_slot_transfer_complete(true),
@@ -330,6 +359,21 @@ impl DsSlot {
         self.rom_data_out
     }
 
+    pub(crate) fn write_rom_data_arm9(
+        &mut self,
+        value: u32,
+        irqs: &mut arm9::Irqs,
+        schedule: &mut arm9::Schedule,
+    ) {
+        if !self.rom_control.write_enabled() || !self.rom_control.data_ready() {
+            return;
+        }
+        self.rom_output_buffer
+            .write_le(self.rom_output_pos.get() as usize, value);
+        // Writes advance the transfer in the same way reads do
+        self.read_rom_data_arm9(irqs, schedule);
+    }
+
     #[inline]
     pub const fn spi_data_out(&self) -> u8 {
         // TODO: What's the response while busy?
@@ -371,6 +415,40 @@ impl DsSlot {
         self.spi_control.set_spi_busy(false);
     }
 
+    /// Returns the contents of the save memory, stored in the ROM chip itself for NAND cartridges
+    /// and in the SPI device otherwise.
+    #[inline]
+    pub fn save_contents(&self) -> ByteSlice {
+        match &self.rom {
+            rom::Rom::Nand(rom) => rom.save_contents(),
+            _ => self.spi.contents(),
+        }
+    }
+
+    #[inline]
+    pub fn save_contents_mut(&mut self) -> ByteMutSlice {
+        match &mut self.rom {
+            rom::Rom::Nand(rom) => rom.save_contents_mut(),
+            _ => self.spi.contents_mut(),
+        }
+    }
+
+    #[inline]
+    pub fn save_contents_dirty(&self) -> bool {
+        match &self.rom {
+            rom::Rom::Nand(rom) => rom.save_contents_dirty(),
+            _ => self.spi.contents_dirty(),
+        }
+    }
+
+    #[inline]
+    pub fn mark_save_contents_flushed(&mut self) {
+        match &mut self.rom {
+            rom::Rom::Nand(rom) => rom.mark_save_contents_flushed(),
+            _ => self.spi.mark_contents_flushed(),
+        }
+    }
+
     #[inline]
     pub const fn arm7_access(&self) -> bool {
         self.arm7_access
@@ -3,6 +3,7 @@ mod key1;
 pub use empty::Empty;
 pub mod header;
 pub mod icon;
+pub mod nand;
 pub mod normal;
 
 use super::RomOutputLen;
@@ -18,26 +19,33 @@ trait RomDevice {
         output: &mut Bytes<0x4000>,
         output_len: RomOutputLen,
     );
+    fn handle_rom_data_write(
+        &mut self,
+        cmd: Bytes<8>,
+        input: &Bytes<0x4000>,
+        input_len: RomOutputLen,
+    );
 }
 
 #[derive(Clone, Savestate)]
 #[load(in_place_only)]
 pub enum Rom {
     Normal(normal::Normal),
+    Nand(nand::Nand),
     Empty(Empty),
 }
 
 impl Rom {
     pub fn read(&self, addr: u32, output: ByteMutSlice) {
-        handle_variants!(Rom; Normal, Empty; self, read(addr, output));
+        handle_variants!(Rom; Normal, Nand, Empty; self, read(addr, output));
     }
 
     pub fn chip_id(&self) -> u32 {
-        handle_variants!(Rom; Normal, Empty; self, chip_id())
+        handle_variants!(Rom; Normal, Nand, Empty; self, chip_id())
     }
 
     pub(crate) fn setup(&mut self, direct_boot: bool) {
-        handle_variants!(Rom; Normal, Empty; self, setup(direct_boot));
+        handle_variants!(Rom; Normal, Nand, Empty; self, setup(direct_boot));
     }
 
     pub fn handle_rom_command(
@@ -46,8 +54,21 @@ impl Rom {
         output: &mut Bytes<0x4000>,
         output_len: RomOutputLen,
     ) {
-        handle_variants!(Rom; Normal, Empty; self, handle_rom_command(cmd, output, output_len));
+        handle_variants!(
+            Rom; Normal, Nand, Empty; self, handle_rom_command(cmd, output, output_len)
+        );
+    }
+
+    pub fn handle_rom_data_write(
+        &mut self,
+        cmd: Bytes<8>,
+        input: &Bytes<0x4000>,
+        input_len: RomOutputLen,
+    ) {
+        handle_variants!(
+            Rom; Normal, Nand, Empty; self, handle_rom_data_write(cmd, input, input_len)
+        );
     }
 }
 
-impl_from_variants!(Rom; Normal, Empty; normal::Normal, Empty);
+impl_from_variants!(Rom; Normal, Nand, Empty; normal::Normal, nand::Nand, Empty);
@@ -48,4 +48,13 @@ impl super::RomDevice for Empty {
         // the data bus or does it get filled with 0xFF? GBATEK seems to imply the latter.
         output[..output_len.get() as usize].fill(0xFF);
     }
+
+    #[allow(clippy::needless_pass_by_value)]
+    fn handle_rom_data_write(
+        &mut self,
+        _cmd: Bytes<8>,
+        _input: &Bytes<0x4000>,
+        _input_len: RomOutputLen,
+    ) {
+    }
 }
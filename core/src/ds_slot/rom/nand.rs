@@ -0,0 +1,323 @@
+use super::{
+    super::RomOutputLen,
+    header::Header,
+    normal::{self, Normal, Stage},
+    RomDevice,
+};
+use crate::{
+    cpu::arm7,
+    utils::{make_zero, BoxedByteSlice, ByteMutSlice, ByteSlice, Bytes, Savestate},
+    SaveContents,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CreationError {
+    SizeNotPowerOfTwo,
+    InvalidHeader,
+    MissingSaveRegion,
+    SaveSizeNotPowerOfTwo,
+}
+
+impl From<normal::CreationError> for CreationError {
+    fn from(err: normal::CreationError) -> Self {
+        match err {
+            normal::CreationError::SizeNotPowerOfTwo => CreationError::SizeNotPowerOfTwo,
+        }
+    }
+}
+
+proc_bitfield::bitfield! {
+    #[derive(Clone, Copy, PartialEq, Eq, Savestate)]
+    pub const struct Status(pub u8): Debug {
+        pub write_enabled: bool @ 4,
+        pub ready: bool @ 5,
+    }
+}
+
+// The NAND is written in 2 KiB pages, assembled from 512-byte write commands
+const PAGE_SIZE: usize = 0x800;
+// Save windows selected through command B2h are aligned to 128 KiB
+const WINDOW_MASK: u32 = 0x1_FFFF;
+
+#[derive(Clone, Savestate)]
+#[load(in_place_only)]
+pub struct Nand {
+    #[cfg(feature = "log")]
+    #[savestate(skip)]
+    logger: slog::Logger,
+    rom: Normal,
+
+    #[savestate(skip)]
+    save_contents: BoxedByteSlice,
+    #[savestate(skip)]
+    save_contents_dirty: bool,
+    #[savestate(skip)]
+    save_start: u32,
+    #[savestate(skip)]
+    rom_end: u32,
+
+    status: Status,
+    save_window: Option<u32>,
+    write_addr: u32,
+    write_buffer: Box<Bytes<PAGE_SIZE>>,
+    write_buffer_len: u16,
+}
+
+impl Nand {
+    /// # Errors
+    /// - [`CreationError::SizeNotPowerOfTwo`](CreationError::SizeNotPowerOfTwo): the ROM contents'
+    ///   size is not a power of two.
+    /// - [`CreationError::InvalidHeader`](CreationError::InvalidHeader): the ROM header couldn't
+    ///   be parsed.
+    /// - [`CreationError::MissingSaveRegion`](CreationError::MissingSaveRegion): the ROM header
+    ///   doesn't specify where the NAND read/write region starts.
+    /// - [`CreationError::SaveSizeNotPowerOfTwo`](CreationError::SaveSizeNotPowerOfTwo): the save
+    ///   contents' size is not a power of two.
+    pub fn new(
+        rom: BoxedByteSlice,
+        save_contents: SaveContents,
+        arm7_bios: Option<&Bytes<{ arm7::BIOS_SIZE }>>,
+        #[cfg(feature = "log")] logger: slog::Logger,
+    ) -> Result<Self, CreationError> {
+        let (rom_end, save_start) = {
+            let header = Header::new(rom.as_byte_slice()).ok_or(CreationError::InvalidHeader)?;
+            (
+                header.nand_raw_rom_end() as u32 * 0x2_0000,
+                header.nand_raw_rw_start() as u32 * 0x2_0000,
+            )
+        };
+        if save_start == 0 {
+            return Err(CreationError::MissingSaveRegion);
+        }
+        if !save_contents.len().is_power_of_two() {
+            return Err(CreationError::SaveSizeNotPowerOfTwo);
+        }
+        let mut rom = Normal::new(
+            rom,
+            arm7_bios,
+            #[cfg(feature = "log")]
+            logger.clone(),
+        )?;
+        // Bit 27 of the chip ID marks NAND cartridges
+        rom.chip_id |= 1 << 27;
+        Ok(Nand {
+            #[cfg(feature = "log")]
+            logger,
+            rom,
+
+            save_contents: save_contents.get_or_create(|len| {
+                let mut contents = BoxedByteSlice::new_zeroed(len);
+                contents.fill(0xFF);
+                contents
+            }),
+            save_contents_dirty: false,
+            save_start,
+            rom_end,
+
+            status: Status(0).with_ready(true),
+            save_window: None,
+            write_addr: 0,
+            write_buffer: Box::new(Bytes::new([0xFF; PAGE_SIZE])),
+            write_buffer_len: 0,
+        })
+    }
+
+    #[must_use]
+    pub fn reset(self) -> Self {
+        Nand {
+            rom: self.rom.reset(),
+            status: Status(0).with_ready(true),
+            save_window: None,
+            write_addr: 0,
+            write_buffer_len: 0,
+            ..self
+        }
+    }
+
+    #[inline]
+    pub fn save_contents(&self) -> ByteSlice {
+        self.save_contents.as_byte_slice()
+    }
+
+    #[inline]
+    pub fn save_contents_mut(&mut self) -> ByteMutSlice {
+        self.save_contents.as_byte_mut_slice()
+    }
+
+    #[inline]
+    pub fn save_contents_dirty(&self) -> bool {
+        self.save_contents_dirty
+    }
+
+    #[inline]
+    pub fn mark_save_contents_dirty(&mut self) {
+        self.save_contents_dirty = true;
+    }
+
+    #[inline]
+    pub fn mark_save_contents_flushed(&mut self) {
+        self.save_contents_dirty = false;
+    }
+
+    #[inline]
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    #[inline]
+    pub fn save_window(&self) -> Option<u32> {
+        self.save_window
+    }
+
+    fn save_range_contains(&self, addr: u32) -> bool {
+        addr >= self.save_start && addr - self.save_start < self.save_contents.len() as u32
+    }
+}
+
+impl RomDevice for Nand {
+    fn read(&self, addr: u32, output: ByteMutSlice) {
+        self.rom.read(addr, output);
+    }
+
+    fn chip_id(&self) -> u32 {
+        self.rom.chip_id
+    }
+
+    fn setup(&mut self, direct_boot: bool) {
+        self.rom.setup(direct_boot);
+    }
+
+    fn handle_rom_command(
+        &mut self,
+        cmd: Bytes<8>,
+        output: &mut Bytes<0x4000>,
+        output_len: RomOutputLen,
+    ) {
+        if self.rom.stage != Stage::Key2 {
+            return self.rom.handle_rom_command(cmd, output, output_len);
+        }
+
+        match cmd[0] {
+            0x81 => {
+                // Write data, the actual contents are handled when the transfer finishes
+            }
+
+            0x82 => {
+                // Program the write buffer into the NAND
+                if self.status.write_enabled() && self.write_buffer_len != 0 {
+                    // `write_addr` starts at the save window, which can't be below the save
+                    // region, but keeps growing with every program and can run past its end
+                    let offset = (self.write_addr - self.save_start) as usize;
+                    if offset < self.save_contents.len() {
+                        let len =
+                            (self.write_buffer_len as usize).min(self.save_contents.len() - offset);
+                        self.save_contents[offset..offset + len]
+                            .copy_from_slice(&self.write_buffer[..len]);
+                        self.save_contents_dirty = true;
+                    } else {
+                        #[cfg(feature = "log")]
+                        slog::warn!(
+                            self.logger,
+                            "Programming outside of the save region: {:#010X}",
+                            self.write_addr
+                        );
+                    }
+                    self.write_addr = self.write_addr.saturating_add(self.write_buffer_len as u32);
+                    self.write_buffer_len = 0;
+                }
+                make_zero(&mut output[..output_len.get() as usize]);
+            }
+
+            0x84 => {
+                // Discard the write buffer
+                self.write_buffer_len = 0;
+                make_zero(&mut output[..output_len.get() as usize]);
+            }
+
+            0x85 => {
+                // Write enable
+                if let Some(window) = self.save_window {
+                    self.status.set_write_enabled(true);
+                    self.write_addr = window;
+                    self.write_buffer_len = 0;
+                } else {
+                    #[cfg(feature = "log")]
+                    slog::warn!(self.logger, "Write enable outside of save mode");
+                }
+                make_zero(&mut output[..output_len.get() as usize]);
+            }
+
+            0x8B => {
+                // Exit save mode
+                self.save_window = None;
+                self.status.set_write_enabled(false);
+                make_zero(&mut output[..output_len.get() as usize]);
+            }
+
+            0x94 => {
+                // NAND init, the returned data seems to be unused
+                make_zero(&mut output[..output_len.get() as usize]);
+            }
+
+            0xB2 => {
+                // Enter save mode
+                let addr = cmd.read_be::<u32>(1);
+                if self.save_range_contains(addr) {
+                    // The save region's start is a multiple of 128 KiB already, but clamp the
+                    // window to it anyway so offsets from it can never underflow
+                    self.save_window = Some((addr & !WINDOW_MASK).max(self.save_start));
+                } else {
+                    #[cfg(feature = "log")]
+                    slog::warn!(
+                        self.logger,
+                        "Selected save window outside of the save region: {:#010X}",
+                        addr
+                    );
+                }
+                make_zero(&mut output[..output_len.get() as usize]);
+            }
+
+            0xB7 => {
+                let addr = cmd.read_be::<u32>(1);
+                if self.save_window.is_some() && self.save_range_contains(addr) {
+                    let offset = (addr - self.save_start) as usize;
+                    let len = (output_len.get() as usize).min(self.save_contents.len() - offset);
+                    output[..len].copy_from_slice(&self.save_contents[offset..offset + len]);
+                    output[len..output_len.get() as usize].fill(0xFF);
+                } else if self.rom_end != 0 && addr >= self.rom_end {
+                    // Outside of both the read-only and read/write regions
+                    output[..output_len.get() as usize].fill(0xFF);
+                } else {
+                    self.rom.handle_rom_command(cmd, output, output_len);
+                }
+            }
+
+            0xD6 => {
+                // Get status
+                output[..output_len.get() as usize].fill(self.status.0);
+            }
+
+            _ => self.rom.handle_rom_command(cmd, output, output_len),
+        }
+    }
+
+    fn handle_rom_data_write(
+        &mut self,
+        cmd: Bytes<8>,
+        input: &Bytes<0x4000>,
+        input_len: RomOutputLen,
+    ) {
+        if self.rom.stage != Stage::Key2 || cmd[0] != 0x81 {
+            return self.rom.handle_rom_data_write(cmd, input, input_len);
+        }
+        if !self.status.write_enabled() {
+            #[cfg(feature = "log")]
+            slog::warn!(self.logger, "Write data while writes are disabled");
+            return;
+        }
+        let start = self.write_buffer_len as usize;
+        let len = (input_len.get() as usize).min(PAGE_SIZE - start);
+        self.write_buffer[start..start + len].copy_from_slice(&input[..len]);
+        self.write_buffer_len += len as u16;
+    }
+}
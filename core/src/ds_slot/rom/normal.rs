@@ -10,7 +10,7 @@ pub enum CreationError {
 }
 
 #[derive(Clone, Copy, PartialEq, Eq, Savestate)]
-enum Stage {
+pub(super) enum Stage {
     Initial,
     Key1,
     Key2,
@@ -28,10 +28,10 @@ pub struct Normal {
     #[savestate(skip)]
     rom_mask: u32,
     #[savestate(skip)]
-    chip_id: u32,
+    pub(super) chip_id: u32,
     #[savestate(skip)]
     key_buf: Option<Box<key1::KeyBuffer<false>>>, // Always at level 2
-    stage: Stage,
+    pub(super) stage: Stage,
 }
 
 impl Normal {
@@ -294,4 +294,19 @@ impl super::RomDevice for Normal {
               // }
         }
     }
+
+    #[allow(clippy::needless_pass_by_value)]
+    fn handle_rom_data_write(
+        &mut self,
+        _cmd: Bytes<8>,
+        _input: &Bytes<0x4000>,
+        _input_len: RomOutputLen,
+    ) {
+        #[cfg(feature = "log")]
+        slog::warn!(
+            self.logger,
+            "Unexpected ROM data write for command {:016X}",
+            _cmd.read_be::<u64>(0)
+        );
+    }
 }
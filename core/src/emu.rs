@@ -3,6 +3,7 @@ pub use schedule::{
     event_slots, Event, EventSlotIndex, Schedule, Timestamp, DEFAULT_BATCH_DURATION,
 };
 pub mod input;
+pub mod savestate;
 pub mod swram;
 
 use crate::{
@@ -18,6 +19,7 @@ use crate::{
     flash::Flash,
     gpu::{self, engine_3d::Engine3d, Gpu},
     ipc::Ipc,
+    mic,
     rtc::{self, Rtc},
     spi,
     utils::{
@@ -114,6 +116,8 @@ pub struct Emu<E: cpu::Engine> {
     pub audio: Audio,
     pub wifi: WiFi,
     rcnt: u16, // TODO: Move to SIO
+    #[savestate(skip)]
+    model: Model,
     is_debugger: bool,
 }
 
@@ -161,6 +165,7 @@ pub struct Builder {
     pub ds_rom: ds_slot::rom::Rom,
     pub ds_spi: ds_slot::spi::Spi,
     pub audio_backend: Box<dyn audio::Backend>,
+    pub mic_backend: Box<dyn mic::Backend>,
     pub rtc_backend: Box<dyn rtc::Backend>,
     pub renderer_3d: Box<dyn gpu::engine_3d::Renderer>,
 
@@ -206,6 +211,7 @@ impl Builder {
         ds_rom: ds_slot::rom::Rom,
         ds_spi: ds_slot::spi::Spi,
         audio_backend: Box<dyn audio::Backend>,
+        mic_backend: Box<dyn mic::Backend>,
         rtc_backend: Box<dyn rtc::Backend>,
         renderer_3d: Box<dyn gpu::engine_3d::Renderer>,
         #[cfg(feature = "log")] logger: slog::Logger,
@@ -218,6 +224,7 @@ impl Builder {
             ds_rom,
             ds_spi,
             audio_backend,
+            mic_backend,
             rtc_backend,
             renderer_3d,
 
@@ -278,6 +285,7 @@ impl Builder {
             ),
             spi: spi::Controller::new(
                 self.firmware,
+                self.mic_backend,
                 self.model,
                 &mut arm7.schedule,
                 &mut global_schedule,
@@ -287,6 +295,7 @@ impl Builder {
             rtc: Rtc::new(
                 self.rtc_backend,
                 self.first_launch,
+                &mut arm7.schedule,
                 #[cfg(feature = "log")]
                 self.logger.new(slog::o!("rtc" => "")),
             ),
@@ -315,6 +324,7 @@ impl Builder {
             schedule: global_schedule,
             arm7,
             arm9,
+            model: self.model,
             is_debugger: self.is_debugger,
         };
         Arm7::setup(&mut emu);
@@ -494,6 +504,11 @@ impl<E: cpu::Engine> Emu<E> {
         E::Arm9Data::setup_direct_boot(self, header.arm9_entry_addr());
     }
 
+    #[inline]
+    pub fn model(&self) -> Model {
+        self.model
+    }
+
     #[inline]
     pub fn is_debugger(&self) -> bool {
         self.is_debugger
@@ -0,0 +1,350 @@
+//! Versioned container for savestates.
+//!
+//! The raw field stream produced by the `Savestate` derive has no notion of what it was produced
+//! from, so loading one into an incompatible `Emu` would silently corrupt it. The container
+//! prepends a header identifying the format version, the emulated model and the inserted ROM, which
+//! is checked before the field stream is touched.
+//!
+//! Layout (all values little-endian):
+//! - `0x00`: magic (`b"DUSTSAVE"`)
+//! - `0x08`: format version (`u32`)
+//! - `0x0C`: header length in bytes, including the magic and version (`u32`)
+//! - `0x10`: model (`u8`)
+//! - `0x11`: flags (`u8`); bit 0 is set if the field stream is compressed
+//! - `0x12`: ROM header CRC (`u16`)
+//! - `0x14`: ROM game code (`u32`)
+//! - `0x18`: stored field stream length in bytes (`u64`)
+//! - `0x20`: uncompressed field stream length in bytes (`u64`)
+//! - header length: field stream
+//!
+//! Most of the field stream is made of memory contents, which are largely zero-filled, so
+//! compressed streams only encode runs of zero bytes: each packet consists of a zero run length and
+//! a literal length (both LEB128-encoded), followed by the literal bytes. The same encoding is
+//! used for deltas between two states by [`compress_delta`].
+
+use super::Emu;
+use crate::{
+    cpu, ds_slot,
+    utils::{Bytes, LoadableInPlace, SavestateReader, SavestateWriter, Storable},
+    Model,
+};
+use core::fmt;
+use std::error::Error as StdError;
+
+pub const MAGIC: [u8; 8] = *b"DUSTSAVE";
+pub const VERSION: u32 = 1;
+const HEADER_LEN: usize = 0x28;
+
+const FLAG_COMPRESSED: u8 = 1 << 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct RomIdentity {
+    pub game_code: u32,
+    pub header_crc: u16,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Header {
+    pub version: u32,
+    pub model: Model,
+    pub rom: RomIdentity,
+    pub compressed: bool,
+    pub fields_len: u64,
+}
+
+pub enum Error {
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    InvalidModel(u8),
+    ModelMismatch {
+        expected: Model,
+        found: Model,
+    },
+    RomMismatch {
+        expected: RomIdentity,
+        found: RomIdentity,
+    },
+    InvalidData,
+}
+
+impl StdError for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidMagic => f.write_str("not a savestate file"),
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported savestate format version {} (expected {})",
+                version, VERSION
+            ),
+            Error::Truncated => f.write_str("truncated savestate"),
+            Error::InvalidModel(model) => write!(f, "invalid model in savestate: {}", model),
+            Error::ModelMismatch { expected, found } => write!(
+                f,
+                "savestate was created for a different model: expected {:?}, found {:?}",
+                expected, found
+            ),
+            Error::RomMismatch { expected, found } => write!(
+                f,
+                concat!(
+                    "savestate was created for a different ROM: expected game code {:08X} ",
+                    "(header CRC {:04X}), found {:08X} (header CRC {:04X})",
+                ),
+                expected.game_code, expected.header_crc, found.game_code, found.header_crc
+            ),
+            Error::InvalidData => f.write_str("invalid savestate data"),
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        <Self as fmt::Display>::fmt(self, f)
+    }
+}
+
+fn model_to_raw(model: Model) -> u8 {
+    match model {
+        Model::Ds => 0,
+        Model::Lite => 1,
+        Model::Ique => 2,
+        Model::IqueLite => 3,
+        Model::Dsi => 4,
+    }
+}
+
+fn model_from_raw(raw: u8) -> Option<Model> {
+    Some(match raw {
+        0 => Model::Ds,
+        1 => Model::Lite,
+        2 => Model::Ique,
+        3 => Model::IqueLite,
+        4 => Model::Dsi,
+        _ => return None,
+    })
+}
+
+fn read_u16(bytes: &[u8], offset: usize) -> u16 {
+    u16::from_le_bytes([bytes[offset], bytes[offset + 1]])
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn read_u64(bytes: &[u8], offset: usize) -> u64 {
+    u64::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap())
+}
+
+fn write_varint(output: &mut Vec<u8>, mut value: usize) {
+    while value >= 0x80 {
+        output.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    output.push(value as u8);
+}
+
+fn read_varint(input: &[u8], i: &mut usize) -> Option<usize> {
+    let mut value = 0_usize;
+    let mut shift = 0;
+    loop {
+        let byte = *input.get(*i)?;
+        *i += 1;
+        value |= ((byte & 0x7F) as usize).checked_shl(shift)?;
+        if byte & 0x80 == 0 {
+            return Some(value);
+        }
+        shift += 7;
+    }
+}
+
+fn compress_with(len: usize, byte: impl Fn(usize) -> u8) -> Vec<u8> {
+    let mut output = Vec::new();
+    let mut i = 0;
+    while i < len {
+        let zeros_start = i;
+        while i < len && byte(i) == 0 {
+            i += 1;
+        }
+        let literal_start = i;
+        // Only end a literal run at a zero run long enough to be worth a new packet
+        let mut zeros = 0;
+        while i < len && zeros < 4 {
+            if byte(i) == 0 {
+                zeros += 1;
+            } else {
+                zeros = 0;
+            }
+            i += 1;
+        }
+        if zeros == 4 {
+            i -= 4;
+        }
+        write_varint(&mut output, literal_start - zeros_start);
+        write_varint(&mut output, i - literal_start);
+        output.extend((literal_start..i).map(&byte));
+    }
+    output
+}
+
+/// Encodes the XOR of `a` and `b` (which must have the same length), to be applied to either of
+/// them with [`decompress_delta_into`] to obtain the other.
+pub fn compress_delta(a: &[u8], b: &[u8]) -> Vec<u8> {
+    assert_eq!(a.len(), b.len());
+    compress_with(a.len(), |i| a[i] ^ b[i])
+}
+
+/// XORs the data encoded in `delta` into `output`, returning `None` if `delta` is malformed or
+/// doesn't fit in `output`.
+pub fn decompress_delta_into(delta: &[u8], output: &mut [u8]) -> Option<()> {
+    let mut i = 0;
+    let mut pos = 0_usize;
+    while i < delta.len() {
+        pos = pos.checked_add(read_varint(delta, &mut i)?)?;
+        let literal_len = read_varint(delta, &mut i)?;
+        let literal = delta.get(i..i.checked_add(literal_len)?)?;
+        for (dst, src) in output
+            .get_mut(pos..pos.checked_add(literal_len)?)?
+            .iter_mut()
+            .zip(literal)
+        {
+            *dst ^= src;
+        }
+        pos += literal_len;
+        i += literal_len;
+    }
+    Some(())
+}
+
+/// Parses the container header, returning it along with the contained field stream.
+pub fn read_header(bytes: &[u8]) -> Result<(Header, &[u8]), Error> {
+    if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+        return Err(Error::InvalidMagic);
+    }
+    if bytes.len() < HEADER_LEN {
+        return Err(Error::Truncated);
+    }
+    let version = read_u32(bytes, 8);
+    if version != VERSION {
+        return Err(Error::UnsupportedVersion(version));
+    }
+    let header_len = read_u32(bytes, 0xC) as usize;
+    if header_len < HEADER_LEN {
+        return Err(Error::InvalidData);
+    }
+    let compressed = bytes[0x11] & FLAG_COMPRESSED != 0;
+    let model = model_from_raw(bytes[0x10]).ok_or(Error::InvalidModel(bytes[0x10]))?;
+    let rom = RomIdentity {
+        game_code: read_u32(bytes, 0x14),
+        header_crc: read_u16(bytes, 0x12),
+    };
+    let contents_len = read_u64(bytes, 0x18);
+    let fields_len = read_u64(bytes, 0x20);
+    let contents = bytes
+        .get(header_len..)
+        .and_then(|contents| contents.get(..usize::try_from(contents_len).ok()?))
+        .ok_or(Error::Truncated)?;
+    Ok((
+        Header {
+            version,
+            model,
+            rom,
+            compressed,
+            fields_len,
+        },
+        contents,
+    ))
+}
+
+impl<E: cpu::Engine> Emu<E> {
+    pub fn rom_identity(&self) -> RomIdentity {
+        let mut header_bytes = Bytes::new([0; 0x170]);
+        self.ds_slot.rom.read(0, header_bytes.as_byte_mut_slice());
+        let header = ds_slot::rom::header::Header::new(header_bytes.as_byte_slice()).unwrap();
+        RomIdentity {
+            game_code: header.game_code().0,
+            header_crc: header.header_crc(),
+        }
+    }
+
+    fn store_fields(&mut self) -> Vec<u8> {
+        let mut writer = SavestateWriter::new();
+        self.store(&mut writer)
+            .expect("Couldn't serialize emulator state");
+        writer.into_bytes()
+    }
+
+    /// Serializes the emulator's state into a versioned container, which can later be restored
+    /// with [`load_state`](Self::load_state).
+    ///
+    /// Compression should be skipped if the result is going to be diffed against other states
+    /// (for example with [`compress_delta`]), as compressed streams don't line up with each other.
+    pub fn save_state(&mut self, compress: bool) -> Vec<u8> {
+        let fields = self.store_fields();
+        let fields_len = fields.len();
+        let stored_fields = if compress {
+            compress_with(fields_len, |i| fields[i])
+        } else {
+            fields
+        };
+        let rom = self.rom_identity();
+        let mut bytes = Vec::with_capacity(HEADER_LEN + stored_fields.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(HEADER_LEN as u32).to_le_bytes());
+        bytes.push(model_to_raw(self.model));
+        bytes.push(if compress { FLAG_COMPRESSED } else { 0 });
+        bytes.extend_from_slice(&rom.header_crc.to_le_bytes());
+        bytes.extend_from_slice(&rom.game_code.to_le_bytes());
+        bytes.extend_from_slice(&(stored_fields.len() as u64).to_le_bytes());
+        bytes.extend_from_slice(&(fields_len as u64).to_le_bytes());
+        bytes.extend_from_slice(&stored_fields);
+        bytes
+    }
+
+    /// Restores a state previously created by [`save_state`](Self::save_state).
+    ///
+    /// The container's header is checked against the current model and ROM before anything is
+    /// loaded; if the field stream itself turns out to be invalid, the emulator is restored to the
+    /// state it was in before the call.
+    pub fn load_state(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        let (header, stored_fields) = read_header(bytes)?;
+        if header.model != self.model {
+            return Err(Error::ModelMismatch {
+                expected: self.model,
+                found: header.model,
+            });
+        }
+        let rom = self.rom_identity();
+        if header.rom != rom {
+            return Err(Error::RomMismatch {
+                expected: rom,
+                found: header.rom,
+            });
+        }
+
+        let decompressed_fields;
+        let fields = if header.compressed {
+            let mut output =
+                vec![0; usize::try_from(header.fields_len).map_err(|_| Error::InvalidData)?];
+            decompress_delta_into(stored_fields, &mut output).ok_or(Error::InvalidData)?;
+            decompressed_fields = output;
+            &decompressed_fields[..]
+        } else {
+            stored_fields
+        };
+
+        let prev_fields = self.store_fields();
+        if self
+            .load_in_place(&mut SavestateReader::new(fields))
+            .is_err()
+        {
+            self.load_in_place(&mut SavestateReader::new(&prev_fields))
+                .expect("Couldn't restore previous emulator state");
+            return Err(Error::InvalidData);
+        }
+        Ok(())
+    }
+}
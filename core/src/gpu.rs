@@ -54,6 +54,13 @@ impl DispStatus {
 pub const SCREEN_WIDTH: usize = 256;
 pub const SCREEN_HEIGHT: usize = 192;
 
+/// Converts an 18-bit RGB666 color, as used internally by the 2D and 3D engines, to RGBA8888.
+#[inline]
+pub const fn rgb_18_to_rgba_32(value: u32) -> u32 {
+    let rgb_6_8 = (value & 0x3F) | (value << 2 & 0x3F00) | (value << 4 & 0x3F_0000);
+    0xFF00_0000 | rgb_6_8 << 2 | (rgb_6_8 >> 4 & 0x0003_0303)
+}
+
 const TOTAL_SCANLINES: usize = 263;
 const DOT_CYCLES: RawTimestamp = 6;
 const HDRAW_DURATION: Timestamp = Timestamp(SCREEN_WIDTH as RawTimestamp * DOT_CYCLES + 48);
@@ -72,11 +79,22 @@ pub struct Framebuffer(pub [[u32; SCREEN_WIDTH * SCREEN_HEIGHT]; 2]);
 unsafe impl Zero for Framebuffer {}
 unsafe impl Fill8 for Framebuffer {}
 
+/// One bit per framebuffer pixel, set if the pixel was taken unmodified from the 3D engine's
+/// output, so that frontends can replace it with the corresponding pixels of a higher-resolution 3D
+/// render.
+#[repr(C)]
+#[derive(Clone)]
+pub struct Framebuffer3dMask(pub [[[u64; SCREEN_WIDTH / 64]; SCREEN_HEIGHT]; 2]);
+
+unsafe impl Zero for Framebuffer3dMask {}
+
 #[derive(Savestate)]
 #[load(in_place_only)]
 pub struct Gpu {
     #[savestate(skip)]
     pub framebuffer: Box<Framebuffer>,
+    #[savestate(skip)]
+    pub framebuffer_3d_mask: Box<Framebuffer3dMask>,
     power_control: PowerControl,
     vcount: u16,
     next_vcount: Option<u16>,
@@ -104,6 +122,7 @@ impl Gpu {
 
         Gpu {
             framebuffer: zeroed_box(),
+            framebuffer_3d_mask: zeroed_box(),
             power_control: PowerControl(0),
             vcount: 0,
             next_vcount: None,
@@ -248,6 +267,8 @@ impl Gpu {
                             .as_mut_ptr()
                             .add(scanline_base)
                             as *mut Scanline<u32>),
+                        &mut emu.gpu.framebuffer_3d_mask.0[!emu.gpu.swap_screens as usize]
+                            [emu.gpu.cur_scanline as usize],
                         &mut emu.gpu.vram,
                         &mut *emu.gpu.engine_3d.renderer,
                     );
@@ -257,6 +278,8 @@ impl Gpu {
                             .as_mut_ptr()
                             .add(scanline_base)
                             as *mut Scanline<u32>),
+                        &mut emu.gpu.framebuffer_3d_mask.0[emu.gpu.swap_screens as usize]
+                            [emu.gpu.cur_scanline as usize],
                         &mut emu.gpu.vram,
                         &mut *emu.gpu.engine_3d.renderer,
                     );
@@ -328,7 +351,13 @@ impl Gpu {
                 emu.gpu.disp_status_9.set_vcount_match(false);
             }
         }
-        if emu.gpu.vcount == SCREEN_HEIGHT as u16 {
+        if emu.gpu.vcount < SCREEN_HEIGHT as u16 {
+            emu.gpu.engine_2d_a.start_display_fifo_line();
+            if emu.gpu.power_control.display_enabled() {
+                emu.arm9
+                    .start_dma_transfers_with_timing::<{ arm9::dma::Timing::DisplayFifo }>();
+            }
+        } else if emu.gpu.vcount == SCREEN_HEIGHT as u16 {
             // Unlock the 3D engine if it was waiting for VBlank
             if emu.gpu.engine_3d.swap_buffers_waiting() {
                 Engine3d::swap_buffers(emu);
@@ -193,6 +193,21 @@ proc_bitfield::bitfield! {
     }
 }
 
+// Arisotura confirmed that shape 3 just forces 8 pixels of size
+#[rustfmt::skip]
+static OBJ_SIZE_SHIFT: [(u8, u8); 16] = [
+    (0, 0), (1, 0), (0, 1), (0, 0),
+    (1, 1), (2, 0), (0, 2), (0, 0),
+    (2, 2), (2, 1), (1, 2), (0, 0),
+    (3, 3), (3, 2), (2, 3), (0, 0),
+];
+
+/// Returns the base-2 logarithms of a sprite's width and height, in units of 8 pixels.
+#[inline]
+pub fn obj_size_shift(attr_0: OamAttr0, attr_1: OamAttr1) -> (u8, u8) {
+    OBJ_SIZE_SHIFT[((attr_1.0 >> 12 & 0xC) | attr_0.0 >> 14) as usize]
+}
+
 mod bounded {
     use crate::utils::bounded_int_lit;
     bounded_int_lit!(pub struct BgIndex(u8), max 3);
@@ -353,6 +368,9 @@ pub struct Engine2d<R: Role> {
     capture_control: CaptureControl,
     capture_enabled_in_frame: bool,
     capture_height: u8,
+    // Pixels written through DISP_MMEM_FIFO for the current scanline, only used by engine A
+    display_fifo: [u16; SCREEN_WIDTH],
+    display_fifo_len: u16,
 }
 
 impl<R: Role> Engine2d<R> {
@@ -406,6 +424,8 @@ impl<R: Role> Engine2d<R> {
             capture_control: CaptureControl(0),
             capture_enabled_in_frame: false,
             capture_height: 128,
+            display_fifo: [0; SCREEN_WIDTH],
+            display_fifo_len: 0,
         }
     }
 
@@ -444,6 +464,10 @@ impl<R: Role> Engine2d<R> {
 
     #[inline]
     pub fn write_master_brightness_control(&mut self, value: BrightnessControl) {
+        #[cfg(feature = "log")]
+        if value.mode() == 3 && self.master_brightness_control.mode() != 3 {
+            slog::warn!(self.logger, "Reserved master brightness mode 3 selected");
+        }
         self.master_brightness_control.0 = value.0 & 0xC01F;
         self.master_brightness_factor = (value.factor() as u32).min(16);
     }
@@ -528,6 +552,31 @@ impl<R: Role> Engine2d<R> {
         }
     }
 
+    #[inline]
+    pub fn display_fifo_full(&self) -> bool {
+        self.display_fifo_len as usize >= SCREEN_WIDTH
+    }
+
+    #[inline]
+    pub fn write_display_fifo(&mut self, value: u32) {
+        self.write_display_fifo_16(value as u16);
+        self.write_display_fifo_16((value >> 16) as u16);
+    }
+
+    #[inline]
+    pub fn write_display_fifo_16(&mut self, value: u16) {
+        if R::IS_A && !self.display_fifo_full() {
+            self.display_fifo[self.display_fifo_len as usize] = value;
+            self.display_fifo_len += 1;
+        }
+    }
+
+    pub(super) fn start_display_fifo_line(&mut self) {
+        // TODO: The real FIFO only holds 16 words and is drained as the scanline is displayed;
+        // this buffers a whole scanline instead, dropping any writes past its end.
+        self.display_fifo_len = 0;
+    }
+
     pub(super) fn start_vblank(&mut self) {
         if R::IS_A && self.capture_enabled_in_frame {
             self.capture_control.set_enabled(false);
@@ -4,11 +4,11 @@ mod avx2;
 mod common;
 
 use super::{
-    AffineBgIndex, BgIndex, BgObjPixel, Engine2d, OamAttr0, OamAttr1, OamAttr2, ObjPixel, Role,
-    WindowPixel,
+    obj_size_shift, AffineBgIndex, BgIndex, BgObjPixel, Engine2d, OamAttr0, OamAttr1, OamAttr2,
+    ObjPixel, Role, WindowPixel,
 };
 use crate::{
-    gpu::{engine_3d, vram::Vram, Scanline, SCREEN_HEIGHT, SCREEN_WIDTH},
+    gpu::{engine_3d, rgb_18_to_rgba_32, vram::Vram, Scanline, SCREEN_HEIGHT, SCREEN_WIDTH},
     utils::make_zero,
 };
 
@@ -40,11 +40,6 @@ const fn rgb_15_to_18(value: u32) -> u32 {
     (value << 1 & 0x3E) | (value << 2 & 0xF80) | (value << 3 & 0x3_E000)
 }
 
-const fn rgb_18_to_rgba_32(value: u32) -> u32 {
-    let rgb_6_8 = (value & 0x3F) | (value << 2 & 0x3F00) | (value << 4 & 0x3F_0000);
-    0xFF00_0000 | rgb_6_8 << 2 | (rgb_6_8 >> 4 & 0x0003_0303)
-}
-
 impl<R: Role> Engine2d<R> {
     fn apply_color_effects<const EFFECT: u8>(&mut self) {
         #[inline]
@@ -82,9 +77,14 @@ impl<R: Role> Engine2d<R> {
                 let top_mask = top.color_effects_mask();
                 let bot_matches = bot.color_effects_mask() & target_2_mask != 0;
                 if top.is_3d() && bot_matches {
-                    let a_coeff = (top.alpha() + 1) as u32;
-                    let b_coeff = (32 - a_coeff) as u32;
-                    blend_5bit_coeff(top.0, bot.0, a_coeff, b_coeff)
+                    if top.alpha() == 0x1F {
+                        // Blending would leave the color unchanged, keep the pixel marked as 3D
+                        top.0
+                    } else {
+                        let a_coeff = (top.alpha() + 1) as u32;
+                        let b_coeff = (32 - a_coeff) as u32;
+                        blend_5bit_coeff(top.0, bot.0, a_coeff, b_coeff)
+                    }
                 } else if top.force_blending() && bot_matches {
                     let (a_coeff, b_coeff) = if top.custom_alpha() {
                         (top.alpha() as u32, 16 - top.alpha() as u32)
@@ -109,7 +109,7 @@ impl<R: Role> Engine2d<R> {
                                     | (((complement & 0xFC0) * brightness_coeff) & 0xFC00))
                                     >> 4
                             };
-                            top.0 + increment
+                            BgObjPixel(top.0 + increment).with_is_3d(false).0
                         }
 
                         _ => {
@@ -118,7 +118,7 @@ impl<R: Role> Engine2d<R> {
                                     | (((top.0 & 0xFC0) * brightness_coeff) & 0xFC00))
                                     >> 4
                             };
-                            top.0 - decrement
+                            BgObjPixel(top.0 - decrement).with_is_3d(false).0
                         }
                     }
                 } else {
@@ -156,9 +156,12 @@ impl<R: Role> Engine2d<R> {
         &mut self,
         vcount: u16,
         scanline_buffer: &mut Scanline<u32>,
+        scanline_3d_mask: &mut [u64; SCREEN_WIDTH / 64],
         vram: &mut Vram,
         renderer_3d: &mut dyn engine_3d::Renderer,
     ) {
+        *scanline_3d_mask = [0; SCREEN_WIDTH / 64];
+
         // According to melonDS, if vcount falls outside the drawing range or 2D engine B is
         // disabled, the scanline is filled with pure white.
         if vcount >= SCREEN_HEIGHT as u16 || (!R::IS_A && !self.enabled) {
@@ -253,40 +256,9 @@ impl<R: Role> Engine2d<R> {
             ][self.color_effects_control.color_effect() as usize](self);
         }
 
-        #[allow(clippy::match_same_arms)]
-        match display_mode {
-            0 => {
-                scanline_buffer.0.fill(0xFFFF_FFFF);
-                return;
-            }
-
-            1 => {}
-
-            2 => {
-                // The bank must be mapped as LCDC VRAM to be used
-                let bank_index = self.control.a_vram_bank();
-                let bank_control = vram.bank_control()[bank_index as usize];
-                if bank_control.enabled() && bank_control.mst() == 0 {
-                    let bank = match bank_index {
-                        0 => &vram.banks.a,
-                        1 => &vram.banks.b,
-                        2 => &vram.banks.c,
-                        _ => &vram.banks.d,
-                    };
-                    let line_base = (vcount as usize) << 9;
-                    for (i, pixel) in scanline_buffer.0.iter_mut().enumerate() {
-                        let src =
-                            unsafe { bank.read_le_aligned_unchecked::<u16>(line_base | i << 1) };
-                        *pixel = rgb_15_to_18(src as u32);
-                    }
-                } else {
-                    scanline_buffer.0.fill(0);
-                }
-            }
-
-            _ => {
-                // TODO: Main memory display mode
-            }
+        if display_mode == 0 {
+            scanline_buffer.0.fill(0xFFFF_FFFF);
+            return;
         }
 
         #[allow(clippy::similar_names)]
@@ -317,7 +289,7 @@ impl<R: Role> Engine2d<R> {
                     && (factor_b != 0 || capture_source & 2 == 0)
                 {
                     if self.capture_control.src_b_display_fifo() {
-                        todo!("Display capture display FIFO source");
+                        Some(self.display_fifo.as_ptr())
                     } else {
                         let src_bank_index = self.control.a_vram_bank();
                         let src_bank_control = vram.bank_control()[src_bank_index as usize];
@@ -428,6 +400,45 @@ impl<R: Role> Engine2d<R> {
             }
         }
 
+        // The VRAM and main memory display modes replace the 2D engine's output, which display
+        // capture might still need, so they can only be handled after it
+        match display_mode {
+            1 => {}
+
+            2 => {
+                // The bank must be mapped as LCDC VRAM to be used
+                let bank_index = self.control.a_vram_bank();
+                let bank_control = vram.bank_control()[bank_index as usize];
+                if bank_control.enabled() && bank_control.mst() == 0 {
+                    let bank = match bank_index {
+                        0 => &vram.banks.a,
+                        1 => &vram.banks.b,
+                        2 => &vram.banks.c,
+                        _ => &vram.banks.d,
+                    };
+                    let line_base = (vcount as usize) << 9;
+                    for (i, pixel) in self.bg_obj_scanline.0.iter_mut().enumerate() {
+                        let src =
+                            unsafe { bank.read_le_aligned_unchecked::<u16>(line_base | i << 1) };
+                        *pixel = rgb_15_to_18(src as u32) as u64;
+                    }
+                } else {
+                    self.bg_obj_scanline.0.fill(0);
+                }
+            }
+
+            _ => {
+                for (pixel, src) in self
+                    .bg_obj_scanline
+                    .0
+                    .iter_mut()
+                    .zip(self.display_fifo.iter())
+                {
+                    *pixel = rgb_15_to_18(*src as u32) as u64;
+                }
+            }
+        }
+
         match self.master_brightness_control.mode() {
             1 if self.master_brightness_factor != 0 => {
                 for (dst, src) in scanline_buffer
@@ -462,8 +473,7 @@ impl<R: Role> Engine2d<R> {
                 }
             }
 
-            3 => unimplemented!("Unknown 2D engine brightness mode 3"),
-
+            // Mode 3 is reserved, and leaves colors unchanged like mode 0 (this matches melonDS)
             _ => {
                 for (dst, src) in scanline_buffer
                     .0
@@ -472,6 +482,20 @@ impl<R: Role> Engine2d<R> {
                 {
                     *dst = rgb_18_to_rgba_32(*src as u32);
                 }
+
+                // The high-resolution 3D frame can only be used when the 3D layer isn't scrolled,
+                // as it's composed at the same position
+                if R::IS_A
+                    && display_mode == 1
+                    && scanline_3d.is_some()
+                    && self.bgs[0].scroll[0] == 0
+                {
+                    for (i, pixel) in self.bg_obj_scanline.0.iter().enumerate() {
+                        if BgObjPixel(*pixel as u32).is_3d() {
+                            scanline_3d_mask[i >> 6] |= 1 << (i & 63);
+                        }
+                    }
+                }
             }
         }
     }
@@ -567,9 +591,15 @@ impl<R: Role> Engine2d<R> {
                     if self.engine_3d_enabled_in_frame {
                         let scanline_3d = unsafe { scanline_3d.unwrap_unchecked() };
                         let pixel_attrs = BgObjPixel(0).with_color_effects_mask(1).with_is_3d(true);
-                        // TODO: 3D layer scrolling
+                        let x_offset = self.bgs[0].scroll[0] as usize;
                         for i in 0..SCREEN_WIDTH {
-                            let pixel = scanline_3d.0[i];
+                            // The 3D layer doesn't wrap around horizontally, anything scrolled in
+                            // from outside of it is transparent
+                            let src_x = (i + x_offset) & 0x1FF;
+                            if src_x >= SCREEN_WIDTH {
+                                continue;
+                            }
+                            let pixel = scanline_3d.0[src_x];
                             if pixel >> 19 != 0 {
                                 self.bg_obj_scanline.0[i] = (self.bg_obj_scanline.0[i] as u64)
                                     << 32
@@ -594,25 +624,15 @@ impl<R: Role> Engine2d<R> {
                     let pixel_attrs = BgObjPixel(obj_pixel.0 & 0x03F8_0000)
                         .with_color_effects_mask(1 << 4)
                         .0;
-                    let color = unsafe {
-                        rgb_15_to_18(if obj_pixel.use_raw_color() {
-                            obj_pixel.raw_color()
-                        } else if obj_pixel.use_ext_pal() {
-                            (if R::IS_A {
-                                vram.a_obj_ext_pal.as_ptr()
-                            } else {
-                                vram.b_obj_ext_pal_ptr
-                            } as *const u16)
-                                .add(obj_pixel.pal_color() as usize)
-                                .read()
-                        } else {
-                            vram.palette.read_le_aligned_unchecked::<u16>(
-                                (!R::IS_A as usize) << 10
-                                    | 0x200
-                                    | (obj_pixel.pal_color() as usize) << 1,
-                            )
-                        } as u32)
-                    };
+                    let color = rgb_15_to_18(if obj_pixel.use_raw_color() {
+                        obj_pixel.raw_color()
+                    } else {
+                        Self::read_obj_pal_color(
+                            vram,
+                            obj_pixel.pal_color(),
+                            obj_pixel.use_ext_pal(),
+                        )
+                    } as u32);
                     self.bg_obj_scanline.0[i] =
                         self.bg_obj_scanline.0[i] << 32 | (color | pixel_attrs) as u64;
                 }
@@ -921,21 +941,144 @@ impl<R: Role> Engine2d<R> {
         affine.pos[1] = affine.pos[1].wrapping_add(affine.params[3] as i32);
     }
 
-    pub(in super::super) fn prerender_sprites(&mut self, scanline: u32, vram: &Vram) {
-        // Arisotura confirmed that shape 3 just forces 8 pixels of size
-        #[rustfmt::skip]
-        static OBJ_SIZE_SHIFT: [(u8, u8); 16] = [
-            (0, 0), (1, 0), (0, 1), (0, 0),
-            (1, 1), (2, 0), (0, 2), (0, 0),
-            (2, 2), (2, 1), (1, 2), (0, 0),
-            (3, 3), (3, 2), (2, 3), (0, 0),
-        ];
+    /// Returns the offset in OBJ VRAM of the bitmap sprite with the given tile number, along with
+    /// the shift giving its row stride in bytes, or `None` if bitmap sprites can't be displayed
+    /// with the current mapping mode.
+    #[inline]
+    fn obj_bitmap_base(&self, tile_number: u32, width_shift: u8) -> Option<(u32, u8)> {
+        if self.control.obj_bitmap_1d_mapping() {
+            if self.control.bitmap_objs_256x256() {
+                return None;
+            }
+            Some((
+                tile_number
+                    << if R::IS_A {
+                        7 + self.control.a_obj_bitmap_1d_boundary()
+                    } else {
+                        7
+                    },
+                // Rows are as wide as the sprite, at 2 bytes per pixel
+                width_shift + 4,
+            ))
+        } else if self.control.bitmap_objs_256x256() {
+            Some((
+                ((tile_number & 0x1F) << 4) + ((tile_number & !0x1F) << 7),
+                9,
+            ))
+        } else {
+            Some((((tile_number & 0xF) << 4) + ((tile_number & !0xF) << 7), 8))
+        }
+    }
 
-        #[inline]
-        fn obj_size_shift(attr_0: OamAttr0, attr_1: OamAttr1) -> (u8, u8) {
-            OBJ_SIZE_SHIFT[((attr_1.0 >> 12 & 0xC) | attr_0.0 >> 14) as usize]
+    /// Returns the offset in OBJ VRAM of the first tile of the tiled sprite with the given tile
+    /// number.
+    #[inline]
+    fn obj_tile_base(&self, tile_number: u32) -> u32 {
+        (if R::IS_A {
+            self.control.a_tile_base()
+        } else {
+            0
+        }) + if self.control.obj_tile_1d_mapping() {
+            tile_number << (5 + self.control.obj_tile_1d_boundary())
+        } else {
+            tile_number << 5
+        }
+    }
+
+    /// Returns the offset of line `y` of a tiled sprite's leftmost tile column from its tile base.
+    #[inline]
+    fn obj_tile_line_offset(&self, y: u32, width_shift: u8, use_256_colors: bool) -> u32 {
+        if self.control.obj_tile_1d_mapping() {
+            ((y & !7) << width_shift | (y & 7)) << (2 | use_256_colors as u8)
+        } else {
+            (y >> 3 << 10) | ((y & 7) << (2 | use_256_colors as u8))
         }
+    }
+
+    /// Reads the 15-bit color for a sprite pixel's 12-bit palette color index, from either the
+    /// extended or the standard OBJ palette.
+    #[inline]
+    fn read_obj_pal_color(vram: &Vram, pal_color: u16, use_ext_pal: bool) -> u16 {
+        // The index is at most 12 bits wide, which fits within both palettes
+        unsafe {
+            if use_ext_pal {
+                (if R::IS_A {
+                    vram.a_obj_ext_pal.as_ptr()
+                } else {
+                    vram.b_obj_ext_pal_ptr
+                } as *const u16)
+                    .add(pal_color as usize & 0xFFF)
+                    .read()
+            } else {
+                vram.palette.read_le_aligned_unchecked::<u16>(
+                    (!R::IS_A as usize) << 10 | 0x200 | (pal_color as usize & 0xFF) << 1,
+                )
+            }
+        }
+    }
+
+    /// Reads the 15-bit color of the pixel at `(x, y)` inside the untransformed sprite described by
+    /// `attrs`, returning `None` if it's transparent.
+    pub fn read_obj_pixel(
+        &self,
+        attrs: (OamAttr0, OamAttr1, OamAttr2),
+        x: u32,
+        y: u32,
+        vram: &Vram,
+    ) -> Option<u16> {
+        let (width_shift, _) = obj_size_shift(attrs.0, attrs.1);
+        let tile_number = attrs.2.tile_number() as u32;
+
+        if attrs.0.mode() == 3 {
+            if attrs.2.palette_number() == 0 {
+                return None;
+            }
+            let (tile_base, y_shift) = self.obj_bitmap_base(tile_number, width_shift)?;
+            let pixel_addr = tile_base + (y << y_shift) + (x << 1);
+            let color = if R::IS_A {
+                vram.read_a_obj::<u16>(pixel_addr)
+            } else {
+                vram.read_b_obj::<u16>(pixel_addr)
+            };
+            return if color & 0x8000 != 0 {
+                Some(color & 0x7FFF)
+            } else {
+                None
+            };
+        }
+
+        let use_256_colors = attrs.0.use_256_colors();
+        let line_base = self.obj_tile_base(tile_number)
+            + self.obj_tile_line_offset(y, width_shift, use_256_colors);
+        let read_obj = |addr| {
+            if R::IS_A {
+                vram.read_a_obj::<u8>(addr)
+            } else {
+                vram.read_b_obj::<u8>(addr)
+            }
+        };
+        let (pal_color, use_ext_pal) = if use_256_colors {
+            let color_index = read_obj(line_base + (x >> 3 << 6 | (x & 7))) as u16;
+            if color_index == 0 {
+                return None;
+            }
+            if self.control.obj_ext_pal_enabled() {
+                ((attrs.2.palette_number() as u16) << 8 | color_index, true)
+            } else {
+                (color_index, false)
+            }
+        } else {
+            let color_index =
+                read_obj(line_base + (x >> 3 << 5 | (x & 7) >> 1)) as u16 >> ((x & 1) << 2) & 0xF;
+            if color_index == 0 {
+                return None;
+            }
+            ((attrs.2.palette_number() as u16) << 4 | color_index, false)
+        };
+        Some(Self::read_obj_pal_color(vram, pal_color, use_ext_pal))
+    }
 
+    pub(in super::super) fn prerender_sprites(&mut self, scanline: u32, vram: &Vram) {
         self.obj_scanline.0.fill(ObjPixel(0).with_priority(4));
         make_zero(&mut self.obj_window);
         if !self.control.objs_enabled() {
@@ -1071,29 +1214,11 @@ impl<R: Role> Engine2d<R> {
                 value => value + 1,
             };
 
-            let tile_number = attrs.2.tile_number() as u32;
-
-            let (tile_base, y_shift) = if self.control.obj_bitmap_1d_mapping() {
-                if self.control.bitmap_objs_256x256() {
-                    return;
-                }
-                (
-                    tile_number
-                        << if R::IS_A {
-                            7 + self.control.a_obj_bitmap_1d_boundary()
-                        } else {
-                            7
-                        },
-                    width_shift + 1,
-                )
-            } else if self.control.bitmap_objs_256x256() {
-                (
-                    ((tile_number & 0x1F) << 4) + ((tile_number & !0x1F) << 7),
-                    9,
-                )
-            } else {
-                (((tile_number & 0xF) << 4) + ((tile_number & !0xF) << 7), 8)
-            };
+            let (tile_base, y_shift) =
+                match self.obj_bitmap_base(attrs.2.tile_number() as u32, width_shift) {
+                    Some(result) => result,
+                    None => return,
+                };
 
             let pixel_attrs = ObjPixel(0)
                 .with_priority(attrs.2.bg_priority())
@@ -1105,7 +1230,7 @@ impl<R: Role> Engine2d<R> {
             for x in start_x..end_x {
                 if (pos[0] & obj_x_outside_mask) | (pos[1] & obj_y_outside_mask) == 0 {
                     let pixel_addr =
-                        tile_base + (pos[0] as u32 >> 8) + (pos[1] as u32 >> 8 << y_shift);
+                        tile_base + (pos[0] as u32 >> 8 << 1) + (pos[1] as u32 >> 8 << y_shift);
                     let color = if R::IS_A {
                         vram.read_a_obj::<u16>(pixel_addr)
                     } else {
@@ -1123,18 +1248,7 @@ impl<R: Role> Engine2d<R> {
                 pos[1] = pos[1].wrapping_add(params[2] as i32);
             }
         } else {
-            let tile_base = if R::IS_A {
-                self.control.a_tile_base()
-            } else {
-                0
-            } + {
-                let tile_number = attrs.2.tile_number() as u32;
-                if self.control.obj_tile_1d_mapping() {
-                    tile_number << (5 + self.control.obj_tile_1d_boundary())
-                } else {
-                    tile_number << 5
-                }
-            };
+            let tile_base = self.obj_tile_base(attrs.2.tile_number() as u32);
 
             let mut pixel_attrs = ObjPixel(0)
                 .with_priority(attrs.2.bg_priority())
@@ -1291,24 +1405,11 @@ impl<R: Role> Engine2d<R> {
                 value => value + 1,
             };
 
-            let tile_number = attrs.2.tile_number() as u32;
-
-            let mut tile_base = if self.control.obj_bitmap_1d_mapping() {
-                if self.control.bitmap_objs_256x256() {
-                    return;
-                }
-                (tile_number
-                    << if R::IS_A {
-                        7 + self.control.a_obj_bitmap_1d_boundary()
-                    } else {
-                        7
-                    })
-                    + (y_in_obj << (width_shift + 1))
-            } else if self.control.bitmap_objs_256x256() {
-                ((tile_number & 0x1F) << 4) + ((tile_number & !0x1F) << 7) + (y_in_obj << 9)
-            } else {
-                ((tile_number & 0xF) << 4) + ((tile_number & !0xF) << 7) + (y_in_obj << 8)
-            };
+            let mut tile_base =
+                match self.obj_bitmap_base(attrs.2.tile_number() as u32, width_shift) {
+                    Some((tile_base, y_shift)) => tile_base + (y_in_obj << y_shift),
+                    None => return,
+                };
 
             let pixel_attrs = ObjPixel(0)
                 .with_priority(attrs.2.bg_priority())
@@ -1352,24 +1453,8 @@ impl<R: Role> Engine2d<R> {
                 x_in_obj = x_in_obj.wrapping_add(x_in_obj_incr as u32);
             }
         } else {
-            let mut tile_base = if R::IS_A {
-                self.control.a_tile_base()
-            } else {
-                0
-            } + {
-                let tile_number = attrs.2.tile_number() as u32;
-                if self.control.obj_tile_1d_mapping() {
-                    let tile_number_off = tile_number << (5 + self.control.obj_tile_1d_boundary());
-                    let y_off = ((y_in_obj & !7) << width_shift | (y_in_obj & 7))
-                        << (2 | attrs.0.use_256_colors() as u8);
-                    tile_number_off + y_off
-                } else {
-                    let tile_number_off = tile_number << 5;
-                    let y_off = (y_in_obj >> 3 << 10)
-                        | ((y_in_obj & 7) << (2 | attrs.0.use_256_colors() as u8));
-                    tile_number_off + y_off
-                }
-            };
+            let mut tile_base = self.obj_tile_base(attrs.2.tile_number() as u32)
+                + self.obj_tile_line_offset(y_in_obj, width_shift, attrs.0.use_256_colors());
 
             let mut pixel_attrs = ObjPixel(0)
                 .with_priority(attrs.2.bg_priority())
@@ -1,3 +1,5 @@
+#[cfg(feature = "gx-capture")]
+pub mod capture;
 mod io;
 mod matrix;
 mod vertex;
@@ -14,10 +16,12 @@ use crate::{
     emu::{self, Emu},
     gpu::vram::Vram,
     utils::{
-        load_slice_in_place, schedule::RawTimestamp, store_slice, zeroed_box, Fifo, Savestate, Zero,
+        load_slice_in_place, schedule::RawTimestamp, store_slice, zeroed_box, Bytes, Fifo,
+        Savestate, Zero,
     },
 };
 use core::{
+    array,
     mem::{replace, transmute},
     simd::i32x4,
 };
@@ -216,6 +220,18 @@ pub struct RenderingState {
     pub rear_plane_fog_enabled: bool,
 }
 
+/// Copy of a frame submitted to the renderer, for debugging purposes; only taken after
+/// [`Engine3d::request_frame_3d_capture`] is called.
+#[cfg(feature = "frame-3d-capture")]
+pub struct Frame3dCapture {
+    pub vert_ram: Vec<ScreenVertex>,
+    pub poly_ram: Vec<Polygon>,
+    pub control: RenderingControl,
+    pub w_buffering: bool,
+    pub texture: Box<Bytes<0x8_0000>>,
+    pub tex_pal: Box<Bytes<0x1_8000>>,
+}
+
 #[derive(Savestate)]
 #[load(in_place_only)]
 pub struct Engine3d {
@@ -224,6 +240,15 @@ pub struct Engine3d {
     logger: slog::Logger,
     #[savestate(skip)]
     pub renderer: Box<dyn Renderer>,
+    #[cfg(feature = "gx-capture")]
+    #[savestate(skip)]
+    capture: capture::State,
+    #[cfg(feature = "frame-3d-capture")]
+    #[savestate(skip)]
+    frame_3d_capture_requested: bool,
+    #[cfg(feature = "frame-3d-capture")]
+    #[savestate(skip)]
+    frame_3d_capture: Option<Frame3dCapture>,
 
     pub(super) gx_enabled: bool,
     pub(super) rendering_enabled: bool,
@@ -263,6 +288,9 @@ pub struct Engine3d {
     transformed_tex_coords: TexCoords,
     last_vtx_coords: [i16; 3],
 
+    pos_test_result: [i32; 4],
+    vec_test_result: [i16; 3],
+
     shininess_table_enabled: bool,
     diffuse_color: i32x4,
     ambient_color: i32x4,
@@ -329,10 +357,29 @@ impl Engine3d {
             emu::Event::Engine3dCommandFinished,
         );
 
+        Self::new_detached(
+            renderer,
+            #[cfg(feature = "log")]
+            logger,
+        )
+    }
+
+    // Creates an engine that isn't attached to any emulator schedule, only usable to process
+    // commands directly
+    fn new_detached(
+        renderer: Box<dyn Renderer>,
+        #[cfg(feature = "log")] logger: slog::Logger,
+    ) -> Self {
         Engine3d {
             #[cfg(feature = "log")]
             logger,
             renderer,
+            #[cfg(feature = "gx-capture")]
+            capture: capture::State::Idle,
+            #[cfg(feature = "frame-3d-capture")]
+            frame_3d_capture_requested: false,
+            #[cfg(feature = "frame-3d-capture")]
+            frame_3d_capture: None,
 
             gx_enabled: false,
             rendering_enabled: false,
@@ -372,6 +419,8 @@ impl Engine3d {
             tex_coords: TexCoords::splat(0),
             transformed_tex_coords: TexCoords::splat(0),
             last_vtx_coords: [0; 3],
+            pos_test_result: [0; 4],
+            vec_test_result: [0; 3],
             shininess_table_enabled: false,
             diffuse_color: i32x4::splat(0),
             ambient_color: i32x4::splat(0),
@@ -544,18 +593,59 @@ impl Engine3d {
         }
     }
 
-    fn write_to_gx_fifo(emu: &mut Emu<impl cpu::Engine>, value: FifoEntry) {
-        match value.command {
+    /// Returns the number of bus cycles taken to execute `command` once all of its parameters
+    /// have been received, according to GBATEK.
+    #[allow(clippy::match_same_arms)]
+    fn command_cycles(&self, command: u8) -> RawTimestamp {
+        // Multiplications have to update both the position and vector matrices in this mode
+        let mtx_mult_extra_cycles = if self.mtx_mode == MatrixMode::PositionVector {
+            30
+        } else {
+            0
+        };
+        match command {
+            0x10 | 0x20 | 0x22 | 0x29 | 0x2A | 0x2B | 0x33 | 0x40 | 0x41 | 0x60 => 1,
+            0x11 | 0x13 => 17,
+            0x12 | 0x14 => 36,
+            0x15 => 19,
+            0x16 => 34,
+            0x17 => 30,
+            0x18 => 35 + mtx_mult_extra_cycles,
+            0x19 => 31 + mtx_mult_extra_cycles,
+            0x1A => 28 + mtx_mult_extra_cycles,
+            0x1B => 22,
+            0x1C => 22 + mtx_mult_extra_cycles,
+            // One more cycle is taken for every enabled light after the first one
+            0x21 => 8 + self.cur_poly_attrs.lights_mask().count_ones().max(1) as RawTimestamp,
+            0x23 => 9,
+            0x24..=0x28 => 8,
+            0x30 | 0x31 => 4,
+            0x32 => 6,
+            0x34 => 32,
+            0x50 => 392,
+            0x70 => 103,
+            0x71 => 9,
+            0x72 => 5,
+            _ => 1,
+        }
+    }
+
+    fn track_queued_entry(&mut self, command: u8) {
+        match command {
             0x11 | 0x12 => {
-                emu.gpu.engine_3d.queued_mtx_stack_cmds += 1;
-                emu.gpu.engine_3d.gx_status.set_matrix_stack_busy(true);
+                self.queued_mtx_stack_cmds += 1;
+                self.gx_status.set_matrix_stack_busy(true);
             }
             0x70..=0x72 => {
-                emu.gpu.engine_3d.queued_test_cmd_entries += 1;
-                emu.gpu.engine_3d.gx_status.set_test_busy(true);
+                self.queued_test_cmd_entries += 1;
+                self.gx_status.set_test_busy(true);
             }
             _ => {}
         }
+    }
+
+    fn write_to_gx_fifo(emu: &mut Emu<impl cpu::Engine>, value: FifoEntry) {
+        emu.gpu.engine_3d.track_queued_entry(value.command);
 
         if !emu.gpu.engine_3d.gx_pipe.is_full() && emu.gpu.engine_3d.gx_fifo.is_empty() {
             let _ = emu.gpu.engine_3d.gx_pipe.write(value);
@@ -593,64 +683,74 @@ impl Engine3d {
         }
     }
 
-    fn write_unpacked_command(emu: &mut Emu<impl cpu::Engine>, command: u8, param: u32) {
-        if emu.gpu.engine_3d.remaining_command_params == 0 {
-            emu.gpu.engine_3d.remaining_command_params = emu
-                .gpu
-                .engine_3d
-                .params_for_command(command)
-                .saturating_sub(1);
+    fn unpacked_command_entry(&mut self, command: u8, param: u32) -> FifoEntry {
+        if self.remaining_command_params == 0 {
+            self.remaining_command_params = self.params_for_command(command).saturating_sub(1);
         } else {
-            emu.gpu.engine_3d.remaining_command_params -= 1;
+            self.remaining_command_params -= 1;
         }
-        Self::write_to_gx_fifo(emu, FifoEntry { command, param });
+        FifoEntry { command, param }
     }
 
-    fn write_packed_command(emu: &mut Emu<impl cpu::Engine>, value: u32) {
-        // TODO: "Packed commands are first decompressed and then stored in the command FIFO."
-        if emu.gpu.engine_3d.remaining_command_params == 0 {
-            emu.gpu.engine_3d.cur_packed_commands = value;
-            let command = emu.gpu.engine_3d.cur_packed_commands as u8;
-            emu.gpu.engine_3d.remaining_command_params =
-                emu.gpu.engine_3d.params_for_command(command);
-            if emu.gpu.engine_3d.remaining_command_params > 0 {
-                return;
+    fn write_unpacked_command(emu: &mut Emu<impl cpu::Engine>, command: u8, param: u32) {
+        #[cfg(feature = "gx-capture")]
+        emu.gpu
+            .engine_3d
+            .record_port_write(0x400 | (command as u16) << 2, param);
+        let entry = emu.gpu.engine_3d.unpacked_command_entry(command, param);
+        Self::write_to_gx_fifo(emu, entry);
+    }
+
+    fn packed_command_entries(&mut self, value: u32) -> Fifo<FifoEntry, 4> {
+        // "Packed commands are first decompressed and then stored in the command FIFO", so every
+        // command takes up at least one FIFO entry, even when it has no parameters
+        let mut entries = Fifo::new();
+        if self.remaining_command_params == 0 {
+            self.cur_packed_commands = value;
+            let command = self.cur_packed_commands as u8;
+            self.remaining_command_params = self.params_for_command(command);
+            if self.remaining_command_params > 0 {
+                return entries;
             }
-            Self::write_to_gx_fifo(emu, FifoEntry { command, param: 0 });
+            let _ = entries.write(FifoEntry { command, param: 0 });
         } else {
-            let command = emu.gpu.engine_3d.cur_packed_commands as u8;
-            Self::write_to_gx_fifo(
-                emu,
-                FifoEntry {
-                    command,
-                    param: value,
-                },
-            );
-            emu.gpu.engine_3d.remaining_command_params -= 1;
-            if emu.gpu.engine_3d.remaining_command_params > 0 {
-                return;
+            let command = self.cur_packed_commands as u8;
+            let _ = entries.write(FifoEntry {
+                command,
+                param: value,
+            });
+            self.remaining_command_params -= 1;
+            if self.remaining_command_params > 0 {
+                return entries;
             }
         }
-        let mut cur_packed_commands = emu.gpu.engine_3d.cur_packed_commands;
+        let mut cur_packed_commands = self.cur_packed_commands;
         loop {
             cur_packed_commands >>= 8;
             if cur_packed_commands == 0 {
                 break;
             }
             let next_command = cur_packed_commands as u8;
-            let next_command_params = emu.gpu.engine_3d.params_for_command(next_command);
+            let next_command_params = self.params_for_command(next_command);
             if next_command_params > 0 {
-                emu.gpu.engine_3d.cur_packed_commands = cur_packed_commands;
-                emu.gpu.engine_3d.remaining_command_params = next_command_params;
+                self.cur_packed_commands = cur_packed_commands;
+                self.remaining_command_params = next_command_params;
                 break;
             }
-            Self::write_to_gx_fifo(
-                emu,
-                FifoEntry {
-                    command: next_command,
-                    param: 0,
-                },
-            );
+            let _ = entries.write(FifoEntry {
+                command: next_command,
+                param: 0,
+            });
+        }
+        entries
+    }
+
+    fn write_packed_command(emu: &mut Emu<impl cpu::Engine>, value: u32) {
+        #[cfg(feature = "gx-capture")]
+        emu.gpu.engine_3d.record_port_write(0x400, value);
+        let mut entries = emu.gpu.engine_3d.packed_command_entries(value);
+        while let Some(entry) = entries.read() {
+            Self::write_to_gx_fifo(emu, entry);
         }
     }
 
@@ -674,6 +774,78 @@ impl Engine3d {
         self.cur_clip_mtx = self.cur_pos_vec_mtxs[0] * self.cur_proj_mtx;
     }
 
+    fn box_test(&mut self, params: [i16; 6]) -> bool {
+        // TODO: Check whether </> or <=/>= should be used for the frustum checks, and how
+        // degenerate (zero-sized) boxes are handled
+
+        if self.clip_mtx_needs_recalculation {
+            self.update_clip_mtx();
+        }
+
+        let [x, y, z, width, height, depth] = params.map(i32::from);
+        let corners: [[i64; 4]; 8] = array::from_fn(|i| {
+            self.cur_clip_mtx
+                .mul_left_vec3::<i32, i64>([
+                    x + if i & 1 != 0 { width } else { 0 },
+                    y + if i & 2 != 0 { height } else { 0 },
+                    z + if i & 4 != 0 { depth } else { 0 },
+                ])
+                .to_array()
+        });
+
+        // Corner indices for each of the box's faces; the box is considered inside the view
+        // volume if any part of any face is still visible after clipping it against all planes
+        const FACES: [[usize; 4]; 6] = [
+            [0, 1, 3, 2],
+            [4, 5, 7, 6],
+            [0, 1, 5, 4],
+            [2, 3, 7, 6],
+            [0, 2, 6, 4],
+            [1, 3, 7, 5],
+        ];
+
+        FACES.iter().any(|face| {
+            // Clipping a quad against 6 planes can add at most 6 vertices
+            let mut verts = [[0; 4]; 10];
+            let mut clipped_verts = [[0; 4]; 10];
+            let mut verts_len = 4;
+            for (vert, &i) in verts.iter_mut().zip(face) {
+                *vert = corners[i];
+            }
+
+            for axis_i in 0..3 {
+                for sign in [1, -1] {
+                    let input_len = replace(&mut verts_len, 0);
+                    for i in 0..input_len {
+                        let vert = &verts[i];
+                        let next = &verts[if i + 1 == input_len { 0 } else { i + 1 }];
+                        // Signed distance from the plane, positive inside the view volume
+                        let dist = vert[3] - sign * vert[axis_i];
+                        let next_dist = next[3] - sign * next[axis_i];
+                        if dist >= 0 {
+                            clipped_verts[verts_len] = *vert;
+                            verts_len += 1;
+                        }
+                        if (dist >= 0) != (next_dist >= 0) {
+                            let denom = (dist - next_dist) as i128;
+                            clipped_verts[verts_len] = array::from_fn(|j| {
+                                vert[j]
+                                    + ((next[j] - vert[j]) as i128 * dist as i128 / denom) as i64
+                            });
+                            verts_len += 1;
+                        }
+                    }
+                    if verts_len == 0 {
+                        return false;
+                    }
+                    verts = clipped_verts;
+                }
+            }
+
+            true
+        })
+    }
+
     fn load_matrix(&mut self, matrix: Matrix) {
         match self.mtx_mode {
             MatrixMode::Projection => {
@@ -990,26 +1162,35 @@ impl Engine3d {
         {
             vert.coords[3] &= 0x00FF_FFFF;
             let w = vert.coords[3];
-            let coords = if w == 0 {
+            let (coords, coords_fract) = if w == 0 {
                 // TODO: What should actually happen for W == 0?
-                ScreenCoords::splat(0)
+                (ScreenCoords::splat(0), ScreenCoords::splat(0))
             } else {
                 let w_2 = ConversionScreenCoords::splat(w as i64);
-                ((ConversionScreenCoords::from_array([
+                let numer = (ConversionScreenCoords::from_array([
                     vert.coords[0] as i64,
                     -vert.coords[1] as i64,
                 ]) + w_2)
-                    * viewport_size
-                    / (w_2 << ConversionScreenCoords::splat(1))
-                    + viewport_origin)
-                    .cast::<u16>()
-                    & ScreenCoords::from_array([0x1FF, 0xFF])
+                    * viewport_size;
+                let denom = w_2 << ConversionScreenCoords::splat(1);
+                let int_coords = numer / denom;
+                (
+                    (int_coords + viewport_origin).cast::<u16>()
+                        & ScreenCoords::from_array([0x1FF, 0xFF]),
+                    ScreenCoords::from_array(
+                        ((numer << ConversionScreenCoords::splat(8)) / denom
+                            - (int_coords << ConversionScreenCoords::splat(8)))
+                        .to_array()
+                        .map(|fract| fract.clamp(0, 0xFF) as u16),
+                    ),
+                )
             };
             let y = coords[1] as u8;
             top_y = top_y.min(y);
             bot_y = bot_y.max(y);
             self.vert_ram[self.vert_ram_level as usize] = ScreenVertex {
                 coords,
+                coords_fract,
                 uv: vert.uv,
                 color: vert.color.cast() << InterpColor::splat(3)
                     | vert.color.cast() >> InterpColor::splat(3),
@@ -1074,6 +1255,19 @@ impl Engine3d {
         }
     }
 
+    /// Requests a copy of the next frame submitted to the renderer, discarding any capture that
+    /// wasn't taken yet.
+    #[cfg(feature = "frame-3d-capture")]
+    pub fn request_frame_3d_capture(&mut self) {
+        self.frame_3d_capture_requested = true;
+        self.frame_3d_capture = None;
+    }
+
+    #[cfg(feature = "frame-3d-capture")]
+    pub fn take_frame_3d_capture(&mut self) -> Option<Frame3dCapture> {
+        self.frame_3d_capture.take()
+    }
+
     pub(super) fn swap_buffers_waiting(&self) -> bool {
         self.command_finish_time.0 == RawTimestamp::MAX
     }
@@ -1092,676 +1286,710 @@ impl Engine3d {
         }
     }
 
-    pub(super) fn swap_buffers(emu: &mut Emu<impl cpu::Engine>) {
-        if emu.gpu.engine_3d.rendering_enabled {
+    fn submit_frame(&mut self, texture: &Bytes<0x8_0000>, tex_pal: &Bytes<0x1_8000>) {
+        if self.rendering_enabled {
             // According to melonDS, the sort order is determined by these things, in order of
             // decreasing priority:
             // - Being translucent/opaque (opaque polygons always come first, GBATEK says this too)
             // - Bottom Y (lower first)
             // - Top Y (lower first)
             // - Submit order (thus needing a stable sort)
-            if emu
-                .gpu
-                .engine_3d
-                .swap_buffers_attrs
-                .translucent_auto_sort_disabled()
-            {
-                emu.gpu.engine_3d.poly_ram[..emu.gpu.engine_3d.poly_ram_level as usize]
-                    .sort_by_key(|poly| {
-                        if poly.is_translucent {
-                            0x1_0000
-                        } else {
-                            (poly.bot_y as u32) << 8 | poly.top_y as u32
-                        }
-                    });
+            if self.swap_buffers_attrs.translucent_auto_sort_disabled() {
+                self.poly_ram[..self.poly_ram_level as usize].sort_by_key(|poly| {
+                    if poly.is_translucent {
+                        0x1_0000
+                    } else {
+                        (poly.bot_y as u32) << 8 | poly.top_y as u32
+                    }
+                });
             } else {
-                emu.gpu.engine_3d.poly_ram[..emu.gpu.engine_3d.poly_ram_level as usize]
-                    .sort_by_key(|poly| {
-                        (poly.is_translucent as u32) << 16
-                            | (poly.bot_y as u32) << 8
-                            | poly.top_y as u32
-                    });
+                self.poly_ram[..self.poly_ram_level as usize].sort_by_key(|poly| {
+                    (poly.is_translucent as u32) << 16
+                        | (poly.bot_y as u32) << 8
+                        | poly.top_y as u32
+                });
             }
-            unsafe {
-                emu.gpu.engine_3d.renderer.swap_buffers(
-                    &*emu.gpu.vram.texture.as_bytes_ptr(),
-                    &*emu.gpu.vram.tex_pal.as_bytes_ptr(),
-                    &emu.gpu.engine_3d.vert_ram[..emu.gpu.engine_3d.vert_ram_level as usize],
-                    &emu.gpu.engine_3d.poly_ram[..emu.gpu.engine_3d.poly_ram_level as usize],
-                    &emu.gpu.engine_3d.rendering_state,
-                    emu.gpu.engine_3d.swap_buffers_attrs.w_buffering(),
-                );
+            #[cfg(feature = "frame-3d-capture")]
+            if replace(&mut self.frame_3d_capture_requested, false) {
+                let mut capture = Frame3dCapture {
+                    vert_ram: self.vert_ram[..self.vert_ram_level as usize].to_vec(),
+                    poly_ram: self.poly_ram[..self.poly_ram_level as usize].to_vec(),
+                    control: self.rendering_state.control,
+                    w_buffering: self.swap_buffers_attrs.w_buffering(),
+                    texture: zeroed_box(),
+                    tex_pal: zeroed_box(),
+                };
+                capture.texture.copy_from_slice(&texture[..]);
+                capture.tex_pal.copy_from_slice(&tex_pal[..]);
+                self.frame_3d_capture = Some(capture);
             }
-            emu.gpu.engine_3d.rendering_state.texture_dirty = 0;
-            emu.gpu.engine_3d.rendering_state.tex_pal_dirty = 0;
+            self.renderer.swap_buffers(
+                texture,
+                tex_pal,
+                &self.vert_ram[..self.vert_ram_level as usize],
+                &self.poly_ram[..self.poly_ram_level as usize],
+                &self.rendering_state,
+                self.swap_buffers_attrs.w_buffering(),
+            );
+            self.rendering_state.texture_dirty = 0;
+            self.rendering_state.tex_pal_dirty = 0;
         }
-        emu.gpu.engine_3d.vert_ram_level = 0;
-        emu.gpu.engine_3d.poly_ram_level = 0;
-        Self::process_next_command(emu);
+        self.vert_ram_level = 0;
+        self.poly_ram_level = 0;
     }
 
-    pub(crate) fn process_next_command(emu: &mut Emu<impl cpu::Engine>) {
-        loop {
-            if emu.gpu.engine_3d.gx_pipe.is_empty() {
-                break;
-            }
+    pub(super) fn swap_buffers(emu: &mut Emu<impl cpu::Engine>) {
+        let (texture, tex_pal) = unsafe {
+            (
+                &*emu.gpu.vram.texture.as_bytes_ptr(),
+                &*emu.gpu.vram.tex_pal.as_bytes_ptr(),
+            )
+        };
+        #[cfg(feature = "gx-capture")]
+        emu.gpu.engine_3d.finish_capture(texture, tex_pal);
+        emu.gpu.engine_3d.submit_frame(texture, tex_pal);
+        #[cfg(feature = "gx-capture")]
+        emu.gpu.engine_3d.start_capture();
+        Self::process_next_command(emu);
+    }
 
-            macro_rules! read_from_gx_pipe {
-                () => {
-                    emu.gpu.engine_3d.gx_pipe.read_unchecked()
-                };
-                (
-                    $len: literal,
-                    $iter: expr,
-                    |$elem_ident: ident, $entry_ident: ident| $f: expr
-                ) => {
-                    let mut iter = $iter.into_iter();
-                    let pipe_len = emu.gpu.engine_3d.gx_pipe.len();
-                    if pipe_len >= $len {
-                        for $elem_ident in iter {
-                            let $entry_ident = emu.gpu.engine_3d.gx_pipe.read_unchecked();
-                            $f
-                        }
-                    } else {
-                        for $elem_ident in Iterator::take(&mut iter, pipe_len) {
-                            let $entry_ident = emu.gpu.engine_3d.gx_pipe.read_unchecked();
-                            $f
-                        }
-                        for $elem_ident in iter {
-                            let $entry_ident = emu.gpu.engine_3d.gx_fifo.read_unchecked();
-                            $f
-                        }
+    /// Executes `command`, whose first parameter has already been read from the GX pipe while the
+    /// remaining ones are still queued; returns whether the command was `SWAP_BUFFERS`, which
+    /// stalls the geometry engine until the frame is submitted.
+    fn execute_command(&mut self, command: u8, first_param: u32) -> bool {
+        macro_rules! read_from_gx_pipe {
+            () => {
+                self.gx_pipe.read_unchecked()
+            };
+            (
+                $len: literal,
+                $iter: expr,
+                |$elem_ident: ident, $entry_ident: ident| $f: expr
+            ) => {
+                let mut iter = $iter.into_iter();
+                let pipe_len = self.gx_pipe.len();
+                if pipe_len >= $len {
+                    for $elem_ident in iter {
+                        let $entry_ident = self.gx_pipe.read_unchecked();
+                        $f
+                    }
+                } else {
+                    for $elem_ident in Iterator::take(&mut iter, pipe_len) {
+                        let $entry_ident = self.gx_pipe.read_unchecked();
+                        $f
+                    }
+                    for $elem_ident in iter {
+                        let $entry_ident = self.gx_fifo.read_unchecked();
+                        $f
                     }
-                };
-            }
-
-            let FifoEntry {
-                command,
-                param: first_param,
-            } = unsafe { emu.gpu.engine_3d.gx_pipe.peek_unchecked() };
-
-            if command == 0 {
-                unsafe {
-                    read_from_gx_pipe!();
                 }
-                emu.gpu.engine_3d.refill_gx_pipe(&mut emu.arm9, 0);
-                continue;
-            }
-
-            let params = emu.gpu.engine_3d.params_for_command(command);
+            };
+        }
 
-            if emu.gpu.engine_3d.gx_pipe.len() + emu.gpu.engine_3d.gx_fifo.len() < params as usize {
-                break;
-            }
+        macro_rules! dequeue_mtx_stack_cmd {
+            () => {
+                self.queued_mtx_stack_cmds -= 1;
+                if self.queued_mtx_stack_cmds == 0 {
+                    self.gx_status.set_matrix_stack_busy(false);
+                }
+            };
+        }
 
-            emu.gpu.engine_3d.gx_status.set_busy(true);
-            let prev_gx_pipe_len = emu.gpu.engine_3d.gx_pipe.len();
+        macro_rules! dequeue_test_cmd_entries {
+            ($num: expr) => {
+                self.queued_test_cmd_entries -= $num;
+                if self.queued_test_cmd_entries == 0 {
+                    self.gx_status.set_test_busy(false);
+                }
+            };
+        }
 
-            unsafe {
-                read_from_gx_pipe!();
+        #[allow(clippy::match_same_arms)]
+        match command {
+            0x10 => {
+                // MTX_MODE
+                self.mtx_mode = unsafe { transmute(first_param as u8 & 3) };
             }
 
-            macro_rules! dequeue_mtx_stack_cmd {
-                () => {
-                    emu.gpu.engine_3d.queued_mtx_stack_cmds -= 1;
-                    if emu.gpu.engine_3d.queued_mtx_stack_cmds == 0 {
-                        emu.gpu.engine_3d.gx_status.set_matrix_stack_busy(false);
+            0x11 => {
+                // MTX_PUSH
+                match self.mtx_mode {
+                    MatrixMode::Projection => {
+                        if self.proj_stack_pointer {
+                            self.gx_status.set_matrix_stack_overflow(true);
+                        }
+                        self.proj_stack = self.cur_proj_mtx;
+                        self.proj_stack_pointer = true;
                     }
-                };
-            }
 
-            macro_rules! dequeue_test_cmd_entries {
-                ($num: expr) => {
-                    emu.gpu.engine_3d.queued_test_cmd_entries -= $num;
-                    if emu.gpu.engine_3d.queued_test_cmd_entries == 0 {
-                        emu.gpu.engine_3d.gx_status.set_test_busy(false);
+                    MatrixMode::Position | MatrixMode::PositionVector => {
+                        if self.pos_vec_stack_pointer >= 31 {
+                            self.gx_status.set_matrix_stack_overflow(true);
+                        }
+                        self.pos_vec_stack[(self.pos_vec_stack_pointer & 31) as usize] =
+                            self.cur_pos_vec_mtxs;
+                        self.pos_vec_stack_pointer = (self.pos_vec_stack_pointer + 1).min(63);
                     }
-                };
-            }
 
-            #[allow(clippy::match_same_arms)]
-            match command {
-                0x10 => {
-                    // MTX_MODE
-                    emu.gpu.engine_3d.mtx_mode = unsafe { transmute(first_param as u8 & 3) };
+                    MatrixMode::Texture => {
+                        self.tex_stack = self.cur_tex_mtx;
+                    }
                 }
+                dequeue_mtx_stack_cmd!();
+            }
 
-                0x11 => {
-                    // MTX_PUSH
-                    match emu.gpu.engine_3d.mtx_mode {
-                        MatrixMode::Projection => {
-                            if emu.gpu.engine_3d.proj_stack_pointer {
-                                emu.gpu.engine_3d.gx_status.set_matrix_stack_overflow(true);
-                            }
-                            emu.gpu.engine_3d.proj_stack = emu.gpu.engine_3d.cur_proj_mtx;
-                            emu.gpu.engine_3d.proj_stack_pointer = true;
-                        }
+            0x12 => {
+                // MTX_POP
+                match self.mtx_mode {
+                    MatrixMode::Projection => {
+                        self.proj_stack_pointer = false;
+                        self.cur_proj_mtx = self.proj_stack;
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::Position | MatrixMode::PositionVector => {
-                            if emu.gpu.engine_3d.pos_vec_stack_pointer >= 31 {
-                                emu.gpu.engine_3d.gx_status.set_matrix_stack_overflow(true);
-                            }
-                            emu.gpu.engine_3d.pos_vec_stack
-                                [(emu.gpu.engine_3d.pos_vec_stack_pointer & 31) as usize] =
-                                emu.gpu.engine_3d.cur_pos_vec_mtxs;
-                            emu.gpu.engine_3d.pos_vec_stack_pointer =
-                                (emu.gpu.engine_3d.pos_vec_stack_pointer + 1).min(63);
+                    MatrixMode::Position | MatrixMode::PositionVector => {
+                        self.pos_vec_stack_pointer =
+                            (self.pos_vec_stack_pointer as i8 - ((first_param as i8) << 2 >> 2))
+                                .clamp(0, 63) as u8;
+                        if self.pos_vec_stack_pointer >= 31 {
+                            self.gx_status.set_matrix_stack_overflow(true);
                         }
+                        self.cur_pos_vec_mtxs =
+                            self.pos_vec_stack[(self.pos_vec_stack_pointer & 31) as usize];
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::Texture => {
-                            emu.gpu.engine_3d.tex_stack = emu.gpu.engine_3d.cur_tex_mtx;
-                        }
+                    MatrixMode::Texture => {
+                        self.cur_tex_mtx = self.tex_stack;
                     }
-                    dequeue_mtx_stack_cmd!();
                 }
+                dequeue_mtx_stack_cmd!();
+            }
 
-                0x12 => {
-                    // MTX_POP
-                    match emu.gpu.engine_3d.mtx_mode {
-                        MatrixMode::Projection => {
-                            emu.gpu.engine_3d.proj_stack_pointer = false;
-                            emu.gpu.engine_3d.cur_proj_mtx = emu.gpu.engine_3d.proj_stack;
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+            0x13 => {
+                // MTX_STORE
+                match self.mtx_mode {
+                    MatrixMode::Projection => {
+                        self.proj_stack = self.cur_proj_mtx;
+                    }
 
-                        MatrixMode::Position | MatrixMode::PositionVector => {
-                            emu.gpu.engine_3d.pos_vec_stack_pointer =
-                                (emu.gpu.engine_3d.pos_vec_stack_pointer as i8
-                                    - ((first_param as i8) << 2 >> 2))
-                                    .clamp(0, 63) as u8;
-                            if emu.gpu.engine_3d.pos_vec_stack_pointer >= 31 {
-                                emu.gpu.engine_3d.gx_status.set_matrix_stack_overflow(true);
-                            }
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs = emu.gpu.engine_3d.pos_vec_stack
-                                [(emu.gpu.engine_3d.pos_vec_stack_pointer & 31) as usize];
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
+                    MatrixMode::Position | MatrixMode::PositionVector => {
+                        let addr = first_param as u8 & 31;
+                        if addr == 31 {
+                            self.gx_status.set_matrix_stack_overflow(true);
                         }
+                        self.pos_vec_stack[addr as usize] = self.cur_pos_vec_mtxs;
+                    }
 
-                        MatrixMode::Texture => {
-                            emu.gpu.engine_3d.cur_tex_mtx = emu.gpu.engine_3d.tex_stack;
-                        }
+                    MatrixMode::Texture => {
+                        self.tex_stack = self.cur_tex_mtx;
                     }
-                    dequeue_mtx_stack_cmd!();
                 }
+            }
 
-                0x13 => {
-                    // MTX_STORE
-                    match emu.gpu.engine_3d.mtx_mode {
-                        MatrixMode::Projection => {
-                            emu.gpu.engine_3d.proj_stack = emu.gpu.engine_3d.cur_proj_mtx;
-                        }
+            0x14 => {
+                // MTX_RESTORE
+                match self.mtx_mode {
+                    MatrixMode::Projection => {
+                        self.cur_proj_mtx = self.proj_stack;
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::Position | MatrixMode::PositionVector => {
-                            let addr = first_param as u8 & 31;
-                            if addr == 31 {
-                                emu.gpu.engine_3d.gx_status.set_matrix_stack_overflow(true);
-                            }
-                            emu.gpu.engine_3d.pos_vec_stack[addr as usize] =
-                                emu.gpu.engine_3d.cur_pos_vec_mtxs;
+                    MatrixMode::Position | MatrixMode::PositionVector => {
+                        let addr = first_param as u8 & 31;
+                        if addr == 31 {
+                            self.gx_status.set_matrix_stack_overflow(true);
                         }
+                        self.cur_pos_vec_mtxs = self.pos_vec_stack[addr as usize];
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::Texture => {
-                            emu.gpu.engine_3d.tex_stack = emu.gpu.engine_3d.cur_tex_mtx;
-                        }
+                    MatrixMode::Texture => {
+                        self.cur_tex_mtx = self.tex_stack;
                     }
                 }
+            }
 
-                0x14 => {
-                    // MTX_RESTORE
-                    match emu.gpu.engine_3d.mtx_mode {
-                        MatrixMode::Projection => {
-                            emu.gpu.engine_3d.cur_proj_mtx = emu.gpu.engine_3d.proj_stack;
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
-
-                        MatrixMode::Position | MatrixMode::PositionVector => {
-                            let addr = first_param as u8 & 31;
-                            if addr == 31 {
-                                emu.gpu.engine_3d.gx_status.set_matrix_stack_overflow(true);
-                            }
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs =
-                                emu.gpu.engine_3d.pos_vec_stack[addr as usize];
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
-
-                        MatrixMode::Texture => {
-                            emu.gpu.engine_3d.cur_tex_mtx = emu.gpu.engine_3d.tex_stack;
-                        }
+            0x15 => {
+                // MTX_IDENTITY
+                match self.mtx_mode {
+                    MatrixMode::Projection => {
+                        self.cur_proj_mtx = Matrix::identity();
+                        self.clip_mtx_needs_recalculation = true;
                     }
-                }
 
-                0x15 => {
-                    // MTX_IDENTITY
-                    match emu.gpu.engine_3d.mtx_mode {
-                        MatrixMode::Projection => {
-                            emu.gpu.engine_3d.cur_proj_mtx = Matrix::identity();
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::Position => {
+                        self.cur_pos_vec_mtxs[0] = Matrix::identity();
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::Position => {
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[0] = Matrix::identity();
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::PositionVector => {
+                        self.cur_pos_vec_mtxs[0] = Matrix::identity();
+                        self.cur_pos_vec_mtxs[1] = Matrix::identity();
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::PositionVector => {
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[0] = Matrix::identity();
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[1] = Matrix::identity();
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::Texture => self.cur_tex_mtx = Matrix::identity(),
+                }
+            }
 
-                        MatrixMode::Texture => emu.gpu.engine_3d.cur_tex_mtx = Matrix::identity(),
-                    }
+            0x16 => {
+                // MTX_LOAD_4x4
+                let mut contents = MatrixBuffer([0; 16]);
+                contents.0[0] = first_param as i32;
+                unsafe {
+                    read_from_gx_pipe!(15, &mut contents.0[1..], |elem, entry| *elem =
+                        entry.param as i32);
                 }
+                self.load_matrix(Matrix::new(contents));
+            }
 
-                0x16 => {
-                    // MTX_LOAD_4x4
-                    let mut contents = MatrixBuffer([0; 16]);
-                    contents.0[0] = first_param as i32;
-                    unsafe {
-                        read_from_gx_pipe!(15, &mut contents.0[1..], |elem, entry| *elem =
-                            entry.param as i32);
-                    }
-                    emu.gpu.engine_3d.load_matrix(Matrix::new(contents));
+            0x17 => {
+                // MTX_LOAD_4x3
+                let mut contents = MatrixBuffer([0; 16]);
+                contents.0[0] = first_param as i32;
+                contents.0[15] = 0x1000;
+                unsafe {
+                    read_from_gx_pipe!(11, [1, 2, 4, 5, 6, 8, 9, 10, 12, 13, 14], |i, entry| {
+                        contents.0[i] = entry.param as i32
+                    });
                 }
+                self.load_matrix(Matrix::new(contents));
+            }
 
-                0x17 => {
-                    // MTX_LOAD_4x3
-                    let mut contents = MatrixBuffer([0; 16]);
-                    contents.0[0] = first_param as i32;
-                    contents.0[15] = 0x1000;
-                    unsafe {
-                        read_from_gx_pipe!(
-                            11,
-                            [1, 2, 4, 5, 6, 8, 9, 10, 12, 13, 14],
-                            |i, entry| contents.0[i] = entry.param as i32
-                        );
-                    }
-                    emu.gpu.engine_3d.load_matrix(Matrix::new(contents));
+            0x18 => {
+                // MTX_MULT_4x4
+                let mut contents = MatrixBuffer([0; 16]);
+                contents.0[0] = first_param as i32;
+                unsafe {
+                    read_from_gx_pipe!(15, &mut contents.0[1..], |elem, entry| *elem =
+                        entry.param as i32);
                 }
 
-                0x18 => {
-                    // MTX_MULT_4x4
-                    let mut contents = MatrixBuffer([0; 16]);
-                    contents.0[0] = first_param as i32;
-                    unsafe {
-                        read_from_gx_pipe!(15, &mut contents.0[1..], |elem, entry| *elem =
-                            entry.param as i32);
+                match self.mtx_mode {
+                    MatrixMode::Projection => {
+                        self.cur_proj_mtx.mul_left_4x4(contents);
+                        self.clip_mtx_needs_recalculation = true;
                     }
 
-                    match emu.gpu.engine_3d.mtx_mode {
-                        MatrixMode::Projection => {
-                            emu.gpu.engine_3d.cur_proj_mtx.mul_left_4x4(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::Position => {
+                        self.cur_pos_vec_mtxs[0].mul_left_4x4(contents);
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::Position => {
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[0].mul_left_4x4(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::PositionVector => {
+                        self.cur_pos_vec_mtxs[0].mul_left_4x4(contents);
+                        self.cur_pos_vec_mtxs[1].mul_left_4x4(contents);
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::PositionVector => {
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[0].mul_left_4x4(contents);
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[1].mul_left_4x4(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::Texture => self.cur_tex_mtx.mul_left_4x4(contents),
+                }
+            }
 
-                        MatrixMode::Texture => emu.gpu.engine_3d.cur_tex_mtx.mul_left_4x4(contents),
-                    }
+            0x19 => {
+                // MTX_MULT_4x3
+                let mut contents = MatrixBuffer([0; 12]);
+                contents.0[0] = first_param as i32;
+                unsafe {
+                    read_from_gx_pipe!(11, &mut contents.0[1..], |elem, entry| *elem =
+                        entry.param as i32);
                 }
 
-                0x19 => {
-                    // MTX_MULT_4x3
-                    let mut contents = MatrixBuffer([0; 12]);
-                    contents.0[0] = first_param as i32;
-                    unsafe {
-                        read_from_gx_pipe!(11, &mut contents.0[1..], |elem, entry| *elem =
-                            entry.param as i32);
+                match self.mtx_mode {
+                    MatrixMode::Projection => {
+                        self.cur_proj_mtx.mul_left_4x3(contents);
+                        self.clip_mtx_needs_recalculation = true;
                     }
 
-                    match emu.gpu.engine_3d.mtx_mode {
-                        MatrixMode::Projection => {
-                            emu.gpu.engine_3d.cur_proj_mtx.mul_left_4x3(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::Position => {
+                        self.cur_pos_vec_mtxs[0].mul_left_4x3(contents);
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::Position => {
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[0].mul_left_4x3(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::PositionVector => {
+                        self.cur_pos_vec_mtxs[0].mul_left_4x3(contents);
+                        self.cur_pos_vec_mtxs[1].mul_left_4x3(contents);
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::PositionVector => {
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[0].mul_left_4x3(contents);
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[1].mul_left_4x3(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::Texture => self.cur_tex_mtx.mul_left_4x3(contents),
+                }
+            }
 
-                        MatrixMode::Texture => emu.gpu.engine_3d.cur_tex_mtx.mul_left_4x3(contents),
-                    }
+            0x1A => {
+                // MTX_MULT_3x3
+                let mut contents = MatrixBuffer([0; 9]);
+                contents.0[0] = first_param as i32;
+                unsafe {
+                    read_from_gx_pipe!(8, &mut contents.0[1..], |elem, entry| *elem =
+                        entry.param as i32);
                 }
 
-                0x1A => {
-                    // MTX_MULT_3x3
-                    let mut contents = MatrixBuffer([0; 9]);
-                    contents.0[0] = first_param as i32;
-                    unsafe {
-                        read_from_gx_pipe!(8, &mut contents.0[1..], |elem, entry| *elem =
-                            entry.param as i32);
+                match self.mtx_mode {
+                    MatrixMode::Projection => {
+                        self.cur_proj_mtx.mul_left_3x3(contents);
+                        self.clip_mtx_needs_recalculation = true;
                     }
 
-                    match emu.gpu.engine_3d.mtx_mode {
-                        MatrixMode::Projection => {
-                            emu.gpu.engine_3d.cur_proj_mtx.mul_left_3x3(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::Position => {
+                        self.cur_pos_vec_mtxs[0].mul_left_3x3(contents);
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::Position => {
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[0].mul_left_3x3(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::PositionVector => {
+                        self.cur_pos_vec_mtxs[0].mul_left_3x3(contents);
+                        self.cur_pos_vec_mtxs[1].mul_left_3x3(contents);
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::PositionVector => {
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[0].mul_left_3x3(contents);
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[1].mul_left_3x3(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::Texture => self.cur_tex_mtx.mul_left_3x3(contents),
+                }
+            }
 
-                        MatrixMode::Texture => emu.gpu.engine_3d.cur_tex_mtx.mul_left_3x3(contents),
-                    }
+            0x1B => {
+                // MTX_SCALE
+                let mut contents = [first_param as i32, 0, 0];
+                unsafe {
+                    read_from_gx_pipe!(2, &mut contents[1..], |elem, entry| *elem =
+                        entry.param as i32);
                 }
 
-                0x1B => {
-                    // MTX_SCALE
-                    let mut contents = [first_param as i32, 0, 0];
-                    unsafe {
-                        read_from_gx_pipe!(2, &mut contents[1..], |elem, entry| *elem =
-                            entry.param as i32);
+                match self.mtx_mode {
+                    MatrixMode::Projection => {
+                        self.cur_proj_mtx.scale(contents);
+                        self.clip_mtx_needs_recalculation = true;
                     }
 
-                    match emu.gpu.engine_3d.mtx_mode {
-                        MatrixMode::Projection => {
-                            emu.gpu.engine_3d.cur_proj_mtx.scale(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::Position | MatrixMode::PositionVector => {
+                        self.cur_pos_vec_mtxs[0].scale(contents);
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::Position | MatrixMode::PositionVector => {
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[0].scale(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::Texture => self.cur_tex_mtx.scale(contents),
+                }
+            }
+            0x1C => {
+                // MTX_TRANS
+                let mut contents = [first_param as i32, 0, 0];
+                unsafe {
+                    read_from_gx_pipe!(2, &mut contents[1..], |elem, entry| *elem =
+                        entry.param as i32);
+                }
 
-                        MatrixMode::Texture => emu.gpu.engine_3d.cur_tex_mtx.scale(contents),
+                match self.mtx_mode {
+                    MatrixMode::Projection => {
+                        self.cur_proj_mtx.translate(contents);
+                        self.clip_mtx_needs_recalculation = true;
                     }
-                }
-                0x1C => {
-                    // MTX_TRANS
-                    let mut contents = [first_param as i32, 0, 0];
-                    unsafe {
-                        read_from_gx_pipe!(2, &mut contents[1..], |elem, entry| *elem =
-                            entry.param as i32);
+
+                    MatrixMode::Position => {
+                        self.cur_pos_vec_mtxs[0].translate(contents);
+                        self.clip_mtx_needs_recalculation = true;
                     }
 
-                    match emu.gpu.engine_3d.mtx_mode {
-                        MatrixMode::Projection => {
-                            emu.gpu.engine_3d.cur_proj_mtx.translate(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::PositionVector => {
+                        self.cur_pos_vec_mtxs[0].translate(contents);
+                        self.cur_pos_vec_mtxs[1].translate(contents);
+                        self.clip_mtx_needs_recalculation = true;
+                    }
 
-                        MatrixMode::Position => {
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[0].translate(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+                    MatrixMode::Texture => self.cur_tex_mtx.translate(contents),
+                }
+            }
 
-                        MatrixMode::PositionVector => {
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[0].translate(contents);
-                            emu.gpu.engine_3d.cur_pos_vec_mtxs[1].translate(contents);
-                            emu.gpu.engine_3d.clip_mtx_needs_recalculation = true;
-                        }
+            0x20 => {
+                // COLOR
+                self.vert_color = rgb_5_to_6(decode_rgb_5(first_param as u16, 0));
+            }
 
-                        MatrixMode::Texture => emu.gpu.engine_3d.cur_tex_mtx.translate(contents),
-                    }
+            0x21 => {
+                // NORMAL
+                self.vert_normal = [
+                    (first_param as i16) << 6 >> 6,
+                    (first_param >> 4) as i16 >> 6,
+                    (first_param >> 14) as i16 >> 6,
+                ];
+
+                if self.tex_params.coord_transform_mode() == 2 {
+                    let [u, v, ..] = self
+                        .cur_tex_mtx
+                        .mul_left_vec3_zero::<i16, i16, 21>(self.vert_normal)
+                        .to_array();
+                    self.transformed_tex_coords = self.tex_coords + TexCoords::from_array([u, v]);
                 }
 
-                0x20 => {
-                    // COLOR
-                    emu.gpu.engine_3d.vert_color = rgb_5_to_6(decode_rgb_5(first_param as u16, 0));
-                }
+                self.apply_lighting();
+            }
 
-                0x21 => {
-                    // NORMAL
-                    emu.gpu.engine_3d.vert_normal = [
-                        (first_param as i16) << 6 >> 6,
-                        (first_param >> 4) as i16 >> 6,
-                        (first_param >> 14) as i16 >> 6,
-                    ];
+            0x22 => {
+                // TEXCOORD
+                self.tex_coords =
+                    TexCoords::from_array([first_param as i16, (first_param >> 16) as i16]);
 
-                    if emu.gpu.engine_3d.tex_params.coord_transform_mode() == 2 {
-                        let [u, v, ..] = emu
-                            .gpu
-                            .engine_3d
+                match self.tex_params.coord_transform_mode() {
+                    0 => {
+                        self.transformed_tex_coords = self.tex_coords;
+                    }
+                    1 => {
+                        let [u, v, ..] = self
                             .cur_tex_mtx
-                            .mul_left_vec3_zero::<i16, i16, 21>(emu.gpu.engine_3d.vert_normal)
+                            .mul_left_vec2_one_one::<i16, i16>(self.tex_coords)
                             .to_array();
-                        emu.gpu.engine_3d.transformed_tex_coords =
-                            emu.gpu.engine_3d.tex_coords + TexCoords::from_array([u, v]);
+                        self.transformed_tex_coords = TexCoords::from_array([u, v]);
                     }
-
-                    emu.gpu.engine_3d.apply_lighting();
+                    _ => {}
                 }
+            }
 
-                0x22 => {
-                    // TEXCOORD
-                    emu.gpu.engine_3d.tex_coords =
-                        TexCoords::from_array([first_param as i16, (first_param >> 16) as i16]);
+            0x23 => {
+                // VTX_16
+                let second_param = unsafe { read_from_gx_pipe!() }.param;
+                self.add_vert([
+                    first_param as i16,
+                    (first_param >> 16) as i16,
+                    second_param as i16,
+                ]);
+            }
 
-                    match emu.gpu.engine_3d.tex_params.coord_transform_mode() {
-                        0 => {
-                            emu.gpu.engine_3d.transformed_tex_coords = emu.gpu.engine_3d.tex_coords;
-                        }
-                        1 => {
-                            let [u, v, ..] = emu
-                                .gpu
-                                .engine_3d
-                                .cur_tex_mtx
-                                .mul_left_vec2_one_one::<i16, i16>(emu.gpu.engine_3d.tex_coords)
-                                .to_array();
-                            emu.gpu.engine_3d.transformed_tex_coords =
-                                TexCoords::from_array([u, v]);
-                        }
-                        _ => {}
-                    }
-                }
+            0x24 => {
+                // VTX_10
+                self.add_vert([
+                    (first_param as i16) << 6,
+                    ((first_param >> 10) as i16) << 6,
+                    ((first_param >> 20) as i16) << 6,
+                ]);
+            }
+            0x25 => {
+                // VTX_XY
+                self.add_vert([
+                    first_param as i16,
+                    (first_param >> 16) as i16,
+                    self.last_vtx_coords[2],
+                ]);
+            }
+            0x26 => {
+                // VTX_XZ
+                self.add_vert([
+                    first_param as i16,
+                    self.last_vtx_coords[1],
+                    (first_param >> 16) as i16,
+                ]);
+            }
 
-                0x23 => {
-                    // VTX_16
-                    let second_param = unsafe { read_from_gx_pipe!() }.param;
-                    emu.gpu.engine_3d.add_vert([
-                        first_param as i16,
-                        (first_param >> 16) as i16,
-                        second_param as i16,
-                    ]);
-                }
+            0x27 => {
+                // VTX_YZ
+                self.add_vert([
+                    self.last_vtx_coords[0],
+                    first_param as i16,
+                    (first_param >> 16) as i16,
+                ]);
+            }
 
-                0x24 => {
-                    // VTX_10
-                    emu.gpu.engine_3d.add_vert([
-                        (first_param as i16) << 6,
-                        ((first_param >> 10) as i16) << 6,
-                        ((first_param >> 20) as i16) << 6,
-                    ]);
-                }
-                0x25 => {
-                    // VTX_XY
-                    emu.gpu.engine_3d.add_vert([
-                        first_param as i16,
-                        (first_param >> 16) as i16,
-                        emu.gpu.engine_3d.last_vtx_coords[2],
-                    ]);
-                }
-                0x26 => {
-                    // VTX_XZ
-                    emu.gpu.engine_3d.add_vert([
-                        first_param as i16,
-                        emu.gpu.engine_3d.last_vtx_coords[1],
-                        (first_param >> 16) as i16,
-                    ]);
-                }
+            0x28 => {
+                // VTX_DIFF
+                self.add_vert([
+                    self.last_vtx_coords[0].wrapping_add((first_param as i16) << 6 >> 6),
+                    self.last_vtx_coords[1].wrapping_add((first_param >> 4) as i16 >> 6),
+                    self.last_vtx_coords[2].wrapping_add((first_param >> 14) as i16 >> 6),
+                ]);
+            }
 
-                0x27 => {
-                    // VTX_YZ
-                    emu.gpu.engine_3d.add_vert([
-                        emu.gpu.engine_3d.last_vtx_coords[0],
-                        first_param as i16,
-                        (first_param >> 16) as i16,
-                    ]);
-                }
+            0x29 => {
+                // POLYGON_ATTR
+                self.next_poly_attrs = PolygonAttrs(first_param);
+            }
 
-                0x28 => {
-                    // VTX_DIFF
-                    emu.gpu.engine_3d.add_vert([
-                        emu.gpu.engine_3d.last_vtx_coords[0]
-                            .wrapping_add((first_param as i16) << 6 >> 6),
-                        emu.gpu.engine_3d.last_vtx_coords[1]
-                            .wrapping_add((first_param >> 4) as i16 >> 6),
-                        emu.gpu.engine_3d.last_vtx_coords[2]
-                            .wrapping_add((first_param >> 14) as i16 >> 6),
-                    ]);
-                }
+            0x2A => {
+                // TEXIMAGE_PARAM
+                self.tex_params = TextureParams(first_param);
+            }
 
-                0x29 => {
-                    // POLYGON_ATTR
-                    emu.gpu.engine_3d.next_poly_attrs = PolygonAttrs(first_param);
-                }
+            0x2B => {
+                // PLTT_BASE
+                self.tex_palette_base = first_param as u16 & 0x1FFF;
+            }
 
-                0x2A => {
-                    // TEXIMAGE_PARAM
-                    emu.gpu.engine_3d.tex_params = TextureParams(first_param);
+            0x30 => {
+                // DIF_AMB
+                let diffuse_color = decode_rgb_5(first_param as u16, 0);
+                self.diffuse_color = diffuse_color.cast();
+                self.ambient_color = decode_rgb_5((first_param >> 16) as u16, 0).cast();
+                if first_param & 1 << 15 != 0 {
+                    self.vert_color = rgb_5_to_6(diffuse_color);
                 }
+            }
 
-                0x2B => {
-                    // PLTT_BASE
-                    emu.gpu.engine_3d.tex_palette_base = first_param as u16 & 0x1FFF;
-                }
+            0x31 => {
+                // SPE_EMI
+                self.specular_color = decode_rgb_5(first_param as u16, 0).cast();
+                self.emission_color = decode_rgb_5((first_param >> 16) as u16, 0).cast();
+                self.shininess_table_enabled = first_param & 1 << 15 != 0;
+            }
 
-                0x30 => {
-                    // DIF_AMB
-                    let diffuse_color = decode_rgb_5(first_param as u16, 0);
-                    emu.gpu.engine_3d.diffuse_color = diffuse_color.cast();
-                    emu.gpu.engine_3d.ambient_color =
-                        decode_rgb_5((first_param >> 16) as u16, 0).cast();
-                    if first_param & 1 << 15 != 0 {
-                        emu.gpu.engine_3d.vert_color = rgb_5_to_6(diffuse_color);
-                    }
-                }
+            0x32 => {
+                // LIGHT_VECTOR
+                let transformed = self.cur_pos_vec_mtxs[1]
+                    .mul_left_vec3_zero::<i16, i16, 12>([
+                        (first_param as i16) << 6 >> 6,
+                        (first_param >> 4) as i16 >> 6,
+                        (first_param >> 14) as i16 >> 6,
+                    ])
+                    .to_array();
+                let light = &mut self.lights[(first_param >> 30) as usize];
+                light.direction = [transformed[0], transformed[1], transformed[2]];
+                light.half_vec = [
+                    transformed[0] >> 1,
+                    transformed[1] >> 1,
+                    (transformed[2] - 0x200) >> 1,
+                ];
+            }
 
-                0x31 => {
-                    // SPE_EMI
-                    emu.gpu.engine_3d.specular_color = decode_rgb_5(first_param as u16, 0).cast();
-                    emu.gpu.engine_3d.emission_color =
-                        decode_rgb_5((first_param >> 16) as u16, 0).cast();
-                    emu.gpu.engine_3d.shininess_table_enabled = first_param & 1 << 15 != 0;
-                }
+            0x33 => {
+                // LIGHT_COLOR
+                self.lights[(first_param >> 30) as usize].color =
+                    decode_rgb_5(first_param as u16, 0).cast();
+            }
 
-                0x32 => {
-                    // LIGHT_VECTOR
-                    let transformed = emu.gpu.engine_3d.cur_pos_vec_mtxs[1]
-                        .mul_left_vec3_zero::<i16, i16, 12>([
-                            (first_param as i16) << 6 >> 6,
-                            (first_param >> 4) as i16 >> 6,
-                            (first_param >> 14) as i16 >> 6,
-                        ])
-                        .to_array();
-                    let light = &mut emu.gpu.engine_3d.lights[(first_param >> 30) as usize];
-                    light.direction = [transformed[0], transformed[1], transformed[2]];
-                    light.half_vec = [
-                        transformed[0] >> 1,
-                        transformed[1] >> 1,
-                        (transformed[2] - 0x200) >> 1,
-                    ];
+            0x34 => {
+                // SHININESS
+                self.shininess_table[0] = first_param as u8;
+                self.shininess_table[1] = (first_param >> 8) as u8;
+                self.shininess_table[2] = (first_param >> 16) as u8;
+                self.shininess_table[3] = (first_param >> 24) as u8;
+                unsafe {
+                    read_from_gx_pipe!(31, (4..128).step_by(4), |i, entry| {
+                        self.shininess_table[i] = entry.param as u8;
+                        self.shininess_table[i + 1] = (entry.param >> 8) as u8;
+                        self.shininess_table[i + 2] = (entry.param >> 16) as u8;
+                        self.shininess_table[i + 3] = (entry.param >> 24) as u8;
+                    });
                 }
+            }
 
-                0x33 => {
-                    // LIGHT_COLOR
-                    emu.gpu.engine_3d.lights[(first_param >> 30) as usize].color =
-                        decode_rgb_5(first_param as u16, 0).cast();
-                }
+            0x40 => {
+                // BEGIN_VTXS
+                self.cur_poly_attrs = self.next_poly_attrs;
+                self.cur_prim_type = unsafe { transmute(first_param as u8 & 3) };
+                self.cur_prim_vert_index = PrimVertIndex::new(0);
+                self.cur_prim_max_verts = match self.cur_prim_type {
+                    PrimitiveType::Triangles | PrimitiveType::TriangleStrip => PrimMaxVerts::new(3),
+                    PrimitiveType::Quads | PrimitiveType::QuadStrip => PrimMaxVerts::new(4),
+                };
+                self.cur_strip_prim_is_odd = false;
+                self.connect_to_last_strip_prim = false;
+            }
 
-                0x34 => {
-                    // SHININESS
-                    emu.gpu.engine_3d.shininess_table[0] = first_param as u8;
-                    emu.gpu.engine_3d.shininess_table[1] = (first_param >> 8) as u8;
-                    emu.gpu.engine_3d.shininess_table[2] = (first_param >> 16) as u8;
-                    emu.gpu.engine_3d.shininess_table[3] = (first_param >> 24) as u8;
-                    unsafe {
-                        read_from_gx_pipe!(31, (4..128).step_by(4), |i, entry| {
-                            emu.gpu.engine_3d.shininess_table[i] = entry.param as u8;
-                            emu.gpu.engine_3d.shininess_table[i + 1] = (entry.param >> 8) as u8;
-                            emu.gpu.engine_3d.shininess_table[i + 2] = (entry.param >> 16) as u8;
-                            emu.gpu.engine_3d.shininess_table[i + 3] = (entry.param >> 24) as u8;
-                        });
-                    }
-                }
+            0x41 => {
+                // END_VTXS
+                // Should do nothing according to GBATEK
+            }
 
-                0x40 => {
-                    // BEGIN_VTXS
-                    emu.gpu.engine_3d.cur_poly_attrs = emu.gpu.engine_3d.next_poly_attrs;
-                    emu.gpu.engine_3d.cur_prim_type = unsafe { transmute(first_param as u8 & 3) };
-                    emu.gpu.engine_3d.cur_prim_vert_index = PrimVertIndex::new(0);
-                    emu.gpu.engine_3d.cur_prim_max_verts = match emu.gpu.engine_3d.cur_prim_type {
-                        PrimitiveType::Triangles | PrimitiveType::TriangleStrip => {
-                            PrimMaxVerts::new(3)
-                        }
-                        PrimitiveType::Quads | PrimitiveType::QuadStrip => PrimMaxVerts::new(4),
-                    };
-                    emu.gpu.engine_3d.cur_strip_prim_is_odd = false;
-                    emu.gpu.engine_3d.connect_to_last_strip_prim = false;
-                }
+            0x50 => {
+                // SWAP_BUFFERS
+                self.swap_buffers_attrs = SwapBuffersAttrs(first_param as u8);
+                // Gets unlocked by the GPU when VBlank starts
+                self.command_finish_time.0 = RawTimestamp::MAX;
+                return true;
+            }
 
-                0x41 => {
-                    // END_VTXS
-                    // Should do nothing according to GBATEK
+            0x60 => {
+                // VIEWPORT
+                for i in 0..4 {
+                    self.viewport[i] = (first_param >> (i << 3)) as u8;
                 }
+            }
 
-                0x50 => {
-                    // SWAP_BUFFERS
-                    emu.gpu.engine_3d.swap_buffers_attrs = SwapBuffersAttrs(first_param as u8);
-                    // Gets unlocked by the GPU when VBlank starts
-                    emu.gpu.engine_3d.command_finish_time.0 = RawTimestamp::MAX;
-                    return;
-                }
+            0x70 => {
+                // BOX_TEST
+                let second_param = unsafe { read_from_gx_pipe!() }.param;
+                let third_param = unsafe { read_from_gx_pipe!() }.param;
+                let result = self.box_test([
+                    first_param as i16,
+                    (first_param >> 16) as i16,
+                    second_param as i16,
+                    (second_param >> 16) as i16,
+                    third_param as i16,
+                    (third_param >> 16) as i16,
+                ]);
+                self.gx_status.set_box_test_result(result);
+                dequeue_test_cmd_entries!(3);
+            }
 
-                0x60 => {
-                    // VIEWPORT
-                    for i in 0..4 {
-                        emu.gpu.engine_3d.viewport[i] = (first_param >> (i << 3)) as u8;
-                    }
+            0x71 => {
+                // POS_TEST
+                let second_param = unsafe { read_from_gx_pipe!() }.param;
+                let coords = [
+                    first_param as i16,
+                    (first_param >> 16) as i16,
+                    second_param as i16,
+                ];
+                self.last_vtx_coords = coords;
+                if self.clip_mtx_needs_recalculation {
+                    self.update_clip_mtx();
                 }
+                self.pos_test_result = self
+                    .cur_clip_mtx
+                    .mul_left_vec3::<i16, i32>(coords)
+                    .to_array();
+                dequeue_test_cmd_entries!(2);
+            }
 
-                0x70 => {
-                    // TODO: BOX_TEST
-                    emu.gpu.engine_3d.gx_status.set_box_test_result(true);
-                    for _ in 1..3 {
-                        unsafe {
-                            read_from_gx_pipe!();
-                        }
-                    }
-                    dequeue_test_cmd_entries!(3);
-                }
+            0x72 => {
+                // VEC_TEST
+                let [x, y, z, _] = self.cur_pos_vec_mtxs[1]
+                    .mul_left_vec3_zero::<i16, i16, 9>([
+                        (first_param as i16) << 6 >> 6,
+                        (first_param >> 4) as i16 >> 6,
+                        (first_param >> 14) as i16 >> 6,
+                    ])
+                    .to_array();
+                // The results are 4.12 fixed-point values, sign-extended from bit 12
+                self.vec_test_result = [x, y, z].map(|v| v << 3 >> 3);
+                dequeue_test_cmd_entries!(1);
+            }
 
-                0x71 => {
-                    // TODO: POS_TEST
-                    for _ in 1..2 {
-                        unsafe {
-                            read_from_gx_pipe!();
-                        }
-                    }
-                    dequeue_test_cmd_entries!(2);
-                }
+            _ => {}
+        }
+
+        false
+    }
+
+    pub(crate) fn process_next_command(emu: &mut Emu<impl cpu::Engine>) {
+        loop {
+            if emu.gpu.engine_3d.gx_pipe.is_empty() {
+                break;
+            }
+
+            let FifoEntry {
+                command,
+                param: first_param,
+            } = unsafe { emu.gpu.engine_3d.gx_pipe.peek_unchecked() };
 
-                0x72 => {
-                    // TODO: VEC_TEST
-                    dequeue_test_cmd_entries!(1);
+            if command == 0 {
+                unsafe {
+                    emu.gpu.engine_3d.gx_pipe.read_unchecked();
                 }
+                emu.gpu.engine_3d.refill_gx_pipe(&mut emu.arm9, 0);
+                continue;
+            }
 
-                _ => {}
+            let params = emu.gpu.engine_3d.params_for_command(command);
+
+            if emu.gpu.engine_3d.gx_pipe.len() + emu.gpu.engine_3d.gx_fifo.len() < params as usize {
+                break;
+            }
+
+            emu.gpu.engine_3d.gx_status.set_busy(true);
+            let prev_gx_pipe_len = emu.gpu.engine_3d.gx_pipe.len();
+            let cycles = emu.gpu.engine_3d.command_cycles(command);
+
+            unsafe {
+                emu.gpu.engine_3d.gx_pipe.read_unchecked();
+            }
+
+            if emu.gpu.engine_3d.execute_command(command, first_param) {
+                return;
             }
 
             emu.gpu.engine_3d.refill_gx_pipe(
@@ -1769,8 +1997,10 @@ impl Engine3d {
                 (prev_gx_pipe_len ^ params.max(1) as usize) & 1,
             );
 
+            // The next command can only start executing after this one's cycle count has elapsed
             emu.gpu.engine_3d.command_finish_time.0 =
-                emu::Timestamp::from(arm9::Timestamp(emu.arm9.schedule.cur_time().0 + 1)).0 + 10;
+                emu::Timestamp::from(arm9::Timestamp(emu.arm9.schedule.cur_time().0 + 1)).0
+                    + cycles;
             emu.gpu.engine_3d.gx_fifo_stalled &= emu.gpu.engine_3d.gx_fifo.len() > 256;
             if emu.gpu.engine_3d.gx_fifo_stalled() {
                 emu.schedule.schedule_event(
@@ -0,0 +1,320 @@
+//! Capture and offline replay of a single frame's geometry command stream.
+//!
+//! A capture starts at a frame boundary (when the 3D engine's buffers are swapped) and contains:
+//! - the 3D engine's state at that point, including the matrix stacks, the lighting and material
+//!   parameters and any commands still queued in the GX FIFO;
+//! - every write to the GX FIFO and command ports up to the next buffer swap, after which the
+//!   capture ends;
+//! - the rendering state and the texture and texture palette VRAM as seen by the renderer when
+//!   the frame is submitted, as they're usually only updated during VBlank, after the geometry
+//!   for the next frame has started being sent.
+//!
+//! Replaying a capture only needs the 3D engine and a renderer, so the same frame can be rendered
+//! again without the rest of the emulator.
+//!
+//! Layout (all values little-endian):
+//! - `0x00`: magic (`b"DUSTGXCP"`)
+//! - `0x08`: format version (`u32`)
+//! - `0x0C`: 3D engine state field stream length in bytes (`u32`)
+//! - `0x10`: rendering state field stream length in bytes (`u32`)
+//! - `0x14`: port write count (`u32`)
+//! - `0x18`: 3D engine state field stream
+//! - rendering state field stream
+//! - texture VRAM (`0x8_0000` bytes)
+//! - texture palette VRAM (`0x1_8000` bytes)
+//! - port writes, each as a `u16` I/O address (relative to `0x0400_0000`) followed by the `u32`
+//!   written value
+//!
+//! The field streams are produced by the `Savestate` derive, so the version needs to be bumped
+//! whenever the 3D engine's savestate layout changes.
+
+use super::{Engine3d, FifoEntry, Renderer};
+use crate::utils::{
+    zeroed_box, Bytes, Fifo, LoadableInPlace, SavestateReader, SavestateWriter, Storable,
+};
+use core::{fmt, mem::replace};
+use std::error::Error as StdError;
+
+pub const MAGIC: [u8; 8] = *b"DUSTGXCP";
+pub const VERSION: u32 = 1;
+const HEADER_LEN: usize = 0x18;
+
+pub enum Error {
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    InvalidData,
+}
+
+impl StdError for Error {}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidMagic => f.write_str("not a GX capture file"),
+            Error::UnsupportedVersion(version) => write!(
+                f,
+                "unsupported GX capture format version {} (expected {})",
+                version, VERSION
+            ),
+            Error::Truncated => f.write_str("truncated GX capture"),
+            Error::InvalidData => f.write_str("invalid GX capture data"),
+        }
+    }
+}
+
+impl fmt::Debug for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        <Self as fmt::Display>::fmt(self, f)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PortWrite {
+    pub addr: u16,
+    pub value: u32,
+}
+
+pub struct Capture {
+    engine_state: Vec<u8>,
+    rendering_state: Vec<u8>,
+    texture: Box<Bytes<0x8_0000>>,
+    tex_pal: Box<Bytes<0x1_8000>>,
+    writes: Vec<PortWrite>,
+}
+
+pub(super) enum State {
+    Idle,
+    Requested,
+    Recording(Capture),
+    Finished(Capture),
+}
+
+fn read_u32(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(bytes[offset..offset + 4].try_into().unwrap())
+}
+
+fn take<'a>(contents: &mut &'a [u8], len: usize) -> Result<&'a [u8], Error> {
+    let current = *contents;
+    if current.len() < len {
+        return Err(Error::Truncated);
+    }
+    let (result, rest) = current.split_at(len);
+    *contents = rest;
+    Ok(result)
+}
+
+impl Capture {
+    #[inline]
+    pub fn writes(&self) -> &[PortWrite] {
+        &self.writes
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(
+            HEADER_LEN
+                + self.engine_state.len()
+                + self.rendering_state.len()
+                + self.texture.len()
+                + self.tex_pal.len()
+                + self.writes.len() * 6,
+        );
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&(self.engine_state.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.rendering_state.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&(self.writes.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&self.engine_state);
+        bytes.extend_from_slice(&self.rendering_state);
+        bytes.extend_from_slice(&self.texture[..]);
+        bytes.extend_from_slice(&self.tex_pal[..]);
+        for write in &self.writes {
+            bytes.extend_from_slice(&write.addr.to_le_bytes());
+            bytes.extend_from_slice(&write.value.to_le_bytes());
+        }
+        bytes
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, Error> {
+        if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Err(Error::InvalidMagic);
+        }
+        if bytes.len() < HEADER_LEN {
+            return Err(Error::Truncated);
+        }
+        let version = read_u32(bytes, 8);
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let engine_state_len = read_u32(bytes, 0xC) as usize;
+        let rendering_state_len = read_u32(bytes, 0x10) as usize;
+        let writes_len = read_u32(bytes, 0x14) as usize;
+
+        let mut contents = &bytes[HEADER_LEN..];
+        let engine_state = take(&mut contents, engine_state_len)?.to_vec();
+        let rendering_state = take(&mut contents, rendering_state_len)?.to_vec();
+        let mut texture = zeroed_box::<Bytes<0x8_0000>>();
+        texture.copy_from_slice(take(&mut contents, 0x8_0000)?);
+        let mut tex_pal = zeroed_box::<Bytes<0x1_8000>>();
+        tex_pal.copy_from_slice(take(&mut contents, 0x1_8000)?);
+        let writes = take(&mut contents, writes_len * 6)?
+            .chunks_exact(6)
+            .map(|write| PortWrite {
+                addr: u16::from_le_bytes([write[0], write[1]]),
+                value: read_u32(write, 2),
+            })
+            .collect();
+
+        Ok(Capture {
+            engine_state,
+            rendering_state,
+            texture,
+            tex_pal,
+            writes,
+        })
+    }
+
+    /// Replays the captured frame through a standalone 3D engine, submitting it to `renderer`,
+    /// which is then returned so its output can be read back.
+    pub fn replay(
+        &self,
+        renderer: Box<dyn Renderer>,
+        #[cfg(feature = "log")] logger: slog::Logger,
+    ) -> Result<Box<dyn Renderer>, Error> {
+        let mut engine = Engine3d::new_detached(
+            renderer,
+            #[cfg(feature = "log")]
+            logger,
+        );
+        engine
+            .load_in_place(&mut SavestateReader::new(&self.engine_state))
+            .map_err(|_| Error::InvalidData)?;
+
+        // Commands that were still queued when the capture started have to be processed first
+        if !engine.run_queued_commands() {
+            for write in &self.writes {
+                let mut entries = if write.addr < 0x440 {
+                    engine.packed_command_entries(write.value)
+                } else {
+                    let mut entries = Fifo::new();
+                    let _ = entries
+                        .write(engine.unpacked_command_entry((write.addr >> 2) as u8, write.value));
+                    entries
+                };
+                while let Some(entry) = entries.read() {
+                    if engine.gx_fifo.is_full() {
+                        return Err(Error::InvalidData);
+                    }
+                    engine.track_queued_entry(entry.command);
+                    let _ = engine.gx_fifo.write(entry);
+                }
+                if engine.run_queued_commands() {
+                    break;
+                }
+            }
+        }
+
+        engine
+            .rendering_state
+            .load_in_place(&mut SavestateReader::new(&self.rendering_state))
+            .map_err(|_| Error::InvalidData)?;
+        engine.submit_frame(&self.texture, &self.tex_pal);
+        Ok(engine.renderer)
+    }
+}
+
+impl Engine3d {
+    /// Starts capturing the geometry command stream at the next frame boundary; the capture can be
+    /// retrieved through [`take_capture`](Self::take_capture) once the frame is submitted.
+    pub fn request_capture(&mut self) {
+        if matches!(self.capture, State::Idle | State::Finished(_)) {
+            self.capture = State::Requested;
+        }
+    }
+
+    pub fn take_capture(&mut self) -> Option<Capture> {
+        match replace(&mut self.capture, State::Idle) {
+            State::Finished(capture) => Some(capture),
+            state => {
+                self.capture = state;
+                None
+            }
+        }
+    }
+
+    #[inline]
+    pub(super) fn record_port_write(&mut self, addr: u16, value: u32) {
+        if let State::Recording(capture) = &mut self.capture {
+            capture.writes.push(PortWrite { addr, value });
+        }
+    }
+
+    pub(super) fn start_capture(&mut self) {
+        if !matches!(self.capture, State::Requested) {
+            return;
+        }
+        let mut writer = SavestateWriter::new();
+        self.store(&mut writer)
+            .expect("Couldn't serialize 3D engine state");
+        self.capture = State::Recording(Capture {
+            engine_state: writer.into_bytes(),
+            rendering_state: Vec::new(),
+            texture: zeroed_box(),
+            tex_pal: zeroed_box(),
+            writes: Vec::new(),
+        });
+    }
+
+    pub(super) fn finish_capture(&mut self, texture: &Bytes<0x8_0000>, tex_pal: &Bytes<0x1_8000>) {
+        let mut capture = match replace(&mut self.capture, State::Idle) {
+            State::Recording(capture) => capture,
+            state => {
+                self.capture = state;
+                return;
+            }
+        };
+        let mut writer = SavestateWriter::new();
+        self.rendering_state
+            .store(&mut writer)
+            .expect("Couldn't serialize 3D rendering state");
+        capture.rendering_state = writer.into_bytes();
+        capture.texture.copy_from_slice(&texture[..]);
+        capture.tex_pal.copy_from_slice(&tex_pal[..]);
+        self.capture = State::Finished(capture);
+    }
+
+    /// Processes all queued commands that have received all of their parameters, ignoring timing;
+    /// returns whether processing stopped because of `SWAP_BUFFERS`.
+    fn run_queued_commands(&mut self) -> bool {
+        loop {
+            while !self.gx_pipe.is_full() {
+                match self.gx_fifo.read() {
+                    Some(entry) => {
+                        let _ = self.gx_pipe.write(entry);
+                    }
+                    None => break,
+                }
+            }
+            if self.gx_pipe.is_empty() {
+                return false;
+            }
+
+            let FifoEntry {
+                command,
+                param: first_param,
+            } = unsafe { self.gx_pipe.peek_unchecked() };
+            if command != 0
+                && self.gx_pipe.len() + self.gx_fifo.len()
+                    < self.params_for_command(command) as usize
+            {
+                return false;
+            }
+            unsafe {
+                self.gx_pipe.read_unchecked();
+            }
+            if command != 0 && self.execute_command(command, first_param) {
+                return true;
+            }
+        }
+    }
+}
@@ -76,6 +76,9 @@ pub fn front_facing(v0: &Vertex, v1: &Vertex, v2: &Vertex) -> bool {
 #[repr(C)]
 pub struct ScreenVertex {
     pub coords: ScreenCoords,
+    /// 8-bit fractional part of `coords`; unused by the hardware, but available to renderers
+    /// that rasterize at a higher resolution.
+    pub coords_fract: ScreenCoords,
     pub uv: TexCoords,
     pub color: InterpColor,
 }
@@ -86,6 +89,7 @@ impl ScreenVertex {
     pub const fn new() -> Self {
         ScreenVertex {
             coords: ScreenCoords::splat(0),
+            coords_fract: ScreenCoords::splat(0),
             uv: TexCoords::splat(0),
             color: InterpColor::splat(0),
         }
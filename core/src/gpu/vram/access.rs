@@ -445,7 +445,7 @@ impl Vram {
     #[inline]
     pub fn read_a_obj_ext_pal<T: MemValue>(&self, addr: u32) -> T {
         unsafe {
-            self.a_bg_ext_pal
+            self.a_obj_ext_pal
                 .read_le_aligned_unchecked(addr as usize & (0x1FFF & !(mem::size_of::<T>() - 1)))
         }
     }
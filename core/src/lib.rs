@@ -45,6 +45,7 @@ pub mod emu;
 pub mod flash;
 pub mod gpu;
 pub mod ipc;
+pub mod mic;
 pub mod rtc;
 pub mod spi;
 pub mod wifi;
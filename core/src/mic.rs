@@ -0,0 +1,72 @@
+use crate::{cpu::arm7, utils::schedule::RawTimestamp};
+
+// One sample is consumed every 1024 cycles (33.554432 MHz / 32.768 kHz), matching the audio output
+const CYCLES_PER_SAMPLE: RawTimestamp = 1024;
+pub const SAMPLE_RATE: u32 = 32768;
+
+pub const SAMPLE_CHUNK_SIZE: usize = 0x200;
+
+pub trait Backend {
+    /// Fills `samples` with the next signed 16-bit PCM samples, at [`SAMPLE_RATE`].
+    fn fill_sample_chunk(&mut self, samples: &mut [i16; SAMPLE_CHUNK_SIZE]);
+}
+
+pub struct DummyBackend;
+
+impl Backend for DummyBackend {
+    fn fill_sample_chunk(&mut self, samples: &mut [i16; SAMPLE_CHUNK_SIZE]) {
+        samples.fill(0);
+    }
+}
+
+pub struct Mic {
+    pub backend: Box<dyn Backend>,
+    samples: Box<[i16; SAMPLE_CHUNK_SIZE]>,
+    // Index (in samples since the start of emulation) of the first sample in `samples`, or `None`
+    // if no chunk has been requested yet
+    chunk_start_index: Option<RawTimestamp>,
+}
+
+impl Mic {
+    pub(crate) fn new(backend: Box<dyn Backend>) -> Self {
+        Mic {
+            backend,
+            samples: Box::new([0; SAMPLE_CHUNK_SIZE]),
+            chunk_start_index: None,
+        }
+    }
+
+    /// Replaces the backend, discarding any samples already buffered from the previous one.
+    pub fn set_backend(&mut self, backend: Box<dyn Backend>) {
+        self.backend = backend;
+        self.chunk_start_index = None;
+    }
+
+    pub(crate) fn sample(&mut self, time: arm7::Timestamp) -> i16 {
+        let index = time.0 / CYCLES_PER_SAMPLE;
+        let chunk_start_index = match self.chunk_start_index {
+            Some(start) if (start..start + SAMPLE_CHUNK_SIZE as RawTimestamp).contains(&index) => {
+                start
+            }
+            Some(start)
+                if (start + SAMPLE_CHUNK_SIZE as RawTimestamp
+                    ..start + 2 * SAMPLE_CHUNK_SIZE as RawTimestamp)
+                    .contains(&index) =>
+            {
+                // Keep the sample stream contiguous while it's being read continuously
+                let start = start + SAMPLE_CHUNK_SIZE as RawTimestamp;
+                self.backend.fill_sample_chunk(&mut self.samples);
+                self.chunk_start_index = Some(start);
+                start
+            }
+            _ => {
+                // The microphone wasn't read for a while (or time went backwards after loading a
+                // savestate), there's no point in catching up on the skipped samples
+                self.backend.fill_sample_chunk(&mut self.samples);
+                self.chunk_start_index = Some(index);
+                index
+            }
+        };
+        self.samples[(index - chunk_start_index) as usize]
+    }
+}
@@ -1,6 +1,21 @@
-use crate::utils::Savestate;
-
-// TODO: Implement INT1 and INT2 (and also expose them)
+use crate::{
+    cpu::{self, arm7, Schedule as _},
+    emu::Emu,
+    utils::{schedule::RawTimestamp, Savestate},
+};
+
+// INT1 and INT2 are checked 32 times a second, which is enough to resolve both edges of the highest
+// selectable steady interrupt frequency (16 Hz)
+const TICKS_PER_SECOND: u8 = 32;
+const ARM7_CLOCK_RATE: RawTimestamp = 33_513_982;
+
+// The ARM7 clock rate isn't a multiple of 32 Hz, so the tick periods are spread over a second
+// (each lasting either 1047311 or 1047312 cycles) to avoid drifting
+fn tick_cycles(tick_index: u8) -> RawTimestamp {
+    let tick_start =
+        |index: RawTimestamp| index * ARM7_CLOCK_RATE / TICKS_PER_SECOND as RawTimestamp;
+    tick_start(tick_index as RawTimestamp + 1) - tick_start(tick_index as RawTimestamp)
+}
 
 proc_bitfield::bitfield! {
     #[derive(Clone, Copy, PartialEq, Eq, Savestate)]
@@ -128,6 +143,8 @@ pub struct Rtc {
     int2: [u8; 3],
     pub clock_adjust: u8,
     pub free_reg: u8,
+    tick_index: u8,
+    last_minute: u8,
 }
 
 fn from_bcd(value: u8) -> u8 {
@@ -142,8 +159,11 @@ impl Rtc {
     pub(crate) fn new(
         backend: Box<dyn Backend>,
         first_launch: bool,
+        arm7_schedule: &mut arm7::Schedule,
         #[cfg(feature = "log")] logger: slog::Logger,
     ) -> Self {
+        arm7_schedule.set_event(arm7::event_slots::RTC, arm7::Event::RtcTick);
+        arm7_schedule.schedule_event(arm7::event_slots::RTC, arm7::Timestamp(tick_cycles(0)));
         Rtc {
             #[cfg(feature = "log")]
             logger,
@@ -164,6 +184,8 @@ impl Rtc {
             int2: [0; 3],
             clock_adjust: 0,
             free_reg: 0,
+            tick_index: 0,
+            last_minute: 0,
         }
     }
 
@@ -232,15 +254,97 @@ impl Rtc {
             #[cfg(feature = "log")]
             slog::warn!(self.logger, "Tried to enter unimplemented test mode");
         }
-        if value.int1_mode() != 0 {
-            #[cfg(feature = "log")]
-            slog::warn!(self.logger, "Tried to enable unimplemented alarm 1");
-        }
-        if value.int2_enabled() {
+        if value.int1_mode() & 8 != 0 {
             #[cfg(feature = "log")]
-            slog::warn!(self.logger, "Tried to enable unimplemented alarm 2");
+            slog::warn!(
+                self.logger,
+                "Tried to enable unimplemented 32 kHz INT1 output"
+            );
         }
         self.status2 = value;
+        if self.minute_checks_enabled() {
+            // Avoid spurious interrupts caused by a stale minute value
+            self.last_minute = self.backend.get_time().minute;
+        }
+    }
+
+    fn minute_checks_enabled(&self) -> bool {
+        matches!(self.status2.int1_mode(), 2 | 3 | 4 | 6 | 7) || self.status2.int2_enabled()
+    }
+
+    fn alarm_matches(&self, alarm: [u8; 3], date: Date, time: Time) -> bool {
+        if alarm[0] & 0x80 != 0 && alarm[0] & 7 != date.days_from_sunday {
+            return false;
+        }
+        if alarm[1] & 0x80 != 0 {
+            let hour = from_bcd(alarm[1] & 0x3F)
+                + if self.status1.is_in_24_hour_mode() {
+                    0
+                } else {
+                    12 * (alarm[1] >> 6 & 1)
+                };
+            if hour != time.hour {
+                return false;
+            }
+        }
+        alarm[2] & 0x80 == 0 || from_bcd(alarm[2] & 0x7F) == time.minute
+    }
+
+    /// Advances the interrupt logic by one tick, returning whether /INT was asserted.
+    fn tick(&mut self) -> bool {
+        self.tick_index = (self.tick_index + 1) % TICKS_PER_SECOND;
+
+        let mut int1 = false;
+        let mut int2 = false;
+
+        // Selected frequency steady interrupt
+        if self.status2.int1_mode() & 0xB == 1 {
+            let frequencies = self.int1[2] & 0x1F;
+            if frequencies != 0 {
+                // Assert /INT1 at the start of each period of the highest selected frequency
+                let period = TICKS_PER_SECOND >> (7 - frequencies.leading_zeros());
+                int1 = self.tick_index % period == 0;
+            }
+        }
+
+        if self.minute_checks_enabled() {
+            let (date, time) = self.backend.get_date_time();
+            if time.minute != self.last_minute {
+                self.last_minute = time.minute;
+                match self.status2.int1_mode() {
+                    // Per-minute edge and steady interrupts
+                    2 | 3 | 6 | 7 => int1 = true,
+                    // Alarm 1
+                    4 => int1 = self.alarm_matches(self.int1, date, time),
+                    _ => {}
+                }
+                if self.status2.int2_enabled() {
+                    int2 = self.alarm_matches(self.int2, date, time);
+                }
+            }
+        }
+
+        if int1 {
+            self.status1.set_int1_flag(true);
+        }
+        if int2 {
+            self.status1.set_int2_flag(true);
+        }
+        int1 || int2
+    }
+
+    pub(crate) fn handle_tick<E: cpu::Engine>(emu: &mut Emu<E>, time: arm7::Timestamp) {
+        let int_asserted = emu.rtc.tick();
+        emu.arm7.schedule.schedule_event(
+            arm7::event_slots::RTC,
+            time + arm7::Timestamp(tick_cycles(emu.rtc.tick_index)),
+        );
+        // /INT is connected to SI, which only raises an IRQ if enabled in RCNT
+        if int_asserted && emu.rcnt() & 1 << 8 != 0 {
+            emu.arm7
+                .irqs
+                .write_requested(emu.arm7.irqs.requested().with_sio_rtc(true), ());
+        }
     }
 
     fn latch_date(&mut self, date: Date) {
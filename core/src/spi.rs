@@ -6,6 +6,7 @@ use crate::{
     cpu::{arm7, Schedule as _},
     emu::{self, input},
     flash::Flash,
+    mic,
     utils::Savestate,
     Model,
 };
@@ -45,6 +46,7 @@ pub struct Controller {
 impl Controller {
     pub(crate) fn new(
         firmware: Flash,
+        mic_backend: Box<dyn mic::Backend>,
         model: Model,
         arm7_schedule: &mut arm7::Schedule,
         emu_schedule: &mut emu::Schedule,
@@ -65,6 +67,7 @@ impl Controller {
             touchscreen_hold: false,
             tsc: Tsc::new(
                 model == Model::Lite,
+                mic_backend,
                 #[cfg(feature = "log")]
                 logger.new(slog::o!("tsc" => "")),
             ),
@@ -134,7 +137,13 @@ impl Controller {
             2 => {
                 let is_first = !self.touchscreen_hold;
                 self.touchscreen_hold = self.control.hold();
-                self.tsc.handle_byte(value, is_first, input_status)
+                self.tsc.handle_byte(
+                    value,
+                    is_first,
+                    input_status,
+                    self.power.mic_gain(),
+                    arm7_schedule.cur_time(),
+                )
             }
 
             _ => {
@@ -1,4 +1,9 @@
-use crate::{emu::input, utils::Savestate};
+use crate::{
+    cpu::arm7,
+    emu::input,
+    mic::{self, Mic},
+    utils::Savestate,
+};
 
 proc_bitfield::bitfield! {
     #[derive(Clone, Copy, PartialEq, Eq, Savestate)]
@@ -19,6 +24,8 @@ pub struct Tsc {
     logger: slog::Logger,
     #[savestate(skip)]
     is_ds_lite: bool,
+    #[savestate(skip)]
+    pub mic: Mic,
     pen_down: bool,
     pos: u8,
     cur_control_byte: ControlByte,
@@ -28,11 +35,16 @@ pub struct Tsc {
 }
 
 impl Tsc {
-    pub(super) fn new(is_ds_lite: bool, #[cfg(feature = "log")] logger: slog::Logger) -> Self {
+    pub(super) fn new(
+        is_ds_lite: bool,
+        mic_backend: Box<dyn mic::Backend>,
+        #[cfg(feature = "log")] logger: slog::Logger,
+    ) -> Self {
         Tsc {
             #[cfg(feature = "log")]
             logger,
             is_ds_lite,
+            mic: Mic::new(mic_backend),
             pen_down: false,
             pos: 0,
             cur_control_byte: ControlByte(0),
@@ -85,7 +97,23 @@ impl Tsc {
         }
     }
 
-    fn handle_control_byte(&mut self, value: ControlByte, input_status: &mut input::Status) -> u16 {
+    fn mic_input(&mut self, mic_gain: u8, time: arm7::Timestamp) -> u16 {
+        // Without the amplifier, the input stays at the midpoint; otherwise, the raw samples are
+        // assumed to be at the minimum gain (20x)
+        if mic_gain == 0 {
+            return 0x800;
+        }
+        let sample = self.mic.sample(time) as i32 * mic_gain as i32 / 20;
+        ((sample >> 4) + 0x800).clamp(0, 0xFFF) as u16
+    }
+
+    fn handle_control_byte(
+        &mut self,
+        value: ControlByte,
+        input_status: &mut input::Status,
+        mic_gain: u8,
+        time: arm7::Timestamp,
+    ) -> u16 {
         if value.power_down_mode() & 1 == 0 {
             input_status.set_pen_down(!self.pen_down);
         } else {
@@ -139,12 +167,7 @@ impl Tsc {
             5 => self.x_pos,
             6 => {
                 if value.single_ended_mode() {
-                    #[cfg(feature = "log")]
-                    slog::warn!(
-                        self.logger,
-                        "Reading from unimplemented channel 6 (mic input)"
-                    );
-                    0xFFF
+                    self.mic_input(mic_gain, time)
                 } else if self.is_ds_lite {
                     0xFFF
                 } else {
@@ -184,6 +207,8 @@ impl Tsc {
         value: u8,
         is_first: bool,
         input_status: &mut input::Status,
+        mic_gain: u8,
+        time: arm7::Timestamp,
     ) -> u8 {
         if is_first {
             self.pos = 0;
@@ -191,7 +216,8 @@ impl Tsc {
         if self.pos == 0 {
             if ControlByte(value).start() {
                 self.pos = 1;
-                self.data_out = self.handle_control_byte(ControlByte(value), input_status);
+                self.data_out =
+                    self.handle_control_byte(ControlByte(value), input_status, mic_gain, time);
             }
             0
         } else {
@@ -200,7 +226,8 @@ impl Tsc {
             if self.pos == 2 {
                 if ControlByte(value).start() {
                     self.pos = 1;
-                    self.data_out = self.handle_control_byte(ControlByte(value), input_status);
+                    self.data_out =
+                        self.handle_control_byte(ControlByte(value), input_status, mic_gain, time);
                 }
             } else {
                 self.pos = 2;
@@ -2,6 +2,7 @@ mod saves;
 
 use super::{
     audio,
+    emu::mic::Source as MicSource,
     utils::{config_base, data_base},
 };
 use dust_core::{
@@ -68,8 +69,16 @@ pub struct Global {
     pub autosave_interval_ms: f32,
     pub rtc_time_offset_seconds: i64,
     pub prefer_hle_bios: bool,
+    pub rewind_interval_frames: u32,
+    pub rewind_buffer_size_mib: u32,
+    pub mic_source: MicSource,
+    pub mic_wav_path: Option<PathBuf>,
+    pub renderer_3d_thread_count: u8,
+    pub renderer_3d_scale: u8,
 
     pub save_dir_path: PathBuf,
+    pub savestate_dir_path: PathBuf,
+    pub movie_dir_path: PathBuf,
 
     pub fullscreen_render: bool,
     pub screen_integer_scale: bool,
@@ -103,8 +112,16 @@ impl Default for Global {
             autosave_interval_ms: 1000.0,
             rtc_time_offset_seconds: 0,
             prefer_hle_bios: false,
+            rewind_interval_frames: 10,
+            rewind_buffer_size_mib: 0,
+            mic_source: MicSource::Disabled,
+            mic_wav_path: None,
+            renderer_3d_thread_count: 0,
+            renderer_3d_scale: 1,
 
             save_dir_path: data_base.join("saves"),
+            savestate_dir_path: data_base.join("savestates"),
+            movie_dir_path: data_base.join("movies"),
 
             fullscreen_render: true,
             screen_integer_scale: false,
@@ -138,6 +155,12 @@ pub struct Game {
     pub autosave_interval_ms: Option<f32>,
     pub rtc_time_offset_seconds: Option<i64>,
     pub prefer_hle_bios: Option<bool>,
+    pub rewind_interval_frames: Option<u32>,
+    pub rewind_buffer_size_mib: Option<u32>,
+    pub mic_source: Option<MicSource>,
+    pub mic_wav_path: Option<PathBuf>,
+    pub renderer_3d_thread_count: Option<u8>,
+    pub renderer_3d_scale: Option<u8>,
 
     pub save_path: Option<SavePathConfig>,
 }
@@ -161,6 +184,12 @@ impl Default for Game {
             autosave_interval_ms: None,
             rtc_time_offset_seconds: None,
             prefer_hle_bios: None,
+            rewind_interval_frames: None,
+            rewind_buffer_size_mib: None,
+            mic_source: None,
+            mic_wav_path: None,
+            renderer_3d_thread_count: None,
+            renderer_3d_scale: None,
 
             save_path: Some(SavePathConfig::GlobalSingle),
         }
@@ -424,6 +453,12 @@ pub struct CommonLaunchConfig {
     pub audio_channel_interp_method: GameOverridable<AudioChannelInterpMethod>,
     pub autosave_interval_ms: GameOverridable<f32>,
     pub rtc_time_offset_seconds: GameOverridable<i64>,
+    pub rewind_interval_frames: u32,
+    pub rewind_buffer_size_mib: u32,
+    pub mic_source: MicSource,
+    pub mic_wav_path: Option<PathBuf>,
+    pub renderer_3d_thread_count: u8,
+    pub renderer_3d_scale: u8,
 }
 
 pub struct GameLaunchConfig {
@@ -616,6 +651,14 @@ fn common_launch_config(
     let audio_channel_interp_method = game_overridable!(audio_channel_interp_method);
     let autosave_interval_ms = game_overridable!(autosave_interval_ms);
     let rtc_time_offset_seconds = game_overridable!(rtc_time_offset_seconds);
+    let rewind_interval_frames = plain_setting!(rewind_interval_frames);
+    let rewind_buffer_size_mib = plain_setting!(rewind_buffer_size_mib);
+    let mic_source = plain_setting!(mic_source);
+    let mic_wav_path = game_config
+        .and_then(|config| config.mic_wav_path.clone())
+        .or_else(|| global_config.mic_wav_path.clone());
+    let renderer_3d_thread_count = plain_setting!(renderer_3d_thread_count);
+    let renderer_3d_scale = plain_setting!(renderer_3d_scale).clamp(1, dust_soft_3d::MAX_SCALE);
 
     Ok((
         CommonLaunchConfig {
@@ -635,6 +678,12 @@ fn common_launch_config(
             pause_on_launch,
             autosave_interval_ms,
             rtc_time_offset_seconds,
+            rewind_interval_frames,
+            rewind_buffer_size_mib,
+            mic_source,
+            mic_wav_path,
+            renderer_3d_thread_count,
+            renderer_3d_scale,
         },
         warnings,
     ))
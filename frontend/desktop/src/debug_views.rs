@@ -12,8 +12,14 @@ mod palettes_2d;
 use palettes_2d::Palettes2D;
 mod bg_maps_2d;
 use bg_maps_2d::BgMaps2d;
+mod oam_2d;
+use oam_2d::Oam2d;
+mod vram_banks;
+use vram_banks::VramBanks;
 mod audio_channels;
 use audio_channels::AudioChannels;
+mod engine_3d;
+use engine_3d::Engine3d;
 
 use super::ui::window::Window;
 use dust_core::{cpu, emu::Emu};
@@ -527,11 +533,14 @@ macro_rules! declare_structs {
 declare_structs!(
     singleton arm7_state, CpuState<false>, ToggleArm7State, UpdateArm7State;
     singleton arm9_state, CpuState<true>, ToggleArm9State, UpdateArm9State;
+    singleton engine_3d, Engine3d, ToggleEngine3d, UpdateEngine3d;
     instanceable arm7_memory, CpuMemory<false>, ToggleArm7Memory, UpdateArm7Memory;
     instanceable arm9_memory, CpuMemory<true>, ToggleArm9Memory, UpdateArm9Memory;
     instanceable arm7_disasm, CpuDisasm<false>, ToggleArm7Disasm, UpdateArm7Disasm;
     instanceable arm9_disasm, CpuDisasm<true>, ToggleArm9Disasm, UpdateArm9Disasm;
     instanceable palettes_2d, Palettes2D, TogglePalettes2D, UpdatePalettes2D;
     instanceable bg_maps_2d, BgMaps2d, ToggleBgMaps2d, UpdateBgMaps2d;
+    instanceable oam_2d, Oam2d, ToggleOam2d, UpdateOam2d;
+    instanceable vram_banks, VramBanks, ToggleVramBanks, UpdateVramBanks;
     instanceable audio_channels, AudioChannels, ToggleAudioChannels, UpdateAudioChannels;
 );
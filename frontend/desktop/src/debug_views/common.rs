@@ -15,8 +15,9 @@ mod scrollbar;
 use scrollbar::Scrollbar;
 mod y_pos;
 
+use crate::ui::{imgui_wgpu, window::Window};
 use dust_core::cpu::psr::Mode;
-use imgui::{StyleColor, StyleVar, Ui};
+use imgui::{StyleColor, StyleVar, TextureId, Ui};
 
 pub fn rgb_5_to_rgba8(value: u16) -> u32 {
     let value = value as u32;
@@ -33,6 +34,33 @@ pub fn rgb_5_to_rgba_f32(value: u16) -> [f32; 4] {
     ]
 }
 
+pub fn create_texture(window: &mut Window, label: &str, width: u32, height: u32) -> TextureId {
+    let texture = window.gfx.imgui.create_texture(
+        &window.gfx.device_state.device,
+        &wgpu::SamplerDescriptor {
+            label: Some(label),
+            ..Default::default()
+        },
+        imgui_wgpu::TextureDescriptor {
+            label: Some(label.to_string()),
+            size: wgpu::Extent3d {
+                width,
+                height,
+                depth_or_array_layers: 1,
+            },
+            format: Some(
+                if window.gfx.device_state.surf_config.format.describe().srgb {
+                    wgpu::TextureFormat::Rgba8UnormSrgb
+                } else {
+                    wgpu::TextureFormat::Rgba8Unorm
+                },
+            ),
+            ..Default::default()
+        },
+    );
+    window.gfx.imgui.add_texture(texture)
+}
+
 pub fn psr_mode_to_str(mode: Mode) -> &'static str {
     match mode {
         Mode::User => "User",
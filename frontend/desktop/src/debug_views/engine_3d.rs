@@ -0,0 +1,593 @@
+use super::{
+    common::{create_texture, rgb_5_to_rgba_f32},
+    FrameDataSlot, View,
+};
+use crate::{
+    emu::scene_export::decode_texture,
+    ui::{imgui_wgpu, window::Window},
+};
+use dust_core::{
+    cpu,
+    emu::Emu,
+    gpu::{
+        engine_3d::{Frame3dCapture, Polygon, TextureParams},
+        SCREEN_HEIGHT, SCREEN_WIDTH,
+    },
+    utils::zeroed_box,
+};
+use imgui::{Image, SliderFlags, StyleColor, StyleVar, TableFlags, TextureId, TreeNodeFlags, Ui};
+use std::{slice, sync::Arc};
+
+pub struct FrameData3d {
+    // Only set if a new frame was submitted since the last time the data was prepared; the capture
+    // is shared as-is with the view instead of being copied again
+    capture: Option<Arc<Frame3dCapture>>,
+    screen: Box<[u32; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+}
+
+impl Default for FrameData3d {
+    fn default() -> Self {
+        FrameData3d {
+            capture: None,
+            screen: zeroed_box(),
+        }
+    }
+}
+
+fn format_name(format: u8) -> &'static str {
+    match format {
+        0 => "None",
+        1 => "A3I5",
+        2 => "4-color palette",
+        3 => "16-color palette",
+        4 => "256-color palette",
+        5 => "4x4 compressed",
+        6 => "A5I3",
+        _ => "Direct color",
+    }
+}
+
+fn palette_len(format: u8) -> usize {
+    match format {
+        1 => 32,
+        2 => 4,
+        3 => 16,
+        4 | 5 => 256,
+        6 => 8,
+        _ => 0,
+    }
+}
+
+fn palette_addr(format: u8, palette_base: u16) -> usize {
+    if format == 2 {
+        (palette_base as usize) << 3
+    } else {
+        (palette_base as usize) << 4
+    }
+}
+
+pub struct Engine3d {
+    frame_tex_id: TextureId,
+    texture_tex_id: TextureId,
+    data: FrameData3d,
+    selected_poly: Option<usize>,
+    tex_params: TextureParams,
+    tex_palette_base: u16,
+    texture_needs_update: bool,
+    texture_pixels: Box<[u8]>,
+}
+
+impl Engine3d {
+    fn capture(&self) -> &Frame3dCapture {
+        self.data.capture.as_deref().unwrap()
+    }
+
+    fn select_poly(&mut self, i: usize) {
+        self.selected_poly = Some(i);
+        let poly = self.capture().poly_ram[i];
+        if poly.tex_params.format() != 0 {
+            self.tex_params = poly.tex_params;
+            self.tex_palette_base = poly.tex_palette_base;
+            self.texture_needs_update = true;
+        }
+    }
+
+    fn render_frame(&self, ui: &Ui) {
+        let image_width = ui.content_region_avail()[0].min(SCREEN_WIDTH as f32 * 2.0);
+        let image_size = [
+            image_width,
+            image_width * (SCREEN_HEIGHT as f32 / SCREEN_WIDTH as f32),
+        ];
+        let image_pos = ui.cursor_pos();
+        Image::new(self.frame_tex_id, image_size).build(ui);
+
+        let poly = match self.selected_poly {
+            Some(i) => &self.capture().poly_ram[i],
+            None => return,
+        };
+        let window_abs_pos = ui.window_pos();
+        let image_abs_pos = [
+            window_abs_pos[0] + image_pos[0] - ui.scroll_x(),
+            window_abs_pos[1] + image_pos[1] - ui.scroll_y(),
+        ];
+        let scale = image_width / SCREEN_WIDTH as f32;
+        let points: Vec<_> = poly.vertices[..poly.vertices_len.get() as usize]
+            .iter()
+            .map(|addr| {
+                let vert = &self.capture().vert_ram[addr.get() as usize];
+                let coords = vert.coords.to_array();
+                let fract = vert.coords_fract.to_array();
+                [0, 1].map(|i| {
+                    image_abs_pos[i] + (coords[i] as f32 + fract[i] as f32 / 256.0) * scale
+                })
+            })
+            .collect();
+        let draw_list = ui.get_window_draw_list();
+        for (i, &start) in points.iter().enumerate() {
+            let end = points[(i + 1) % points.len()];
+            draw_list
+                .add_line(start, end, [1.0, 0.0, 1.0, 1.0])
+                .thickness(2.0)
+                .build();
+        }
+    }
+
+    fn render_poly_list(&mut self, ui: &Ui) {
+        let mut clicked_poly = None;
+        ui.child_window("##polygons")
+            .size([0.0, ui.text_line_height_with_spacing() * 12.0])
+            .build(|| {
+                if let Some(_table_token) = ui.begin_table_with_flags(
+                    "##polygon_list",
+                    6,
+                    TableFlags::BORDERS_INNER_V
+                        | TableFlags::ROW_BG
+                        | TableFlags::SIZING_STRETCH_PROP,
+                ) {
+                    for name in ["#", "Vertices", "Mode", "Alpha", "ID", "Texture"] {
+                        ui.table_setup_column(name);
+                    }
+                    ui.table_headers_row();
+
+                    for (i, poly) in self.capture().poly_ram.iter().enumerate() {
+                        ui.table_next_row();
+                        ui.table_next_column();
+                        if ui
+                            .selectable_config(&format!("{}", i))
+                            .selected(self.selected_poly == Some(i))
+                            .span_all_columns(true)
+                            .build()
+                        {
+                            clicked_poly = Some(i);
+                        }
+                        ui.table_next_column();
+                        ui.text(&format!("{}", poly.vertices_len.get()));
+                        ui.table_next_column();
+                        ui.text(poly_mode_name(poly.attrs.mode()));
+                        ui.table_next_column();
+                        ui.text(&format!("{}", poly.attrs.alpha()));
+                        ui.table_next_column();
+                        ui.text(&format!("{}", poly.attrs.id()));
+                        ui.table_next_column();
+                        ui.text(format_name(poly.tex_params.format()));
+                    }
+                }
+            });
+        if let Some(i) = clicked_poly {
+            self.select_poly(i);
+        }
+    }
+
+    fn render_poly_details(&self, ui: &Ui, poly: &Polygon) {
+        let attrs = poly.attrs;
+        ui.text(&format!(
+            "Mode: {}, alpha: {}, ID: {}, lights: {:04b}",
+            poly_mode_name(attrs.mode()),
+            attrs.alpha(),
+            attrs.id(),
+            attrs.lights_mask(),
+        ));
+        ui.text(&format!(
+            "Lines: {}..={}, {}, {}",
+            poly.top_y,
+            poly.bot_y,
+            if poly.is_front_facing {
+                "front-facing"
+            } else {
+                "back-facing"
+            },
+            if poly.is_translucent {
+                "translucent"
+            } else {
+                "opaque"
+            },
+        ));
+        for (label, mut value) in [
+            ("Show back", attrs.show_back()),
+            ("Show front", attrs.show_front()),
+            ("Depth test equal", attrs.depth_test_equal()),
+            ("Fog", attrs.fog_enabled()),
+        ] {
+            ui.checkbox(label, &mut value);
+            ui.same_line();
+        }
+        ui.new_line();
+
+        let tex_params = poly.tex_params;
+        if tex_params.format() == 0 {
+            ui.text("Untextured");
+        } else {
+            ui.text(&format!(
+                "Texture: {}, {}x{} at {:#07X}, palette at {:#07X}",
+                format_name(tex_params.format()),
+                8 << tex_params.size_shift_s(),
+                8 << tex_params.size_shift_t(),
+                (tex_params.vram_off() as u32) << 3,
+                palette_addr(tex_params.format(), poly.tex_palette_base),
+            ));
+            ui.text(&format!(
+                "Repeat: {}{}, flip: {}{}, color 0 transparent: {}, coord transform mode: {}",
+                if tex_params.repeat_s() { "S" } else { "-" },
+                if tex_params.repeat_t() { "T" } else { "-" },
+                if tex_params.flip_s() { "S" } else { "-" },
+                if tex_params.flip_t() { "T" } else { "-" },
+                tex_params.use_color_0_as_transparent(),
+                tex_params.coord_transform_mode(),
+            ));
+        }
+
+        if let Some(_table_token) = ui.begin_table_with_flags(
+            "##vertices",
+            7,
+            TableFlags::BORDERS_INNER_V | TableFlags::ROW_BG | TableFlags::SIZING_STRETCH_PROP,
+        ) {
+            for name in ["Address", "X", "Y", "Depth", "W", "UV", "Color"] {
+                ui.table_setup_column(name);
+            }
+            ui.table_headers_row();
+
+            let verts_len = poly.vertices_len.get() as usize;
+            for (i, addr) in poly.vertices[..verts_len].iter().enumerate() {
+                let addr = addr.get();
+                let vert = &self.capture().vert_ram[addr as usize];
+                let coords = vert.coords.to_array();
+                let fract = vert.coords_fract.to_array();
+                let uv = vert.uv.to_array();
+                let color = vert.color.to_array();
+
+                ui.table_next_row();
+                ui.table_next_column();
+                ui.text(&format!("{:#06X}", addr));
+                for (coord, fract) in coords.into_iter().zip(fract) {
+                    ui.table_next_column();
+                    ui.text(&format!("{:.3}", coord as f32 + fract as f32 / 256.0));
+                }
+                ui.table_next_column();
+                ui.text(&format!("{:#08X}", poly.depth_values[i]));
+                ui.table_next_column();
+                ui.text(&format!("{:#06X}", poly.w_values[i]));
+                ui.table_next_column();
+                ui.text(&format!(
+                    "{:.4}, {:.4}",
+                    uv[0] as f32 / 16.0,
+                    uv[1] as f32 / 16.0
+                ));
+                ui.table_next_column();
+                ui.color_button_config(
+                    &format!("Vertex {} color", i),
+                    [
+                        color[0] as f32 / 511.0,
+                        color[1] as f32 / 511.0,
+                        color[2] as f32 / 511.0,
+                        1.0,
+                    ],
+                )
+                .border(false)
+                .alpha(false)
+                .size([ui.text_line_height(); 2])
+                .build();
+                ui.same_line();
+                ui.text(&format!("{}, {}, {}", color[0], color[1], color[2]));
+            }
+        }
+    }
+
+    fn render_texture_browser(&mut self, ui: &Ui, window: &mut Window) {
+        let style = unsafe { ui.style() };
+        let content_width = ui.content_region_avail()[0];
+        let three_widgets_width = (content_width - 2.0 * style.item_spacing[0]) / 3.0;
+
+        static FORMATS: [u8; 7] = [1, 2, 3, 4, 5, 6, 7];
+        static SIZE_SHIFTS: [u8; 8] = [0, 1, 2, 3, 4, 5, 6, 7];
+
+        let mut format_index = (self.tex_params.format().max(1) - 1) as usize;
+        ui.set_next_item_width(three_widgets_width);
+        if ui.combo("##format", &mut format_index, &FORMATS, |format| {
+            format_name(*format).into()
+        }) {
+            self.tex_params.set_format(FORMATS[format_index]);
+            self.texture_needs_update = true;
+        }
+
+        ui.same_line();
+        let mut size_shift_s = self.tex_params.size_shift_s() as usize;
+        ui.set_next_item_width(three_widgets_width);
+        if ui.combo("##width", &mut size_shift_s, &SIZE_SHIFTS, |shift| {
+            format!("Width: {}", 8 << shift).into()
+        }) {
+            self.tex_params.set_size_shift_s(size_shift_s as u8);
+            self.texture_needs_update = true;
+        }
+
+        ui.same_line();
+        let mut size_shift_t = self.tex_params.size_shift_t() as usize;
+        ui.set_next_item_width(three_widgets_width);
+        if ui.combo("##height", &mut size_shift_t, &SIZE_SHIFTS, |shift| {
+            format!("Height: {}", 8 << shift).into()
+        }) {
+            self.tex_params.set_size_shift_t(size_shift_t as u8);
+            self.texture_needs_update = true;
+        }
+
+        ui.set_next_item_width(three_widgets_width);
+        let mut vram_off = self.tex_params.vram_off();
+        if ui
+            .slider_config("##vram_off", 0_u16, 0xFFFF)
+            .display_format("Offset: %04X (x8)")
+            .flags(SliderFlags::ALWAYS_CLAMP)
+            .build(&mut vram_off)
+        {
+            self.tex_params.set_vram_off(vram_off);
+            self.texture_needs_update = true;
+        }
+
+        ui.same_line();
+        ui.set_next_item_width(three_widgets_width);
+        if ui
+            .slider_config("##palette_base", 0_u16, 0x1FFF)
+            .display_format("Palette: %04X")
+            .flags(SliderFlags::ALWAYS_CLAMP)
+            .build(&mut self.tex_palette_base)
+        {
+            self.texture_needs_update = true;
+        }
+
+        ui.same_line();
+        let mut color_0_transparent = self.tex_params.use_color_0_as_transparent();
+        if ui.checkbox("Color 0 transparent", &mut color_0_transparent) {
+            self.tex_params
+                .set_use_color_0_as_transparent(color_0_transparent);
+            self.texture_needs_update = true;
+        }
+
+        let format = self.tex_params.format();
+        if format == 0 {
+            return;
+        }
+
+        let size = [
+            8 << self.tex_params.size_shift_s(),
+            8 << self.tex_params.size_shift_t(),
+        ];
+        if self.texture_needs_update {
+            self.texture_needs_update = false;
+            let capture = self.capture();
+            let pixels = decode_texture(
+                &capture.texture,
+                &capture.tex_pal,
+                self.tex_params,
+                if format == 7 {
+                    0
+                } else {
+                    self.tex_palette_base
+                },
+            );
+            let row_len = size[0] * 4;
+            for (src, dst) in pixels
+                .chunks_exact(row_len)
+                .zip(self.texture_pixels.chunks_exact_mut(1024 * 4))
+            {
+                dst[..row_len].copy_from_slice(src);
+            }
+            window.gfx.imgui.texture_mut(self.texture_tex_id).set_data(
+                &window.gfx.device_state.queue,
+                &self.texture_pixels,
+                imgui_wgpu::TextureRange {
+                    width: Some(size[0] as u32),
+                    height: Some(size[1] as u32),
+                    ..Default::default()
+                },
+            );
+        }
+
+        let image_width = ui.content_region_avail()[0].min(size[0].max(128) as f32 * 2.0);
+        Image::new(
+            self.texture_tex_id,
+            [image_width, image_width * size[1] as f32 / size[0] as f32],
+        )
+        .uv1([size[0] as f32 / 1024.0, size[1] as f32 / 1024.0])
+        .border_col(ui.style_color(StyleColor::Border))
+        .build(ui);
+
+        let palette_len = palette_len(format);
+        if palette_len == 0 {
+            return;
+        }
+        let pal_base = palette_addr(format, self.tex_palette_base);
+        ui.text(&format!("Palette at {:#07X}", pal_base));
+
+        let _frame_rounding = ui.push_style_var(StyleVar::FrameRounding(1.0));
+        let _cell_padding = ui.push_style_var(StyleVar::CellPadding([1.0; 2]));
+        if let Some(_table_token) = ui.begin_table_with_flags(
+            "##palette",
+            16,
+            TableFlags::NO_CLIP | TableFlags::SIZING_FIXED_FIT,
+        ) {
+            for i in 0..palette_len {
+                ui.table_next_column();
+                let color = self
+                    .data
+                    .tex_pal
+                    .read_le::<u16>((pal_base + (i << 1)) & 0x1_FFFE);
+                ui.color_button_config(&format!("Color {:#04X}", i), rgb_5_to_rgba_f32(color))
+                    .border(false)
+                    .alpha(false)
+                    .size([16.0, 16.0])
+                    .build();
+            }
+        }
+    }
+}
+
+fn poly_mode_name(mode: u8) -> &'static str {
+    match mode {
+        0 => "Modulation",
+        1 => "Decal",
+        2 => "Toon/highlight",
+        _ => "Shadow",
+    }
+}
+
+impl View for Engine3d {
+    const NAME: &'static str = "3D engine";
+
+    type FrameData = FrameData3d;
+    type EmuState = ();
+
+    fn new(window: &mut Window) -> Self {
+        Engine3d {
+            frame_tex_id: create_texture(
+                window,
+                "3D engine frame texture",
+                SCREEN_WIDTH as u32,
+                SCREEN_HEIGHT as u32,
+            ),
+            texture_tex_id: create_texture(window, "3D engine texture browser texture", 1024, 1024),
+            data: FrameData3d::default(),
+            selected_poly: None,
+            tex_params: TextureParams(0).with_format(1),
+            tex_palette_base: 0,
+            texture_needs_update: true,
+            texture_pixels: vec![0; 1024 * 1024 * 4].into_boxed_slice(),
+        }
+    }
+
+    fn destroy(self, window: &mut Window) {
+        window.gfx.imgui.remove_texture(self.frame_tex_id);
+        window.gfx.imgui.remove_texture(self.texture_tex_id);
+    }
+
+    fn emu_state(&self) -> Self::EmuState {}
+
+    fn handle_emu_state_changed<E: cpu::Engine>(
+        _prev: Option<&Self::EmuState>,
+        new: Option<&Self::EmuState>,
+        emu: &mut Emu<E>,
+    ) {
+        if new.is_some() {
+            emu.gpu.engine_3d.request_frame_3d_capture();
+        } else {
+            // Drop any capture that was requested but not taken before the view was closed
+            emu.gpu.engine_3d.take_frame_3d_capture();
+        }
+    }
+
+    fn prepare_frame_data<'a, E: cpu::Engine, S: FrameDataSlot<'a, Self::FrameData>>(
+        _emu_state: &Self::EmuState,
+        emu: &mut Emu<E>,
+        frame_data: S,
+    ) {
+        let frame_data = frame_data.get_or_insert_with(Default::default);
+        frame_data.capture = emu.gpu.engine_3d.take_frame_3d_capture().map(Arc::new);
+        emu.gpu.engine_3d.request_frame_3d_capture();
+        // The 3D engine's output is only visible through engine A
+        frame_data.screen.copy_from_slice(
+            &emu.gpu.framebuffer.0[!emu.gpu.power_control().swap_screens() as usize],
+        );
+    }
+
+    fn clear_frame_data(&mut self) {
+        self.data.capture = None;
+        self.selected_poly = None;
+    }
+
+    fn update_from_frame_data(&mut self, frame_data: &Self::FrameData, window: &mut Window) {
+        if let Some(capture) = &frame_data.capture {
+            self.data.capture = Some(Arc::clone(capture));
+            if self
+                .selected_poly
+                .map_or(false, |i| i >= capture.poly_ram.len())
+            {
+                self.selected_poly = None;
+            }
+            self.texture_needs_update = true;
+        }
+        self.data.screen.copy_from_slice(&frame_data.screen[..]);
+
+        window.gfx.imgui.texture_mut(self.frame_tex_id).set_data(
+            &window.gfx.device_state.queue,
+            unsafe {
+                slice::from_raw_parts(
+                    self.data.screen.as_ptr() as *const u8,
+                    SCREEN_WIDTH * SCREEN_HEIGHT * 4,
+                )
+            },
+            imgui_wgpu::TextureRange::default(),
+        );
+    }
+
+    fn customize_window<'ui, 'a, T: AsRef<str>>(
+        &mut self,
+        _ui: &imgui::Ui,
+        window: imgui::Window<'ui, 'a, T>,
+    ) -> imgui::Window<'ui, 'a, T> {
+        window
+    }
+
+    fn render(
+        &mut self,
+        ui: &Ui,
+        window: &mut Window,
+        _emu_running: bool,
+    ) -> Option<Self::EmuState> {
+        let capture = match &self.data.capture {
+            Some(capture) => capture,
+            None => return None,
+        };
+
+        ui.text(&format!(
+            "{} polygons, {} vertices, {}, texture mapping {}",
+            capture.poly_ram.len(),
+            capture.vert_ram.len(),
+            if capture.w_buffering {
+                "W-buffering"
+            } else {
+                "Z-buffering"
+            },
+            if capture.control.texture_mapping_enabled() {
+                "enabled"
+            } else {
+                "disabled"
+            },
+        ));
+
+        if ui.collapsing_header("Frame", TreeNodeFlags::DEFAULT_OPEN) {
+            self.render_frame(ui);
+        }
+
+        if ui.collapsing_header("Polygons", TreeNodeFlags::DEFAULT_OPEN) {
+            self.render_poly_list(ui);
+            if let Some(i) = self.selected_poly {
+                let poly = self.capture().poly_ram[i];
+                ui.separator();
+                self.render_poly_details(ui, &poly);
+            }
+        }
+
+        if ui.collapsing_header("Texture browser", TreeNodeFlags::empty()) {
+            self.render_texture_browser(ui, window);
+        }
+
+        None
+    }
+}
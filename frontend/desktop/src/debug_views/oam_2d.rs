@@ -0,0 +1,496 @@
+use super::{
+    common::{create_texture, rgb_5_to_rgba8},
+    FrameDataSlot, InstanceableView, View,
+};
+use crate::ui::{imgui_wgpu, window::Window};
+use dust_core::{
+    cpu,
+    emu::Emu,
+    gpu::{
+        engine_2d::{self, obj_size_shift, OamAttr0, OamAttr1, OamAttr2, Role},
+        vram::Vram,
+        SCREEN_HEIGHT, SCREEN_WIDTH,
+    },
+    utils::{zeroed_box, Bytes},
+};
+use imgui::{Image, SliderFlags, StyleColor, TableFlags, TextureId, TreeNodeFlags, Ui};
+use std::slice;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Engine2d {
+    A,
+    B,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Selection {
+    engine: Engine2d,
+}
+
+// Every sprite is decoded into its own 64x64 cell of a 16x8 cell atlas
+const ATLAS_WIDTH: usize = 1024;
+const ATLAS_HEIGHT: usize = 512;
+
+pub struct OamData {
+    engine: Option<Engine2d>,
+    oam: Box<Bytes<0x400>>,
+    obj_pixels: Box<[u32; ATLAS_WIDTH * ATLAS_HEIGHT]>,
+    screen: Box<[u32; SCREEN_WIDTH * SCREEN_HEIGHT]>,
+}
+
+impl Default for OamData {
+    fn default() -> Self {
+        OamData {
+            engine: None,
+            oam: zeroed_box(),
+            obj_pixels: zeroed_box(),
+            screen: zeroed_box(),
+        }
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Obj {
+    attrs: (OamAttr0, OamAttr1, OamAttr2),
+    size_shift: (u8, u8),
+}
+
+impl Obj {
+    fn read(oam: &Bytes<0x400>, i: usize) -> Self {
+        let attrs = (
+            OamAttr0(oam.read_le::<u16>(i << 3)),
+            OamAttr1(oam.read_le::<u16>(i << 3 | 2)),
+            OamAttr2(oam.read_le::<u16>(i << 3 | 4)),
+        );
+        Obj {
+            attrs,
+            size_shift: obj_size_shift(attrs.0, attrs.1),
+        }
+    }
+
+    fn size(&self) -> [u16; 2] {
+        [8 << self.size_shift.0, 8 << self.size_shift.1]
+    }
+
+    fn is_visible(&self) -> bool {
+        self.attrs.0.rot_scale() || !self.attrs.0.disabled()
+    }
+
+    fn is_double_size(&self) -> bool {
+        self.attrs.0.rot_scale() && self.attrs.0.double_size()
+    }
+
+    /// Returns the position and size of the area covered by the sprite on screen; sprites extending
+    /// past the bottom of the 256-line vertical range wrap around to the top, like in the renderer.
+    fn bounds(&self) -> ([i16; 2], [u16; 2]) {
+        let size = self.size().map(|size| size << self.is_double_size() as u8);
+        let y_start = self.attrs.0.y_start() as i16;
+        let y = if y_start + size[1] as i16 > 256 {
+            y_start - 256
+        } else {
+            y_start
+        };
+        ([self.attrs.1.x_start(), y], size)
+    }
+}
+
+fn mode_name(mode: u8) -> &'static str {
+    match mode {
+        0 => "Normal",
+        1 => "Semi-transparent",
+        2 => "Window",
+        _ => "Bitmap",
+    }
+}
+
+pub struct Oam2d {
+    cur_selection: Selection,
+    screen_tex_id: TextureId,
+    objs_tex_id: TextureId,
+    show_disabled_objs: bool,
+    selected_obj: Option<usize>,
+    data: OamData,
+}
+
+impl Oam2d {
+    fn render_screen(&self, ui: &Ui) {
+        let image_width = ui.content_region_avail()[0].min(SCREEN_WIDTH as f32 * 2.0);
+        let image_pos = ui.cursor_pos();
+        Image::new(
+            self.screen_tex_id,
+            [
+                image_width,
+                image_width * (SCREEN_HEIGHT as f32 / SCREEN_WIDTH as f32),
+            ],
+        )
+        .build(ui);
+
+        let window_abs_pos = ui.window_pos();
+        let image_abs_pos = [
+            window_abs_pos[0] + image_pos[0] - ui.scroll_x(),
+            window_abs_pos[1] + image_pos[1] - ui.scroll_y(),
+        ];
+        let scale = image_width / SCREEN_WIDTH as f32;
+        let draw_list = ui.get_window_draw_list();
+        let screen_size = [SCREEN_WIDTH as i16, SCREEN_HEIGHT as i16];
+        for obj_i in 0..128 {
+            let obj = Obj::read(&self.data.oam, obj_i);
+            let selected = self.selected_obj == Some(obj_i);
+            if !obj.is_visible() && !selected {
+                continue;
+            }
+            // Clip the outline to the screen, as sprites can extend past its edges
+            let (pos, size) = obj.bounds();
+            let start = [0, 1].map(|i| pos[i].clamp(0, screen_size[i]));
+            let end = [0, 1].map(|i| (pos[i] + size[i] as i16).clamp(0, screen_size[i]));
+            if start[0] == end[0] || start[1] == end[1] {
+                continue;
+            }
+            let color = if selected {
+                [1.0, 0.0, 1.0, 1.0]
+            } else {
+                [1.0, 1.0, 0.0, 0.75]
+            };
+            draw_list
+                .add_rect(
+                    [0, 1].map(|i| image_abs_pos[i] + start[i] as f32 * scale),
+                    [0, 1].map(|i| image_abs_pos[i] + end[i] as f32 * scale),
+                    color,
+                )
+                .thickness(if selected { 2.0 } else { 1.0 })
+                .build();
+        }
+    }
+
+    fn render_obj_list(&mut self, ui: &Ui) {
+        let mut clicked_obj = None;
+        ui.child_window("##objs")
+            .size([0.0, ui.text_line_height_with_spacing() * 12.0])
+            .build(|| {
+                if let Some(_table_token) = ui.begin_table_with_flags(
+                    "##obj_list",
+                    8,
+                    TableFlags::BORDERS_INNER_V
+                        | TableFlags::ROW_BG
+                        | TableFlags::SIZING_STRETCH_PROP,
+                ) {
+                    for name in [
+                        "#", "Position", "Size", "Mode", "Priority", "Tile", "Palette", "Affine",
+                    ] {
+                        ui.table_setup_column(name);
+                    }
+                    ui.table_headers_row();
+
+                    for i in 0..128 {
+                        let obj = Obj::read(&self.data.oam, i);
+                        if !obj.is_visible() && !self.show_disabled_objs {
+                            continue;
+                        }
+                        let (attr_0, attr_1, attr_2) = obj.attrs;
+                        let size = obj.size();
+
+                        ui.table_next_row();
+                        ui.table_next_column();
+                        if ui
+                            .selectable_config(&format!("{}", i))
+                            .selected(self.selected_obj == Some(i))
+                            .span_all_columns(true)
+                            .build()
+                        {
+                            clicked_obj = Some(i);
+                        }
+                        ui.table_next_column();
+                        ui.text(&format!("{}, {}", attr_1.x_start(), attr_0.y_start()));
+                        ui.table_next_column();
+                        ui.text(&format!("{}x{}", size[0], size[1]));
+                        ui.table_next_column();
+                        ui.text(mode_name(attr_0.mode()));
+                        ui.table_next_column();
+                        ui.text(&format!("{}", attr_2.bg_priority()));
+                        ui.table_next_column();
+                        ui.text(&format!("{:#05X}", attr_2.tile_number()));
+                        ui.table_next_column();
+                        if attr_0.mode() == 3 {
+                            ui.text("-");
+                        } else {
+                            ui.text(&format!("{}", attr_2.palette_number()));
+                        }
+                        ui.table_next_column();
+                        if attr_0.rot_scale() {
+                            ui.text(&format!("{}", attr_1.rot_scale_params_index()));
+                        } else {
+                            ui.text("-");
+                        }
+                    }
+                }
+            });
+        if clicked_obj.is_some() {
+            self.selected_obj = clicked_obj;
+        }
+    }
+
+    fn render_obj_details(&self, ui: &Ui, obj_i: usize) {
+        let obj = Obj::read(&self.data.oam, obj_i);
+        let (attr_0, attr_1, attr_2) = obj.attrs;
+        let size = obj.size();
+
+        let image_size = [0, 1].map(|i| size[i] as f32 * 2.0);
+        let cell_uv = [(obj_i & 15) as f32 * 64.0, (obj_i >> 4) as f32 * 64.0];
+        Image::new(self.objs_tex_id, image_size)
+            .uv0([
+                cell_uv[0] / ATLAS_WIDTH as f32,
+                cell_uv[1] / ATLAS_HEIGHT as f32,
+            ])
+            .uv1([
+                (cell_uv[0] + size[0] as f32) / ATLAS_WIDTH as f32,
+                (cell_uv[1] + size[1] as f32) / ATLAS_HEIGHT as f32,
+            ])
+            .border_col(ui.style_color(StyleColor::Border))
+            .build(ui);
+
+        ui.text(&format!(
+            "OBJ {}: {}x{} at {}, {}, {}",
+            obj_i,
+            size[0],
+            size[1],
+            attr_1.x_start(),
+            attr_0.y_start(),
+            if obj.is_visible() {
+                "visible"
+            } else {
+                "disabled"
+            },
+        ));
+        ui.text(&format!(
+            "Mode: {}, priority: {}, tile: {:#05X}, {}",
+            mode_name(attr_0.mode()),
+            attr_2.bg_priority(),
+            attr_2.tile_number(),
+            if attr_0.mode() == 3 {
+                format!("alpha: {}", attr_2.palette_number())
+            } else if attr_0.use_256_colors() {
+                format!("256 colors, ext palette: {}", attr_2.palette_number())
+            } else {
+                format!("16 colors, palette: {}", attr_2.palette_number())
+            },
+        ));
+        let mut mosaic = attr_0.mosaic_enabled();
+        ui.checkbox("Mosaic", &mut mosaic);
+
+        if attr_0.rot_scale() {
+            ui.same_line();
+            let mut double_size = attr_0.double_size();
+            ui.checkbox("Double size", &mut double_size);
+
+            let params_index = attr_1.rot_scale_params_index() as usize;
+            let params = [0x06, 0x0E, 0x16, 0x1E]
+                .map(|offset| self.data.oam.read_le::<i16>(params_index << 5 | offset));
+            ui.text(&format!(
+                "Affine parameters {}: PA {:.4}, PB {:.4}, PC {:.4}, PD {:.4}",
+                params_index,
+                params[0] as f32 / 256.0,
+                params[1] as f32 / 256.0,
+                params[2] as f32 / 256.0,
+                params[3] as f32 / 256.0,
+            ));
+        } else {
+            ui.same_line();
+            let mut x_flip = attr_1.x_flip();
+            ui.checkbox("X flip", &mut x_flip);
+            ui.same_line();
+            let mut y_flip = attr_1.y_flip();
+            ui.checkbox("Y flip", &mut y_flip);
+        }
+    }
+}
+
+impl View for Oam2d {
+    const NAME: &'static str = "2D OAM";
+
+    type FrameData = OamData;
+    type EmuState = Selection;
+
+    fn new(window: &mut Window) -> Self {
+        Oam2d {
+            cur_selection: Selection {
+                engine: Engine2d::A,
+            },
+            screen_tex_id: create_texture(
+                window,
+                "OAM screen texture",
+                SCREEN_WIDTH as u32,
+                SCREEN_HEIGHT as u32,
+            ),
+            objs_tex_id: create_texture(
+                window,
+                "OAM sprite texture",
+                ATLAS_WIDTH as u32,
+                ATLAS_HEIGHT as u32,
+            ),
+            show_disabled_objs: false,
+            selected_obj: None,
+            data: OamData::default(),
+        }
+    }
+
+    fn destroy(self, window: &mut Window) {
+        window.gfx.imgui.remove_texture(self.screen_tex_id);
+        window.gfx.imgui.remove_texture(self.objs_tex_id);
+    }
+
+    fn emu_state(&self) -> Self::EmuState {
+        self.cur_selection
+    }
+
+    fn handle_emu_state_changed<E: cpu::Engine>(
+        _prev: Option<&Self::EmuState>,
+        _new: Option<&Self::EmuState>,
+        _emu: &mut Emu<E>,
+    ) {
+    }
+
+    fn prepare_frame_data<'a, E: cpu::Engine, S: FrameDataSlot<'a, Self::FrameData>>(
+        emu_state: &Self::EmuState,
+        emu: &mut Emu<E>,
+        frame_data: S,
+    ) {
+        fn copy_objs<R: Role>(engine: &engine_2d::Engine2d<R>, vram: &Vram, data: &mut OamData) {
+            let oam_base = (!R::IS_A as usize) << 10;
+            data.oam
+                .copy_from_slice(&vram.oam.as_byte_slice()[oam_base..oam_base + 0x400]);
+
+            for i in 0..128 {
+                let obj = Obj::read(&data.oam, i);
+                let size = obj.size();
+                let cell_base = (i >> 4) * 64 * ATLAS_WIDTH + (i & 15) * 64;
+                for y in 0..64 {
+                    let line = &mut data.obj_pixels
+                        [cell_base + y * ATLAS_WIDTH..cell_base + y * ATLAS_WIDTH + 64];
+                    for (x, pixel) in line.iter_mut().enumerate() {
+                        *pixel = if x < size[0] as usize && y < size[1] as usize {
+                            engine
+                                .read_obj_pixel(obj.attrs, x as u32, y as u32, vram)
+                                .map_or(0, rgb_5_to_rgba8)
+                        } else {
+                            0
+                        };
+                    }
+                }
+            }
+        }
+
+        let frame_data = frame_data.get_or_insert_with(Default::default);
+        frame_data.engine = Some(emu_state.engine);
+        let swap_screens = emu.gpu.power_control().swap_screens();
+        match emu_state.engine {
+            Engine2d::A => {
+                copy_objs(&emu.gpu.engine_2d_a, &emu.gpu.vram, frame_data);
+                frame_data
+                    .screen
+                    .copy_from_slice(&emu.gpu.framebuffer.0[!swap_screens as usize]);
+            }
+            Engine2d::B => {
+                copy_objs(&emu.gpu.engine_2d_b, &emu.gpu.vram, frame_data);
+                frame_data
+                    .screen
+                    .copy_from_slice(&emu.gpu.framebuffer.0[swap_screens as usize]);
+            }
+        }
+    }
+
+    fn clear_frame_data(&mut self) {
+        self.data.engine = None;
+    }
+
+    fn update_from_frame_data(&mut self, frame_data: &Self::FrameData, window: &mut Window) {
+        self.data.engine = frame_data.engine;
+        self.data.oam.copy_from_slice(&frame_data.oam[..]);
+        self.data
+            .obj_pixels
+            .copy_from_slice(&frame_data.obj_pixels[..]);
+        self.data.screen.copy_from_slice(&frame_data.screen[..]);
+
+        window.gfx.imgui.texture_mut(self.screen_tex_id).set_data(
+            &window.gfx.device_state.queue,
+            unsafe {
+                slice::from_raw_parts(
+                    self.data.screen.as_ptr() as *const u8,
+                    SCREEN_WIDTH * SCREEN_HEIGHT * 4,
+                )
+            },
+            imgui_wgpu::TextureRange::default(),
+        );
+        window.gfx.imgui.texture_mut(self.objs_tex_id).set_data(
+            &window.gfx.device_state.queue,
+            unsafe {
+                slice::from_raw_parts(
+                    self.data.obj_pixels.as_ptr() as *const u8,
+                    ATLAS_WIDTH * ATLAS_HEIGHT * 4,
+                )
+            },
+            imgui_wgpu::TextureRange::default(),
+        );
+    }
+
+    fn customize_window<'ui, 'a, T: AsRef<str>>(
+        &mut self,
+        _ui: &imgui::Ui,
+        window: imgui::Window<'ui, 'a, T>,
+    ) -> imgui::Window<'ui, 'a, T> {
+        window
+    }
+
+    fn render(
+        &mut self,
+        ui: &Ui,
+        _window: &mut Window,
+        _emu_running: bool,
+    ) -> Option<Self::EmuState> {
+        let mut cur_engine = self.cur_selection.engine as u8;
+        ui.set_next_item_width(ui.content_region_avail()[0] * 0.5);
+        let selection_updated = ui
+            .slider_config("##engine", 0_u8, 1)
+            .display_format(match self.cur_selection.engine {
+                Engine2d::A => "Engine A",
+                Engine2d::B => "Engine B",
+            })
+            .flags(SliderFlags::NO_INPUT)
+            .build(&mut cur_engine);
+        self.cur_selection.engine = match cur_engine {
+            0 => Engine2d::A,
+            _ => Engine2d::B,
+        };
+
+        ui.same_line();
+        ui.checkbox("Show disabled sprites", &mut self.show_disabled_objs);
+
+        let new_state = if selection_updated {
+            self.selected_obj = None;
+            Some(self.cur_selection)
+        } else {
+            None
+        };
+
+        if self.data.engine != Some(self.cur_selection.engine) {
+            return new_state;
+        }
+
+        if ui.collapsing_header("Screen", TreeNodeFlags::DEFAULT_OPEN) {
+            self.render_screen(ui);
+        }
+
+        if ui.collapsing_header("Sprites", TreeNodeFlags::DEFAULT_OPEN) {
+            self.render_obj_list(ui);
+            if let Some(i) = self.selected_obj {
+                ui.separator();
+                self.render_obj_details(ui, i);
+            }
+        }
+
+        new_state
+    }
+}
+
+impl InstanceableView for Oam2d {
+    fn finish_preparing_frame_data<E: cpu::Engine>(_emu: &mut Emu<E>) {}
+}
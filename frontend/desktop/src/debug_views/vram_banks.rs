@@ -0,0 +1,595 @@
+use super::{common::rgb_5_to_rgba8, FrameDataSlot, InstanceableView, View};
+use crate::ui::{imgui_wgpu, window::Window};
+use dust_core::{
+    cpu,
+    emu::Emu,
+    gpu::vram::{Arm7Status, BankControl, Vram},
+    utils::{zeroed_box, Bytes},
+};
+use imgui::{Image, SliderFlags, TableFlags, TextureId, TreeNodeFlags, Ui};
+use std::slice;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Region {
+    Lcdc,
+    ABg,
+    AObj,
+    BBg,
+    BObj,
+    Arm7,
+    Texture,
+    TexPal,
+    ABgExtPal,
+    AObjExtPal,
+    BBgExtPal,
+    BObjExtPal,
+}
+
+impl Region {
+    fn name(self) -> &'static str {
+        match self {
+            Region::Lcdc => "LCDC",
+            Region::ABg => "Engine A BG",
+            Region::AObj => "Engine A OBJ",
+            Region::BBg => "Engine B BG",
+            Region::BObj => "Engine B OBJ",
+            Region::Arm7 => "ARM7",
+            Region::Texture => "Texture",
+            Region::TexPal => "Texture palette",
+            Region::ABgExtPal => "Engine A BG ext palette",
+            Region::AObjExtPal => "Engine A OBJ ext palette",
+            Region::BBgExtPal => "Engine B BG ext palette",
+            Region::BObjExtPal => "Engine B OBJ ext palette",
+        }
+    }
+
+    fn len(self) -> u32 {
+        match self {
+            Region::Lcdc => 0xA_4000,
+            Region::ABg | Region::Texture => 0x8_0000,
+            Region::AObj | Region::Arm7 => 0x4_0000,
+            Region::BBg | Region::BObj => 0x2_0000,
+            Region::TexPal => 0x1_8000,
+            Region::ABgExtPal | Region::BBgExtPal => 0x8000,
+            Region::AObjExtPal | Region::BObjExtPal => 0x2000,
+        }
+    }
+
+    // The address the region starts at in the ARM9 or ARM7 address space, if it's CPU-visible
+    fn cpu_base_addr(self) -> Option<u32> {
+        match self {
+            Region::Lcdc => Some(0x0680_0000),
+            Region::ABg | Region::Arm7 => Some(0x0600_0000),
+            Region::AObj => Some(0x0640_0000),
+            Region::BBg => Some(0x0620_0000),
+            Region::BObj => Some(0x0660_0000),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Selection {
+    region: Region,
+    offset: u32,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum ColorFormat {
+    Bpp4,
+    Bpp8,
+    Direct,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum PaletteSource {
+    ABg,
+    AObj,
+    BBg,
+    BObj,
+}
+
+const WINDOW_LEN: usize = 0x1_0000;
+
+pub struct VramData {
+    bank_control: [BankControl; 9],
+    arm7_status: Arm7Status,
+    selection: Option<Selection>,
+    window: Box<Bytes<WINDOW_LEN>>,
+    palette: Box<Bytes<0x800>>,
+}
+
+impl Default for VramData {
+    fn default() -> Self {
+        VramData {
+            bank_control: [BankControl(0); 9],
+            arm7_status: Arm7Status(0),
+            selection: None,
+            window: zeroed_box(),
+            palette: zeroed_box(),
+        }
+    }
+}
+
+const BANK_NAMES: [&str; 9] = ["A", "B", "C", "D", "E", "F", "G", "H", "I"];
+const BANK_LENS: [u32; 9] = [
+    0x2_0000, 0x2_0000, 0x2_0000, 0x2_0000, 0x1_0000, 0x4000, 0x4000, 0x8000, 0x4000,
+];
+const BANK_LCDC_ADDRS: [u32; 9] = [
+    0x0680_0000,
+    0x0682_0000,
+    0x0684_0000,
+    0x0686_0000,
+    0x0688_0000,
+    0x0689_0000,
+    0x0689_4000,
+    0x0689_8000,
+    0x068A_0000,
+];
+
+/// Describes where a bank is mapped with the given control value, following the same MST/offset
+/// rules as `Vram::write_bank_control_*`.
+fn bank_mapping(bank: usize, control: BankControl) -> String {
+    if !control.enabled() {
+        return "Disabled".to_string();
+    }
+    let len = BANK_LENS[bank];
+    let range = |name: &str, start: u32| format!("{} {:#010X}..{:#010X}", name, start, start + len);
+    let ofs = control.offset() as u32;
+    // F and G are mapped in units of 16 KiB, but skip 64 KiB for every second offset
+    let fg_off = (ofs & 1) * 0x4000 + (ofs >> 1) * 0x1_0000;
+    let mst = if matches!(bank, 0 | 1 | 7 | 8) {
+        control.mst() & 3
+    } else {
+        control.mst()
+    };
+    match (bank, mst) {
+        (_, 0) => range("LCDC", BANK_LCDC_ADDRS[bank]),
+        (0..=3, 1) => range("Engine A BG", 0x0600_0000 + ofs * 0x2_0000),
+        (0 | 1, 2) | (4, 2) => range("Engine A OBJ", 0x0640_0000 + (ofs & 1) * 0x2_0000),
+        (0..=3, 3) => format!("Texture slot {}", ofs),
+        (2 | 3, 2) => range("ARM7", 0x0600_0000 + (ofs & 1) * 0x2_0000),
+        (2, 4) => range("Engine B BG", 0x0620_0000),
+        (3, 4) => range("Engine B OBJ", 0x0660_0000),
+        (4, 1) => range("Engine A BG", 0x0600_0000),
+        (4, 3) => "Texture palette slots 0-3".to_string(),
+        (4, 4) => "Engine A BG ext palette slots 0-3".to_string(),
+        (5 | 6, 1) => range("Engine A BG", 0x0600_0000 + fg_off),
+        (5 | 6, 2) => range("Engine A OBJ", 0x0640_0000 + fg_off),
+        (5 | 6, 3) => format!("Texture palette slot {}", (ofs & 1) + (ofs >> 1) * 4),
+        (5 | 6, 4) => format!(
+            "Engine A BG ext palette slots {}-{}",
+            (ofs & 1) * 2,
+            (ofs & 1) * 2 + 1
+        ),
+        (5 | 6, 5) => "Engine A OBJ ext palette".to_string(),
+        (7, 1) => range("Engine B BG", 0x0620_0000),
+        (7, 2) => "Engine B BG ext palette slots 0-3".to_string(),
+        (8, 1) => range("Engine B BG", 0x0620_8000),
+        (8, 2) => range("Engine B OBJ", 0x0660_0000),
+        (8, 3) => "Engine B OBJ ext palette".to_string(),
+        _ => format!("Invalid (MST {})", mst),
+    }
+}
+
+fn read_window(vram: &Vram, selection: &Selection, window: &mut Bytes<WINDOW_LEN>) {
+    let offset = selection.offset;
+    let len = (selection.region.len() - offset).min(WINDOW_LEN as u32) as usize;
+    window.fill(0);
+    let dst = window.as_mut_ptr() as *mut usize;
+    // Regions without slice accessors are read a word at a time
+    let read_word: fn(&Vram, u32) -> u32 = unsafe {
+        match selection.region {
+            Region::ABg => return vram.read_a_bg_slice(offset, len, dst),
+            Region::BBg => return vram.read_b_bg_slice(offset, len, dst),
+            Region::Texture => return vram.read_texture_slice(offset, len, dst),
+            Region::TexPal => return vram.read_tex_pal_slice(offset, len, dst),
+            Region::ABgExtPal => return vram.read_a_bg_ext_pal_slice(offset, len, dst),
+            Region::AObjExtPal => return vram.read_a_obj_ext_pal_slice(offset, len, dst),
+            Region::BBgExtPal => return vram.read_b_bg_ext_pal_slice(offset, len, dst),
+            Region::BObjExtPal => return vram.read_b_obj_ext_pal_slice(offset, len, dst),
+            Region::Lcdc => Vram::read_lcdc::<u32>,
+            Region::AObj => Vram::read_a_obj::<u32>,
+            Region::BObj => Vram::read_b_obj::<u32>,
+            Region::Arm7 => Vram::read_arm7::<u32>,
+        }
+    };
+    for i in (0..len).step_by(4) {
+        window[i..i + 4].copy_from_slice(&read_word(vram, offset + i as u32).to_le_bytes());
+    }
+}
+
+pub struct VramBanks {
+    cur_selection: Selection,
+    tex_id: TextureId,
+    color_format: ColorFormat,
+    tiled: bool,
+    width: usize,
+    palette_source: PaletteSource,
+    palette_number: u8,
+    pixel_buffer: Box<[u32; 1024 * 1024]>,
+    data: VramData,
+}
+
+impl VramBanks {
+    fn render_banks(&self, ui: &Ui) {
+        if let Some(_table_token) = ui.begin_table_with_flags(
+            "##banks",
+            5,
+            TableFlags::BORDERS_INNER_V | TableFlags::ROW_BG | TableFlags::SIZING_STRETCH_PROP,
+        ) {
+            for name in ["Bank", "VRAMCNT", "MST", "Offset", "Mapping"] {
+                ui.table_setup_column(name);
+            }
+            ui.table_headers_row();
+
+            for (i, control) in self.data.bank_control.iter().enumerate() {
+                ui.table_next_row();
+                ui.table_next_column();
+                ui.text(&format!("{} ({} KiB)", BANK_NAMES[i], BANK_LENS[i] >> 10));
+                ui.table_next_column();
+                ui.text(&format!("{:#04X}", control.0));
+                ui.table_next_column();
+                ui.text(&format!("{}", control.mst()));
+                ui.table_next_column();
+                ui.text(&format!("{}", control.offset()));
+                ui.table_next_column();
+                ui.text(&bank_mapping(i, *control));
+            }
+        }
+
+        ui.text(&format!(
+            "VRAMSTAT: C {}, D {}",
+            if self.data.arm7_status.c_used_as_arm7() {
+                "used as ARM7 VRAM"
+            } else {
+                "not used as ARM7 VRAM"
+            },
+            if self.data.arm7_status.d_used_as_arm7() {
+                "used as ARM7 VRAM"
+            } else {
+                "not used as ARM7 VRAM"
+            },
+        ));
+    }
+
+    fn render_tile_viewer_settings(&mut self, ui: &Ui) -> bool {
+        let style = unsafe { ui.style() };
+        let content_width = ui.content_region_avail()[0];
+        let three_widgets_width = (content_width - 2.0 * style.item_spacing[0]) / 3.0;
+        let mut selection_updated = false;
+
+        static REGIONS: [Region; 12] = [
+            Region::Lcdc,
+            Region::ABg,
+            Region::AObj,
+            Region::BBg,
+            Region::BObj,
+            Region::Arm7,
+            Region::Texture,
+            Region::TexPal,
+            Region::ABgExtPal,
+            Region::AObjExtPal,
+            Region::BBgExtPal,
+            Region::BObjExtPal,
+        ];
+        let mut region_index = REGIONS
+            .iter()
+            .position(|region| *region == self.cur_selection.region)
+            .unwrap();
+        ui.set_next_item_width(three_widgets_width);
+        if ui.combo("##region", &mut region_index, &REGIONS, |region| {
+            region.name().into()
+        }) {
+            self.cur_selection.region = REGIONS[region_index];
+            self.cur_selection.offset = 0;
+            selection_updated = true;
+        }
+
+        ui.same_line();
+        ui.set_next_item_width(three_widgets_width);
+        // The offset is selected in units of 0x100 bytes, printed followed by two zeros
+        let mut offset = self.cur_selection.offset >> 8;
+        if ui
+            .slider_config("##offset", 0, (self.cur_selection.region.len() >> 8) - 1)
+            .display_format("Offset: %04X00")
+            .flags(SliderFlags::ALWAYS_CLAMP)
+            .build(&mut offset)
+        {
+            self.cur_selection.offset = offset << 8;
+            selection_updated = true;
+        }
+
+        ui.same_line();
+        static WIDTHS: [usize; 4] = [64, 128, 256, 512];
+        let mut width_index = WIDTHS.iter().position(|w| *w == self.width).unwrap();
+        ui.set_next_item_width(three_widgets_width);
+        if ui.combo("##width", &mut width_index, &WIDTHS, |width| {
+            format!("Width: {}", width).into()
+        }) {
+            self.width = WIDTHS[width_index];
+        }
+
+        static COLOR_FORMATS: [ColorFormat; 3] =
+            [ColorFormat::Bpp4, ColorFormat::Bpp8, ColorFormat::Direct];
+        let mut color_format_index = COLOR_FORMATS
+            .iter()
+            .position(|format| *format == self.color_format)
+            .unwrap();
+        ui.set_next_item_width(three_widgets_width);
+        if ui.combo(
+            "##color_format",
+            &mut color_format_index,
+            &COLOR_FORMATS,
+            |format| {
+                match format {
+                    ColorFormat::Bpp4 => "4 bpp",
+                    ColorFormat::Bpp8 => "8 bpp",
+                    ColorFormat::Direct => "Direct color",
+                }
+                .into()
+            },
+        ) {
+            self.color_format = COLOR_FORMATS[color_format_index];
+        }
+
+        ui.same_line();
+        static PALETTE_SOURCES: [PaletteSource; 4] = [
+            PaletteSource::ABg,
+            PaletteSource::AObj,
+            PaletteSource::BBg,
+            PaletteSource::BObj,
+        ];
+        let mut palette_source_index = PALETTE_SOURCES
+            .iter()
+            .position(|source| *source == self.palette_source)
+            .unwrap();
+        ui.set_next_item_width(three_widgets_width);
+        if ui.combo(
+            "##palette_source",
+            &mut palette_source_index,
+            &PALETTE_SOURCES,
+            |source| {
+                match source {
+                    PaletteSource::ABg => "Engine A BG palette",
+                    PaletteSource::AObj => "Engine A OBJ palette",
+                    PaletteSource::BBg => "Engine B BG palette",
+                    PaletteSource::BObj => "Engine B OBJ palette",
+                }
+                .into()
+            },
+        ) {
+            self.palette_source = PALETTE_SOURCES[palette_source_index];
+        }
+
+        ui.same_line();
+        ui.set_next_item_width(three_widgets_width);
+        ui.slider_config("##palette_number", 0_u8, 15)
+            .display_format("Palette: %d")
+            .flags(SliderFlags::ALWAYS_CLAMP)
+            .build(&mut self.palette_number);
+
+        ui.checkbox("Tiled", &mut self.tiled);
+
+        selection_updated
+    }
+
+    fn render_tile_viewer(&mut self, ui: &Ui, window: &mut Window) {
+        let pixels_len = match self.color_format {
+            ColorFormat::Bpp4 => WINDOW_LEN * 2,
+            ColorFormat::Bpp8 => WINDOW_LEN,
+            ColorFormat::Direct => WINDOW_LEN / 2,
+        };
+        let width = self.width;
+        let height = (pixels_len / width).min(1024);
+
+        let pal_base = match self.palette_source {
+            PaletteSource::ABg => 0,
+            PaletteSource::AObj => 0x200,
+            PaletteSource::BBg => 0x400,
+            PaletteSource::BObj => 0x600,
+        };
+        let read_pal_color = |index: usize| {
+            self.data
+                .palette
+                .read_le::<u16>(pal_base | (index & 0xFF) << 1)
+        };
+
+        for y in 0..height {
+            for x in 0..width {
+                let i = if self.tiled {
+                    let tile = (y >> 3) * (width >> 3) + (x >> 3);
+                    tile << 6 | (y & 7) << 3 | (x & 7)
+                } else {
+                    y * width + x
+                };
+                let color = match self.color_format {
+                    ColorFormat::Bpp4 => {
+                        let color_index = self.data.window[i >> 1] >> ((i & 1) << 2) & 0xF;
+                        read_pal_color((self.palette_number as usize) << 4 | color_index as usize)
+                    }
+                    ColorFormat::Bpp8 => read_pal_color(self.data.window[i] as usize),
+                    ColorFormat::Direct => self.data.window.read_le::<u16>(i << 1),
+                };
+                self.pixel_buffer[y << 10 | x] = rgb_5_to_rgba8(color);
+            }
+        }
+
+        window.gfx.imgui.texture_mut(self.tex_id).set_data(
+            &window.gfx.device_state.queue,
+            unsafe {
+                slice::from_raw_parts(self.pixel_buffer.as_ptr() as *const u8, 1024 * 1024 * 4)
+            },
+            imgui_wgpu::TextureRange {
+                width: Some(width as u32),
+                height: Some(height as u32),
+                ..Default::default()
+            },
+        );
+
+        let image_width = ui.content_region_avail()[0].min(width as f32 * 2.0);
+        let scale = image_width / width as f32;
+        let image_pos = ui.cursor_screen_pos();
+        Image::new(self.tex_id, [image_width, height as f32 * scale])
+            .uv1([width as f32 / 1024.0, height as f32 / 1024.0])
+            .build(ui);
+
+        if ui.is_item_hovered() {
+            let mouse_pos = ui.io().mouse_pos;
+            let x = (((mouse_pos[0] - image_pos[0]) / scale) as usize).min(width - 1);
+            let y = (((mouse_pos[1] - image_pos[1]) / scale) as usize).min(height - 1);
+            let (i, tile) = if self.tiled {
+                let tile = (y >> 3) * (width >> 3) + (x >> 3);
+                (tile << 6 | (y & 7) << 3 | (x & 7), Some(tile))
+            } else {
+                (y * width + x, None)
+            };
+            let byte_offset = self.cur_selection.offset
+                + match self.color_format {
+                    ColorFormat::Bpp4 => i >> 1,
+                    ColorFormat::Bpp8 => i,
+                    ColorFormat::Direct => i << 1,
+                } as u32;
+            ui.tooltip(|| {
+                if let Some(tile) = tile {
+                    ui.text(&format!("Tile {:#05X}", tile));
+                }
+                ui.text(&format!("X: {}, Y: {}", x, y));
+                ui.text(&format!("Offset: {:#07X}", byte_offset));
+                if let Some(base_addr) = self.cur_selection.region.cpu_base_addr() {
+                    ui.text(&format!("Address: {:#010X}", base_addr + byte_offset));
+                }
+            });
+        }
+    }
+}
+
+impl View for VramBanks {
+    const NAME: &'static str = "VRAM banks";
+
+    type FrameData = VramData;
+    type EmuState = Selection;
+
+    fn new(window: &mut Window) -> Self {
+        let tex_id = {
+            let texture = window.gfx.imgui.create_texture(
+                &window.gfx.device_state.device,
+                &wgpu::SamplerDescriptor {
+                    label: Some("VRAM tile viewer sampler"),
+                    ..Default::default()
+                },
+                imgui_wgpu::TextureDescriptor {
+                    label: Some("VRAM tile viewer texture".to_string()),
+                    size: wgpu::Extent3d {
+                        width: 1024,
+                        height: 1024,
+                        depth_or_array_layers: 1,
+                    },
+                    format: Some(
+                        if window.gfx.device_state.surf_config.format.describe().srgb {
+                            wgpu::TextureFormat::Rgba8UnormSrgb
+                        } else {
+                            wgpu::TextureFormat::Rgba8Unorm
+                        },
+                    ),
+                    ..Default::default()
+                },
+            );
+            window.gfx.imgui.add_texture(texture)
+        };
+        VramBanks {
+            cur_selection: Selection {
+                region: Region::ABg,
+                offset: 0,
+            },
+            tex_id,
+            color_format: ColorFormat::Bpp4,
+            tiled: true,
+            width: 256,
+            palette_source: PaletteSource::ABg,
+            palette_number: 0,
+            pixel_buffer: zeroed_box(),
+            data: VramData::default(),
+        }
+    }
+
+    fn destroy(self, window: &mut Window) {
+        window.gfx.imgui.remove_texture(self.tex_id);
+    }
+
+    fn emu_state(&self) -> Self::EmuState {
+        self.cur_selection
+    }
+
+    fn handle_emu_state_changed<E: cpu::Engine>(
+        _prev: Option<&Self::EmuState>,
+        _new: Option<&Self::EmuState>,
+        _emu: &mut Emu<E>,
+    ) {
+    }
+
+    fn prepare_frame_data<'a, E: cpu::Engine, S: FrameDataSlot<'a, Self::FrameData>>(
+        emu_state: &Self::EmuState,
+        emu: &mut Emu<E>,
+        frame_data: S,
+    ) {
+        let frame_data = frame_data.get_or_insert_with(Default::default);
+        let vram = &emu.gpu.vram;
+        frame_data.bank_control = *vram.bank_control();
+        frame_data.arm7_status = vram.arm7_status();
+        frame_data.selection = Some(*emu_state);
+        read_window(vram, emu_state, &mut frame_data.window);
+        frame_data
+            .palette
+            .copy_from_slice(unsafe { &vram.palette.as_byte_slice()[..0x800] });
+    }
+
+    fn clear_frame_data(&mut self) {
+        self.data.selection = None;
+    }
+
+    fn update_from_frame_data(&mut self, frame_data: &Self::FrameData, _window: &mut Window) {
+        self.data.bank_control = frame_data.bank_control;
+        self.data.arm7_status = frame_data.arm7_status;
+        self.data.selection = frame_data.selection;
+        self.data.window.copy_from_slice(&frame_data.window[..]);
+        self.data.palette.copy_from_slice(&frame_data.palette[..]);
+    }
+
+    fn customize_window<'ui, 'a, T: AsRef<str>>(
+        &mut self,
+        _ui: &imgui::Ui,
+        window: imgui::Window<'ui, 'a, T>,
+    ) -> imgui::Window<'ui, 'a, T> {
+        window
+    }
+
+    fn render(
+        &mut self,
+        ui: &Ui,
+        window: &mut Window,
+        _emu_running: bool,
+    ) -> Option<Self::EmuState> {
+        if self.data.selection.is_some()
+            && ui.collapsing_header("Banks", TreeNodeFlags::DEFAULT_OPEN)
+        {
+            self.render_banks(ui);
+        }
+
+        let mut new_state = None;
+        if ui.collapsing_header("Tile viewer", TreeNodeFlags::DEFAULT_OPEN) {
+            if self.render_tile_viewer_settings(ui) {
+                new_state = Some(self.cur_selection);
+            }
+            if self.data.selection == Some(self.cur_selection) {
+                self.render_tile_viewer(ui, window);
+            }
+        }
+
+        new_state
+    }
+}
+
+impl InstanceableView for VramBanks {
+    fn finish_preparing_frame_data<E: cpu::Engine>(_emu: &mut Emu<E>) {}
+}
@@ -1,19 +1,27 @@
 #[cfg(feature = "gdb-server")]
 mod gdb_server;
+pub mod mic;
+mod movie;
 mod renderer_3d;
+mod rewind;
 mod rtc;
+#[cfg(feature = "debug-views")]
+pub mod scene_export;
 
 #[cfg(feature = "debug-views")]
 use super::debug_views;
 use super::{
-    audio, config::CommonLaunchConfig, game_db::SaveType, input, triple_buffer, FrameData,
+    audio, config::CommonLaunchConfig, game_db::SaveType, input, savestates, triple_buffer,
+    FrameData,
 };
+use chrono::{Local, Timelike};
 use dust_core::{
     audio::DummyBackend as DummyAudioBackend,
     cpu::interpreter::Interpreter,
     ds_slot::{self, rom::Rom as DsSlotRom, spi::Spi as DsSlotSpi},
-    emu::RunOutput,
+    emu::{Emu, RunOutput},
     flash::Flash,
+    mic::DummyBackend as DummyMicBackend,
     spi::firmware,
     utils::BoxedByteSlice,
     SaveContents,
@@ -29,7 +37,7 @@ use std::{
     io::{self, Read},
     path::PathBuf,
     sync::{
-        atomic::{AtomicBool, Ordering},
+        atomic::{AtomicBool, AtomicU64, Ordering},
         Arc,
     },
     time::{Duration, Instant},
@@ -40,6 +48,9 @@ pub struct SharedState {
     pub limit_framerate: AtomicBool,
     pub autosave_interval: RwLock<Duration>,
     pub stopped: AtomicBool,
+    pub rewinding: AtomicBool,
+    pub movie_active: AtomicBool,
+    pub mic_blowing: AtomicBool,
     #[cfg(feature = "gdb-server")]
     pub gdb_server_active: AtomicBool,
 }
@@ -56,6 +67,44 @@ pub enum Message {
     #[cfg(feature = "debug-views")]
     DebugViews(debug_views::Message),
     Reset,
+    SaveState(PathBuf),
+    LoadState(PathBuf),
+    StartMovieRecording {
+        path: PathBuf,
+        from_power_on: bool,
+    },
+    StartMoviePlayback(PathBuf),
+    StopMovie,
+    #[cfg(feature = "debug-views")]
+    CaptureGxFrame(PathBuf),
+    #[cfg(feature = "debug-views")]
+    ExportScene(PathBuf),
+}
+
+enum MovieState {
+    Recording {
+        movie: movie::Movie,
+        path: PathBuf,
+    },
+    Playing {
+        movie: movie::Movie,
+        next_event: usize,
+        // The movie's start replaces the save contents, so the user's own are kept aside to be
+        // restored once playback ends; the save file isn't written to in the meantime
+        user_save: Vec<u8>,
+    },
+}
+
+fn apply_input_changes(emu: &mut Emu<Interpreter>, changes: input::Changes) {
+    emu.press_keys(changes.pressed);
+    emu.release_keys(changes.released);
+    if let Some(new_touch_pos) = changes.touch_pos {
+        if let Some(touch_pos) = new_touch_pos {
+            emu.set_touch_pos(touch_pos);
+        } else {
+            emu.end_touch();
+        }
+    }
 }
 
 pub struct DsSlot {
@@ -82,15 +131,6 @@ pub(super) fn main(
     let mut sync_to_audio = config.sync_to_audio.value;
 
     let (ds_slot_rom, ds_slot_spi) = if let Some(ds_slot) = ds_slot {
-        let rom = ds_slot::rom::normal::Normal::new(
-            ds_slot.rom,
-            config.sys_files.arm7_bios.as_deref(),
-            #[cfg(feature = "log")]
-            logger.new(slog::o!("ds_rom" => "normal")),
-        )
-        .unwrap()
-        .into();
-
         let save_contents = cur_save_path
             .as_deref()
             .and_then(|path| match File::open(path) {
@@ -173,12 +213,26 @@ pub(super) fn main(
             })
         };
 
-        let spi = if save_type == SaveType::None {
-            ds_slot::spi::Empty::new(
+        let normal_rom = |rom| -> DsSlotRom {
+            ds_slot::rom::normal::Normal::new(
+                rom,
+                config.sys_files.arm7_bios.as_deref(),
                 #[cfg(feature = "log")]
-                logger.new(slog::o!("ds_spi" => "empty")),
+                logger.new(slog::o!("ds_rom" => "normal")),
             )
+            .unwrap()
             .into()
+        };
+
+        if save_type == SaveType::None {
+            (
+                normal_rom(ds_slot.rom),
+                ds_slot::spi::Empty::new(
+                    #[cfg(feature = "log")]
+                    logger.new(slog::o!("ds_spi" => "empty")),
+                )
+                .into(),
+            )
         } else {
             let expected_len = save_type.expected_len().unwrap();
             let save_contents = match save_contents {
@@ -196,15 +250,19 @@ pub(super) fn main(
             };
             match save_type {
                 SaveType::None => unreachable!(),
-                SaveType::Eeprom4k => ds_slot::spi::eeprom_4k::Eeprom4k::new(
-                    save_contents,
-                    None,
-                    #[cfg(feature = "log")]
-                    logger.new(slog::o!("ds_spi" => "eeprom_4k")),
-                )
-                .expect("Couldn't create 4 Kib EEPROM DS slot SPI device")
-                .into(),
-                SaveType::EepromFram64k | SaveType::EepromFram512k | SaveType::EepromFram1m => {
+                SaveType::Eeprom4k => (
+                    normal_rom(ds_slot.rom),
+                    ds_slot::spi::eeprom_4k::Eeprom4k::new(
+                        save_contents,
+                        None,
+                        #[cfg(feature = "log")]
+                        logger.new(slog::o!("ds_spi" => "eeprom_4k")),
+                    )
+                    .expect("Couldn't create 4 Kib EEPROM DS slot SPI device")
+                    .into(),
+                ),
+                SaveType::EepromFram64k | SaveType::EepromFram512k | SaveType::EepromFram1m => (
+                    normal_rom(ds_slot.rom),
                     ds_slot::spi::eeprom_fram::EepromFram::new(
                         save_contents,
                         None,
@@ -212,34 +270,41 @@ pub(super) fn main(
                         logger.new(slog::o!("ds_spi" => "eeprom_fram")),
                     )
                     .expect("Couldn't create EEPROM/FRAM DS slot SPI device")
-                    .into()
-                }
-                SaveType::Flash2m | SaveType::Flash4m | SaveType::Flash8m => {
+                    .into(),
+                ),
+                SaveType::Flash2m | SaveType::Flash4m | SaveType::Flash8m => (
+                    normal_rom(ds_slot.rom),
                     ds_slot::spi::flash::Flash::new(
                         save_contents,
                         [0; 20],
                         ds_slot.has_ir,
                         #[cfg(feature = "log")]
-                        logger.new(
-                            slog::o!("ds_spi" => if ds_slot.has_ir { "flash" } else { "flash_ir" }),
-                        ),
+                        logger.new(slog::o!(
+                            "ds_spi" => if ds_slot.has_ir { "flash" } else { "flash_ir" }
+                        )),
                     )
                     .expect("Couldn't create FLASH DS slot SPI device")
-                    .into()
-                }
-                SaveType::Nand64m | SaveType::Nand128m | SaveType::Nand256m => {
-                    #[cfg(feature = "log")]
-                    slog::error!(logger, "TODO: NAND saves");
+                    .into(),
+                ),
+                SaveType::Nand64m | SaveType::Nand128m | SaveType::Nand256m => (
+                    // NAND cartridges store saves in the ROM chip itself and have no SPI device
+                    ds_slot::rom::nand::Nand::new(
+                        ds_slot.rom,
+                        save_contents,
+                        config.sys_files.arm7_bios.as_deref(),
+                        #[cfg(feature = "log")]
+                        logger.new(slog::o!("ds_rom" => "nand")),
+                    )
+                    .expect("Couldn't create NAND DS slot ROM device")
+                    .into(),
                     ds_slot::spi::Empty::new(
                         #[cfg(feature = "log")]
-                        logger.new(slog::o!("ds_spi" => "nand_todo")),
+                        logger.new(slog::o!("ds_spi" => "nand")),
                     )
-                    .into()
-                }
+                    .into(),
+                ),
             }
-        };
-
-        (rom, spi)
+        }
     } else {
         (
             ds_slot::rom::Empty::new(
@@ -255,6 +320,11 @@ pub(super) fn main(
         )
     };
 
+    let renderer =
+        renderer_3d::Renderer::new(config.renderer_3d_thread_count, config.renderer_3d_scale);
+    // The renderer is reused across resets, so its scaled frame stays valid for the whole session
+    let scaled_frame_3d = (config.renderer_3d_scale > 1).then(|| renderer.scaled_frame());
+
     let mut emu_builder = dust_core::emu::Builder::new(
         Flash::new(
             SaveContents::Existing(
@@ -274,8 +344,15 @@ pub(super) fn main(
             Some(data) => Box::new(audio::Sender::new(data, sync_to_audio)),
             None => Box::new(DummyAudioBackend),
         },
+        Box::new(mic::Backend::new(
+            config.mic_source,
+            config.mic_wav_path.as_deref(),
+            Arc::clone(&shared_state),
+            #[cfg(feature = "log")]
+            &logger,
+        )),
         Box::new(rtc::Backend::new(config.rtc_time_offset_seconds.value)),
-        Box::new(renderer_3d::Renderer::new()),
+        Box::new(renderer),
         #[cfg(feature = "log")]
         logger.clone(),
     );
@@ -305,20 +382,79 @@ pub(super) fn main(
 
     let mut last_save_flush_time = last_frame_time;
 
+    let mut rewind = rewind::Rewind::new(
+        config.rewind_interval_frames,
+        (config.rewind_buffer_size_mib as usize) << 20,
+    );
+
     macro_rules! save {
         ($save_path: expr) => {
-            if emu.ds_slot.spi.contents_dirty()
+            if emu.ds_slot.save_contents_dirty()
                 && $save_path
                     .parent()
                     .map(|parent| fs::create_dir_all(parent).is_ok())
                     .unwrap_or(true)
-                && fs::write($save_path, &emu.ds_slot.spi.contents()[..]).is_ok()
+                && fs::write($save_path, &emu.ds_slot.save_contents()[..]).is_ok()
             {
-                emu.ds_slot.spi.mark_contents_flushed();
+                emu.ds_slot.mark_save_contents_flushed();
             }
         };
     }
 
+    // Only advanced while a movie is active, as the emulated RTC is based on it
+    let movie_frames = Arc::new(AtomicU64::new(0));
+    let mut movie: Option<MovieState> = None;
+    #[cfg(feature = "debug-views")]
+    let mut gx_capture_path: Option<PathBuf> = None;
+    #[cfg(feature = "debug-views")]
+    let mut scene_export_path: Option<PathBuf> = None;
+
+    macro_rules! stop_movie {
+        () => {
+            if let Some(prev_movie) = movie.take() {
+                match prev_movie {
+                    MovieState::Recording {
+                        movie: mut recorded_movie,
+                        path,
+                    } => {
+                        recorded_movie.len_frames = movie_frames.load(Ordering::Relaxed);
+                        if let Err(_err) = recorded_movie.write(&path) {
+                            #[cfg(feature = "log")]
+                            slog::error!(logger, "Couldn't write movie: {}", _err);
+                        }
+                    }
+                    MovieState::Playing { user_save, .. } => {
+                        emu.ds_slot.save_contents_mut()[..].copy_from_slice(&user_save);
+                        emu.ds_slot.mark_save_contents_flushed();
+                    }
+                }
+                emu.rtc.backend = Box::new(rtc::Backend::new(config.rtc_time_offset_seconds.value));
+                emu.spi.tsc.mic.set_backend(Box::new(mic::Backend::new(
+                    config.mic_source,
+                    config.mic_wav_path.as_deref(),
+                    Arc::clone(&shared_state),
+                    #[cfg(feature = "log")]
+                    &logger,
+                )));
+                shared_state.movie_active.store(false, Ordering::Relaxed);
+            }
+        };
+    }
+
+    macro_rules! start_movie {
+        ($movie: expr, $rtc_start: expr) => {
+            movie_frames.store(0, Ordering::Relaxed);
+            emu.rtc.backend = Box::new(rtc::Backend::new_emulated(
+                $rtc_start,
+                Arc::clone(&movie_frames),
+            ));
+            // Mic input isn't stored in movies, so keep it silent to make playback deterministic
+            emu.spi.tsc.mic.set_backend(Box::new(DummyMicBackend));
+            movie = Some($movie);
+            shared_state.movie_active.store(true, Ordering::Relaxed);
+        };
+    }
+
     #[cfg(feature = "debug-views")]
     let mut debug_views = debug_views::EmuState::new();
 
@@ -334,17 +470,21 @@ pub(super) fn main(
 
         for message in message_rx.try_iter() {
             match message {
-                Message::UpdateInput(changes) => {
-                    emu.press_keys(changes.pressed);
-                    emu.release_keys(changes.released);
-                    if let Some(new_touch_pos) = changes.touch_pos {
-                        if let Some(touch_pos) = new_touch_pos {
-                            emu.set_touch_pos(touch_pos);
-                        } else {
-                            emu.end_touch();
-                        }
+                Message::UpdateInput(changes) => match &mut movie {
+                    // Inputs come exclusively from the movie during playback
+                    Some(MovieState::Playing { .. }) => {}
+                    Some(MovieState::Recording {
+                        movie: recorded_movie,
+                        ..
+                    }) => {
+                        recorded_movie.events.push(movie::Event {
+                            frame: movie_frames.load(Ordering::Relaxed),
+                            changes,
+                        });
+                        apply_input_changes(&mut emu, changes);
                     }
-                }
+                    None => apply_input_changes(&mut emu, changes),
+                },
 
                 Message::UpdateSavePath(new_path) => {
                     if let Some(prev_path) = cur_save_path {
@@ -384,8 +524,132 @@ pub(super) fn main(
                 }
 
                 Message::Reset => {
+                    stop_movie!();
                     reset_triggered = true;
                 }
+
+                Message::SaveState(path) => {
+                    let thumbnail = savestates::thumbnail(&emu.gpu.framebuffer);
+                    let state = savestates::store(&mut emu);
+                    if let Err(_err) = savestates::write(&path, &thumbnail, &state) {
+                        #[cfg(feature = "log")]
+                        slog::error!(logger, "Couldn't write savestate: {}", _err);
+                    }
+                }
+
+                Message::LoadState(path) => {
+                    if movie.is_some() {
+                        #[cfg(feature = "log")]
+                        slog::warn!(logger, "Can't load savestates while a movie is active");
+                        continue;
+                    }
+                    if let Err(_err) = savestates::read_state(&path)
+                        .and_then(|state| savestates::load(&mut emu, &state))
+                    {
+                        #[cfg(feature = "log")]
+                        slog::error!(logger, "Couldn't load savestate: {}", _err);
+                    }
+                }
+
+                Message::StartMovieRecording {
+                    path,
+                    from_power_on,
+                } => {
+                    stop_movie!();
+                    let now = Local::now().naive_local()
+                        + chrono::Duration::seconds(config.rtc_time_offset_seconds.value);
+                    let rtc_start = now.with_nanosecond(0).unwrap_or(now);
+                    let start = if from_power_on {
+                        reset_triggered = true;
+                        movie::Start::PowerOn {
+                            save: emu.ds_slot.save_contents()[..].to_vec(),
+                            firmware: emu.spi.firmware.contents()[..].to_vec(),
+                        }
+                    } else {
+                        movie::Start::Savestate(emu.save_state(true))
+                    };
+                    start_movie!(
+                        MovieState::Recording {
+                            movie: movie::Movie::new(rtc_start, start),
+                            path,
+                        },
+                        rtc_start
+                    );
+                }
+
+                Message::StartMoviePlayback(path) => {
+                    let new_movie = match movie::Movie::read(&path) {
+                        Ok(movie) => movie,
+                        Err(_err) => {
+                            #[cfg(feature = "log")]
+                            slog::error!(logger, "Couldn't read movie: {}", _err);
+                            continue;
+                        }
+                    };
+                    stop_movie!();
+                    // Make sure the user's own save is on disk, as it won't be written to again
+                    // until playback ends
+                    if let Some(save_path) = &cur_save_path {
+                        save!(save_path);
+                    }
+                    let user_save = emu.ds_slot.save_contents()[..].to_vec();
+                    match &new_movie.start {
+                        movie::Start::PowerOn { save, firmware } => {
+                            if save.len() != emu.ds_slot.save_contents().len()
+                                || firmware.len() != emu.spi.firmware.contents().len()
+                            {
+                                #[cfg(feature = "log")]
+                                slog::error!(
+                                    logger,
+                                    "Movie save or firmware size doesn't match the current one"
+                                );
+                                continue;
+                            }
+                            emu.ds_slot.save_contents_mut()[..].copy_from_slice(save);
+                            emu.ds_slot.mark_save_contents_flushed();
+                            emu.spi.firmware.contents_mut()[..].copy_from_slice(firmware);
+                            reset_triggered = true;
+                        }
+                        movie::Start::Savestate(state) => {
+                            if let Err(_err) = emu.load_state(state) {
+                                #[cfg(feature = "log")]
+                                slog::error!(logger, "Couldn't load movie savestate: {}", _err);
+                                continue;
+                            }
+                        }
+                    }
+                    let rtc_start = new_movie.rtc_start;
+                    start_movie!(
+                        MovieState::Playing {
+                            movie: new_movie,
+                            next_event: 0,
+                            user_save,
+                        },
+                        rtc_start
+                    );
+                }
+
+                Message::StopMovie => {
+                    stop_movie!();
+                }
+
+                #[cfg(feature = "debug-views")]
+                Message::CaptureGxFrame(path) => {
+                    emu.gpu.engine_3d.request_capture();
+                    gx_capture_path = Some(path);
+                }
+
+                #[cfg(feature = "debug-views")]
+                Message::ExportScene(path) => {
+                    emu.gpu.engine_3d.request_frame_3d_capture();
+                    scene_export_path = Some(path);
+                }
+            }
+
+            // Leave any remaining messages for after the reset, so that they apply to the new
+            // emulator instance (in particular, inputs recorded at the start of a power-on movie)
+            if reset_triggered {
+                break;
             }
         }
 
@@ -419,6 +683,12 @@ pub(super) fn main(
         }
 
         if reset_triggered {
+            // Any pending capture request is lost along with the old 3D engine state
+            #[cfg(feature = "debug-views")]
+            {
+                gx_capture_path = None;
+                scene_export_path = None;
+            }
             #[cfg(feature = "xq-audio")]
             let audio_custom_sample_rate = emu.audio.custom_sample_rate();
             #[cfg(feature = "xq-audio")]
@@ -429,6 +699,7 @@ pub(super) fn main(
                 match emu.ds_slot.rom {
                     DsSlotRom::Empty(device) => DsSlotRom::Empty(device.reset()),
                     DsSlotRom::Normal(device) => DsSlotRom::Normal(device.reset()),
+                    DsSlotRom::Nand(device) => DsSlotRom::Nand(device.reset()),
                 },
                 match emu.ds_slot.spi {
                     DsSlotSpi::Empty(device) => DsSlotSpi::Empty(device.reset()),
@@ -437,6 +708,7 @@ pub(super) fn main(
                     DsSlotSpi::Flash(device) => DsSlotSpi::Flash(device.reset()),
                 },
                 emu.audio.backend,
+                emu.spi.tsc.mic.backend,
                 emu.rtc.backend,
                 emu.gpu.engine_3d.renderer,
                 #[cfg(feature = "log")]
@@ -457,6 +729,7 @@ pub(super) fn main(
             }
 
             emu = emu_builder.build(Interpreter).unwrap();
+            rewind.clear();
             #[cfg(feature = "gdb-server")]
             if let Some(server) = &mut gdb_server {
                 server.attach(&mut emu);
@@ -468,6 +741,35 @@ pub(super) fn main(
         let frame = frame_tx.start();
 
         if playing {
+            let rewinding = rewind.enabled()
+                && movie.is_none()
+                && shared_state.rewinding.load(Ordering::Relaxed);
+            if rewinding {
+                if let Some(state) = rewind.pop() {
+                    if let Err(_err) = emu.load_state(state) {
+                        #[cfg(feature = "log")]
+                        slog::error!(logger, "Couldn't load rewind snapshot: {}", _err);
+                        rewind.clear();
+                    }
+                }
+            }
+
+            if let Some(MovieState::Playing {
+                movie: played_movie,
+                next_event,
+                ..
+            }) = &mut movie
+            {
+                let cur_frame = movie_frames.load(Ordering::Relaxed);
+                while let Some(event) = played_movie.events.get(*next_event) {
+                    if event.frame > cur_frame {
+                        break;
+                    }
+                    apply_input_changes(&mut emu, event.changes);
+                    *next_event += 1;
+                }
+            }
+
             #[cfg(feature = "gdb-server")]
             let mut run_forever = 0;
             #[cfg(feature = "gdb-server")]
@@ -480,7 +782,25 @@ pub(super) fn main(
                 #[cfg(feature = "gdb-server")]
                 cycles,
             ) {
-                RunOutput::FrameFinished => {}
+                RunOutput::FrameFinished => {
+                    // While rewinding, frames are only run to render the restored snapshots
+                    if !rewinding && rewind.frame_finished() {
+                        // Left uncompressed, as snapshots are diffed against each other
+                        rewind.push(emu.save_state(false));
+                    }
+                    if let Some(cur_movie) = &movie {
+                        let frames = movie_frames.fetch_add(1, Ordering::Relaxed) + 1;
+                        if let MovieState::Playing {
+                            movie: played_movie,
+                            ..
+                        } = cur_movie
+                        {
+                            if frames >= played_movie.len_frames {
+                                stop_movie!();
+                            }
+                        }
+                    }
+                }
                 RunOutput::Shutdown => {
                     shared_state.stopped.store(true, Ordering::Relaxed);
                     #[cfg(feature = "gdb-server")]
@@ -496,7 +816,37 @@ pub(super) fn main(
                 }
             }
         }
+        #[cfg(feature = "debug-views")]
+        if gx_capture_path.is_some() {
+            if let Some(capture) = emu.gpu.engine_3d.take_capture() {
+                let path = gx_capture_path.take().unwrap();
+                if let Err(_err) = fs::write(path, capture.to_bytes()) {
+                    #[cfg(feature = "log")]
+                    slog::error!(logger, "Couldn't write GX capture: {}", _err);
+                }
+            }
+        }
+        #[cfg(feature = "debug-views")]
+        if scene_export_path.is_some() {
+            if let Some(capture) = emu.gpu.engine_3d.take_frame_3d_capture() {
+                let path = scene_export_path.take().unwrap();
+                if let Err(_err) = scene_export::export(&capture, &path) {
+                    #[cfg(feature = "log")]
+                    slog::error!(logger, "Couldn't export 3D scene: {}", _err);
+                }
+            }
+        }
+
         frame.fb.0.copy_from_slice(&emu.gpu.framebuffer.0);
+        if let Some(scaled_frame_3d) = &scaled_frame_3d {
+            renderer_3d::compose_scaled_framebuffer(
+                &emu.gpu.framebuffer,
+                &emu.gpu.framebuffer_3d_mask,
+                scaled_frame_3d,
+                &mut frame.scaled_fb,
+            );
+            frame.fb_scale = scaled_frame_3d.scale;
+        }
 
         #[cfg(feature = "debug-views")]
         debug_views.prepare_frame_data(&mut emu, &mut frame.debug);
@@ -513,11 +863,14 @@ pub(super) fn main(
 
         frame_tx.finish();
 
-        if let Some(save_path) = &cur_save_path {
-            let now = Instant::now();
-            if now - last_save_flush_time >= *shared_state.autosave_interval.read() {
-                last_save_flush_time = now;
-                save!(save_path);
+        // During playback the save contents come from the movie, see `MovieState::Playing`
+        if !matches!(movie, Some(MovieState::Playing { .. })) {
+            if let Some(save_path) = &cur_save_path {
+                let now = Instant::now();
+                if now - last_save_flush_time >= *shared_state.autosave_interval.read() {
+                    last_save_flush_time = now;
+                    save!(save_path);
+                }
             }
         }
 
@@ -540,5 +893,7 @@ pub(super) fn main(
         }
     }
 
+    stop_movie!();
+
     frame_tx
 }
@@ -0,0 +1,290 @@
+use super::SharedState;
+use cpal::{
+    default_host,
+    platform::Stream,
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    Sample, SampleFormat,
+};
+use dust_core::mic::{self, SAMPLE_CHUNK_SIZE, SAMPLE_RATE};
+use parking_lot::Mutex;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::VecDeque,
+    fmt, fs, io,
+    path::Path,
+    sync::{atomic::Ordering, Arc},
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum Source {
+    Disabled,
+    Device,
+    WavFile,
+}
+
+// Allow for ~62.5 ms of latency before dropping the oldest captured samples
+const CAPTURE_BUFFER_CAPACITY: usize = SAMPLE_CHUNK_SIZE * 4;
+
+struct CaptureData {
+    samples: VecDeque<i16>,
+    fract: f64,
+}
+
+impl CaptureData {
+    fn push<T: Sample>(&mut self, data: &[T], channels: usize, sample_rate_ratio: f64) {
+        for frame in data.chunks_exact(channels) {
+            let sample =
+                (frame.iter().map(|s| s.to_i16() as i32).sum::<i32>() / channels as i32) as i16;
+            self.fract += sample_rate_ratio;
+            while self.fract >= 1.0 {
+                self.fract -= 1.0;
+                if self.samples.len() >= CAPTURE_BUFFER_CAPACITY {
+                    self.samples.pop_front();
+                }
+                self.samples.push_back(sample);
+            }
+        }
+    }
+}
+
+struct Capture {
+    _stream: Stream,
+    data: Arc<Mutex<CaptureData>>,
+    last_sample: i16,
+}
+
+impl Capture {
+    fn new() -> Option<Self> {
+        let input_device = default_host().default_input_device()?;
+        let supported_input_config = input_device.default_input_config().ok()?;
+        let channels = supported_input_config.channels() as usize;
+        let sample_rate_ratio = SAMPLE_RATE as f64 / supported_input_config.sample_rate().0 as f64;
+
+        let data = Arc::new(Mutex::new(CaptureData {
+            samples: VecDeque::with_capacity(CAPTURE_BUFFER_CAPACITY),
+            fract: 0.0,
+        }));
+
+        let err_callback = |err| panic!("Error in default audio input device stream: {}", err);
+        let stream = {
+            let data = Arc::clone(&data);
+            match supported_input_config.sample_format() {
+                SampleFormat::U16 => input_device.build_input_stream(
+                    &supported_input_config.config(),
+                    move |samples: &[u16], _| {
+                        data.lock().push(samples, channels, sample_rate_ratio)
+                    },
+                    err_callback,
+                ),
+                SampleFormat::I16 => input_device.build_input_stream(
+                    &supported_input_config.config(),
+                    move |samples: &[i16], _| {
+                        data.lock().push(samples, channels, sample_rate_ratio)
+                    },
+                    err_callback,
+                ),
+                SampleFormat::F32 => input_device.build_input_stream(
+                    &supported_input_config.config(),
+                    move |samples: &[f32], _| {
+                        data.lock().push(samples, channels, sample_rate_ratio)
+                    },
+                    err_callback,
+                ),
+            }
+        }
+        .ok()?;
+        stream.play().ok()?;
+
+        Some(Capture {
+            _stream: stream,
+            data,
+            last_sample: 0,
+        })
+    }
+
+    fn fill(&mut self, samples: &mut [i16]) {
+        let mut data = self.data.lock();
+        for sample in samples {
+            // Repeat the last sample on underruns instead of introducing sudden jumps to silence
+            if let Some(new_sample) = data.samples.pop_front() {
+                self.last_sample = new_sample;
+            }
+            *sample = self.last_sample;
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum WavError {
+    Io(io::Error),
+    InvalidFormat,
+    UnsupportedFormat,
+}
+
+impl From<io::Error> for WavError {
+    #[inline]
+    fn from(err: io::Error) -> Self {
+        WavError::Io(err)
+    }
+}
+
+impl fmt::Display for WavError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WavError::Io(err) => write!(f, "I/O error: {}", err),
+            WavError::InvalidFormat => f.write_str("not a valid WAV file"),
+            WavError::UnsupportedFormat => {
+                f.write_str("unsupported WAV format (only 8-bit and 16-bit PCM are supported)")
+            }
+        }
+    }
+}
+
+/// Reads a PCM WAV file, mixing it down to mono and resampling it to the mic sample rate.
+fn read_wav(path: &Path) -> Result<Vec<i16>, WavError> {
+    let contents = fs::read(path)?;
+    if contents.len() < 12 || &contents[..4] != b"RIFF" || &contents[8..12] != b"WAVE" {
+        return Err(WavError::InvalidFormat);
+    }
+
+    let mut format = None;
+    let mut data = None;
+    let mut chunks = &contents[12..];
+    while chunks.len() >= 8 {
+        let id = &chunks[..4];
+        let len = u32::from_le_bytes(chunks[4..8].try_into().unwrap()) as usize;
+        let chunk = chunks.get(8..8 + len).ok_or(WavError::InvalidFormat)?;
+        match id {
+            b"fmt " if len >= 16 => {
+                let read_u16 = |i: usize| u16::from_le_bytes([chunk[i], chunk[i + 1]]);
+                format = Some((
+                    read_u16(0),
+                    read_u16(2) as usize,
+                    u32::from_le_bytes(chunk[4..8].try_into().unwrap()),
+                    read_u16(14),
+                ));
+            }
+            b"data" => data = Some(chunk),
+            _ => {}
+        }
+        // Chunks are padded to an even length
+        chunks = chunks.get(8 + len + (len & 1)..).unwrap_or_default();
+    }
+
+    let ((format_tag, channels, sample_rate, bits_per_sample), data) =
+        format.zip(data).ok_or(WavError::InvalidFormat)?;
+    if format_tag != 1 || channels == 0 || sample_rate == 0 {
+        return Err(WavError::UnsupportedFormat);
+    }
+    let input: Vec<i16> = match bits_per_sample {
+        8 => data
+            .chunks_exact(channels)
+            .map(|frame| {
+                (frame.iter().map(|&s| (s as i32 - 0x80) << 8).sum::<i32>() / channels as i32)
+                    as i16
+            })
+            .collect(),
+        16 => data
+            .chunks_exact(channels * 2)
+            .map(|frame| {
+                (frame
+                    .array_chunks::<2>()
+                    .map(|&s| i16::from_le_bytes(s) as i32)
+                    .sum::<i32>()
+                    / channels as i32) as i16
+            })
+            .collect(),
+        _ => return Err(WavError::UnsupportedFormat),
+    };
+
+    let len = (input.len() as u64 * SAMPLE_RATE as u64 / sample_rate as u64) as usize;
+    Ok((0..len)
+        .map(|i| input[(i as u64 * sample_rate as u64 / SAMPLE_RATE as u64) as usize])
+        .collect())
+}
+
+enum Input {
+    Silence,
+    Device(Capture),
+    Wav { samples: Vec<i16>, pos: usize },
+}
+
+pub struct Backend {
+    input: Input,
+    shared_state: Arc<SharedState>,
+    noise_state: u32,
+}
+
+impl Backend {
+    pub fn new(
+        source: Source,
+        wav_path: Option<&Path>,
+        shared_state: Arc<SharedState>,
+        #[cfg(feature = "log")] logger: &slog::Logger,
+    ) -> Self {
+        let input = match source {
+            Source::Disabled => Input::Silence,
+            Source::Device => match Capture::new() {
+                Some(capture) => Input::Device(capture),
+                None => {
+                    #[cfg(feature = "log")]
+                    slog::error!(logger, "Couldn't open the default audio input device");
+                    Input::Silence
+                }
+            },
+            Source::WavFile => match wav_path.map(read_wav) {
+                Some(Ok(samples)) if !samples.is_empty() => Input::Wav { samples, pos: 0 },
+                Some(Ok(_)) => Input::Silence,
+                Some(Err(_err)) => {
+                    #[cfg(feature = "log")]
+                    slog::error!(logger, "Couldn't read mic input WAV file: {}", _err);
+                    Input::Silence
+                }
+                None => {
+                    #[cfg(feature = "log")]
+                    slog::error!(logger, "No mic input WAV file specified");
+                    Input::Silence
+                }
+            },
+        };
+        Backend {
+            input,
+            shared_state,
+            noise_state: 0x1234_5678,
+        }
+    }
+
+    fn fill_noise(&mut self, samples: &mut [i16]) {
+        // Loud white noise is enough for most games to detect blowing into the mic
+        for sample in samples {
+            self.noise_state ^= self.noise_state << 13;
+            self.noise_state ^= self.noise_state >> 17;
+            self.noise_state ^= self.noise_state << 5;
+            *sample = (self.noise_state >> 16) as i16;
+        }
+    }
+}
+
+impl mic::Backend for Backend {
+    fn fill_sample_chunk(&mut self, samples: &mut [i16; SAMPLE_CHUNK_SIZE]) {
+        if self.shared_state.mic_blowing.load(Ordering::Relaxed) {
+            self.fill_noise(samples);
+            return;
+        }
+        match &mut self.input {
+            Input::Silence => samples.fill(0),
+            Input::Device(capture) => capture.fill(samples),
+            Input::Wav {
+                samples: wav_samples,
+                pos,
+            } => {
+                // Loop the file
+                for sample in samples {
+                    *sample = wav_samples[*pos];
+                    *pos = (*pos + 1) % wav_samples.len();
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,223 @@
+//! Input movies, storing every input change along with the frame it was applied on. Mic input
+//! isn't stored, and is kept silent instead while a movie is being recorded or played back.
+//!
+//! File layout (all values little-endian):
+//! - magic (`b"DUSTMOVI"`)
+//! - format version (`u32`)
+//! - RTC time at the start of the movie, as seconds since the Unix epoch in local time (`i64`)
+//! - length in frames (`u64`)
+//! - start kind (`u8`):
+//!   - 0 for power-on, followed by the save contents' length (`u64`) and contents, then the
+//!     firmware's length (`u64`) and contents, as they were when the movie started
+//!   - 1 for an embedded savestate, followed by its length (`u64`) and contents
+//! - event count (`u64`), followed by the events, each consisting of:
+//!   - frame number (`u64`)
+//!   - pressed keys (`u32`)
+//!   - released keys (`u32`)
+//!   - touch change (`u8`): 0 for none, 1 for the end of a touch, 2 for a new touch position,
+//!     followed by its coordinates (2x `u16`)
+
+use super::super::input;
+use chrono::NaiveDateTime;
+use dust_core::emu::input::Keys;
+use std::{fmt, fs, io, path::Path};
+
+pub const MAGIC: [u8; 8] = *b"DUSTMOVI";
+pub const VERSION: u32 = 1;
+
+pub enum Start {
+    PowerOn { save: Vec<u8>, firmware: Vec<u8> },
+    Savestate(Vec<u8>),
+}
+
+pub struct Event {
+    pub frame: u64,
+    pub changes: input::Changes,
+}
+
+pub struct Movie {
+    pub rtc_start: NaiveDateTime,
+    pub len_frames: u64,
+    pub start: Start,
+    pub events: Vec<Event>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    InvalidMagic,
+    UnsupportedVersion(u32),
+    Truncated,
+    InvalidData,
+}
+
+impl From<io::Error> for Error {
+    #[inline]
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::InvalidMagic => f.write_str("not a movie file"),
+            Error::UnsupportedVersion(version) => {
+                write!(f, "unsupported movie format version {}", version)
+            }
+            Error::Truncated => f.write_str("truncated movie file"),
+            Error::InvalidData => f.write_str("invalid movie data"),
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.bytes.len() < len {
+            return Err(Error::Truncated);
+        }
+        let (result, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+        Ok(result)
+    }
+
+    fn array<const LEN: usize>(&mut self) -> Result<[u8; LEN], Error> {
+        Ok(self.bytes(LEN)?.try_into().unwrap())
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        Ok(u16::from_le_bytes(self.array()?))
+    }
+
+    fn u32(&mut self) -> Result<u32, Error> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    fn u64(&mut self) -> Result<u64, Error> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+
+    fn len(&mut self) -> Result<usize, Error> {
+        usize::try_from(self.u64()?).map_err(|_| Error::InvalidData)
+    }
+}
+
+impl Movie {
+    pub fn new(rtc_start: NaiveDateTime, start: Start) -> Self {
+        Movie {
+            rtc_start,
+            len_frames: 0,
+            start,
+            events: Vec::new(),
+        }
+    }
+
+    pub fn write(&self, path: &Path) -> io::Result<()> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&self.rtc_start.timestamp().to_le_bytes());
+        bytes.extend_from_slice(&self.len_frames.to_le_bytes());
+        match &self.start {
+            Start::PowerOn { save, firmware } => {
+                bytes.push(0);
+                bytes.extend_from_slice(&(save.len() as u64).to_le_bytes());
+                bytes.extend_from_slice(save);
+                bytes.extend_from_slice(&(firmware.len() as u64).to_le_bytes());
+                bytes.extend_from_slice(firmware);
+            }
+            Start::Savestate(state) => {
+                bytes.push(1);
+                bytes.extend_from_slice(&(state.len() as u64).to_le_bytes());
+                bytes.extend_from_slice(state);
+            }
+        }
+        bytes.extend_from_slice(&(self.events.len() as u64).to_le_bytes());
+        for event in &self.events {
+            bytes.extend_from_slice(&event.frame.to_le_bytes());
+            bytes.extend_from_slice(&event.changes.pressed.bits().to_le_bytes());
+            bytes.extend_from_slice(&event.changes.released.bits().to_le_bytes());
+            match event.changes.touch_pos {
+                None => bytes.push(0),
+                Some(None) => bytes.push(1),
+                Some(Some([x, y])) => {
+                    bytes.push(2);
+                    bytes.extend_from_slice(&x.to_le_bytes());
+                    bytes.extend_from_slice(&y.to_le_bytes());
+                }
+            }
+        }
+        fs::write(path, bytes)
+    }
+
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let contents = fs::read(path)?;
+        let mut reader = Reader { bytes: &contents };
+
+        if reader.array::<8>().ok() != Some(MAGIC) {
+            return Err(Error::InvalidMagic);
+        }
+        let version = reader.u32()?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+        let rtc_start =
+            NaiveDateTime::from_timestamp_opt(reader.u64()? as i64, 0).ok_or(Error::InvalidData)?;
+        let len_frames = reader.u64()?;
+        let start = match reader.u8()? {
+            0 => {
+                let save_len = reader.len()?;
+                let save = reader.bytes(save_len)?.to_vec();
+                let firmware_len = reader.len()?;
+                let firmware = reader.bytes(firmware_len)?.to_vec();
+                Start::PowerOn { save, firmware }
+            }
+            1 => {
+                let len = reader.len()?;
+                Start::Savestate(reader.bytes(len)?.to_vec())
+            }
+            _ => return Err(Error::InvalidData),
+        };
+        let events_len = reader.len()?;
+        let mut events = Vec::with_capacity(events_len.min(contents.len() / 17));
+        for _ in 0..events_len {
+            let frame = reader.u64()?;
+            let pressed = Keys::from_bits_truncate(reader.u32()?);
+            let released = Keys::from_bits_truncate(reader.u32()?);
+            let touch_pos = match reader.u8()? {
+                0 => None,
+                1 => Some(None),
+                2 => Some(Some([reader.u16()?, reader.u16()?])),
+                _ => return Err(Error::InvalidData),
+            };
+            events.push(Event {
+                frame,
+                changes: input::Changes {
+                    pressed,
+                    released,
+                    touch_pos,
+                },
+            });
+        }
+
+        Ok(Movie {
+            rtc_start,
+            len_frames,
+            start,
+            events,
+        })
+    }
+}
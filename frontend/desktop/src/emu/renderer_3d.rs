@@ -3,15 +3,18 @@ use dust_core::{
         engine_3d::{
             Polygon, Renderer as RendererTrair, RenderingState as CoreRenderingState, ScreenVertex,
         },
-        Scanline, SCREEN_HEIGHT,
+        rgb_18_to_rgba_32, Framebuffer, Framebuffer3dMask, Scanline, SCREEN_HEIGHT, SCREEN_WIDTH,
     },
     utils::{zeroed_box, Bytes},
 };
 use dust_soft_3d::{RawRenderer, RenderingData};
+use parking_lot::Mutex;
 use std::{
     cell::UnsafeCell,
     hint,
     mem::transmute,
+    ops::Range,
+    slice,
     sync::{
         atomic::{AtomicBool, AtomicU8, Ordering},
         Arc,
@@ -19,30 +22,83 @@ use std::{
     thread,
 };
 
+// Rendering threads split the screen between them in bands of contiguous scanlines
+struct Band {
+    lines: Range<u8>,
+    processing_scanline: AtomicU8,
+}
+
 struct SharedData {
     rendering_data: Box<UnsafeCell<RenderingData>>,
     scanline_buffer: Box<UnsafeCell<[Scanline<u32, 256>; SCREEN_HEIGHT]>>,
-    processing_scanline: AtomicU8,
+    scale: u8,
+    // Full-resolution output, only allocated when rendering at a higher resolution
+    scaled_buffer: Box<UnsafeCell<[u32]>>,
+    bands: Box<[Band]>,
     stopped: AtomicBool,
 }
 
 unsafe impl Sync for SharedData {}
 
+/// The last complete frame rendered at a higher resolution, shared with the emulation thread to
+/// compose the upscaled framebuffer.
+#[derive(Clone)]
+pub struct ScaledFrame {
+    pub scale: u8,
+    pub data: Arc<Mutex<Box<[u32]>>>,
+}
+
 pub struct Renderer {
     next_scanline: u8,
     shared_data: Arc<SharedData>,
-    thread: Option<thread::JoinHandle<()>>,
+    threads: Vec<thread::JoinHandle<()>>,
+    scaled_frame: ScaledFrame,
 }
 
 impl Renderer {
     fn wait_for_line(&self, line: u8) {
+        let band = self
+            .shared_data
+            .bands
+            .iter()
+            .find(|band| band.lines.contains(&line))
+            .unwrap();
         while {
-            let processing_scanline = self.shared_data.processing_scanline.load(Ordering::Acquire);
+            let processing_scanline = band.processing_scanline.load(Ordering::Acquire);
             processing_scanline == u8::MAX || processing_scanline <= line
         } {
             hint::spin_loop();
         }
     }
+
+    fn wait_for_frame(&self) {
+        for band in self.shared_data.bands.iter() {
+            self.wait_for_line(band.lines.end - 1);
+        }
+    }
+
+    fn publish_scaled_frame(&self) {
+        if self.shared_data.scale == 1 {
+            return;
+        }
+        self.scaled_frame
+            .data
+            .lock()
+            .copy_from_slice(unsafe { &*self.shared_data.scaled_buffer.get() });
+    }
+
+    pub fn scaled_frame(&self) -> ScaledFrame {
+        self.scaled_frame.clone()
+    }
+
+    fn start_rendering(&self) {
+        for band in self.shared_data.bands.iter() {
+            band.processing_scanline.store(u8::MAX, Ordering::Release);
+        }
+        for thread in &self.threads {
+            thread.thread().unpark();
+        }
+    }
 }
 
 impl RendererTrair for Renderer {
@@ -55,7 +111,8 @@ impl RendererTrair for Renderer {
         state: &CoreRenderingState,
         w_buffering: bool,
     ) {
-        self.wait_for_line(SCREEN_HEIGHT as u8 - 1);
+        self.wait_for_frame();
+        self.publish_scaled_frame();
 
         unsafe { &mut *self.shared_data.rendering_data.get() }.prepare(
             texture,
@@ -66,10 +123,7 @@ impl RendererTrair for Renderer {
             w_buffering,
         );
 
-        self.shared_data
-            .processing_scanline
-            .store(u8::MAX, Ordering::Release);
-        self.thread.as_ref().unwrap().thread().unpark();
+        self.start_rendering();
     }
 
     fn repeat_last_frame(
@@ -78,15 +132,13 @@ impl RendererTrair for Renderer {
         tex_pal: &Bytes<0x1_8000>,
         state: &CoreRenderingState,
     ) {
-        self.wait_for_line(SCREEN_HEIGHT as u8 - 1);
+        self.wait_for_frame();
+        self.publish_scaled_frame();
 
         unsafe { &mut *self.shared_data.rendering_data.get() }
             .repeat_last_frame(texture, tex_pal, state);
 
-        self.shared_data
-            .processing_scanline
-            .store(u8::MAX, Ordering::Release);
-        self.thread.as_ref().unwrap().thread().unpark();
+        self.start_rendering();
     }
 
     fn start_frame(&mut self) {
@@ -108,82 +160,177 @@ impl RendererTrair for Renderer {
 
 impl Drop for Renderer {
     fn drop(&mut self) {
-        if let Some(thread) = self.thread.take() {
-            self.shared_data.stopped.store(true, Ordering::Relaxed);
+        self.shared_data.stopped.store(true, Ordering::Relaxed);
+        for thread in &self.threads {
             thread.thread().unpark();
+        }
+        for thread in self.threads.drain(..) {
             let _ = thread.join();
         }
     }
 }
 
+fn run_band(shared_data: &SharedData, band: &Band) {
+    let mut raw_renderer = RawRenderer::with_scale(shared_data.scale);
+    let scaled_line_len = SCREEN_WIDTH * (shared_data.scale as usize * shared_data.scale as usize);
+    loop {
+        loop {
+            if shared_data.stopped.load(Ordering::Relaxed) {
+                return;
+            }
+            if band
+                .processing_scanline
+                .compare_exchange(
+                    u8::MAX,
+                    band.lines.start,
+                    Ordering::Acquire,
+                    Ordering::Acquire,
+                )
+                .is_ok()
+            {
+                break;
+            } else {
+                thread::park();
+            }
+        }
+        let rendering_data = unsafe { &*shared_data.rendering_data.get() };
+        raw_renderer.start_frame(rendering_data);
+        for y in band.lines.clone() {
+            // Every thread only ever accesses the scanlines in its own band, so no aliasing
+            // references are created
+            let scanline = unsafe {
+                &mut *(shared_data.scanline_buffer.get() as *mut Scanline<u32, 256>).add(y as usize)
+            };
+            raw_renderer.render_line(y, scanline, rendering_data);
+            if shared_data.scale != 1 {
+                let scaled_lines = unsafe {
+                    slice::from_raw_parts_mut(
+                        (shared_data.scaled_buffer.get() as *mut u32)
+                            .add(y as usize * scaled_line_len),
+                        scaled_line_len,
+                    )
+                };
+                raw_renderer.read_scaled_line(y, scaled_lines);
+            }
+            if band
+                .processing_scanline
+                .compare_exchange(y, y + 1, Ordering::Release, Ordering::Relaxed)
+                .is_err()
+            {
+                return;
+            }
+        }
+    }
+}
+
 impl Renderer {
-    pub fn new() -> Self {
+    /// Creates a renderer splitting the screen between `thread_count` rendering threads, or
+    /// picking a thread count based on the available parallelism if it's 0, and rendering at
+    /// `scale` times the native resolution.
+    pub fn new(thread_count: u8, scale: u8) -> Self {
+        let thread_count = if thread_count == 0 {
+            // Leave one core free for the emulation thread
+            thread::available_parallelism()
+                .map_or(1, |threads| threads.get().saturating_sub(1))
+                .clamp(1, 4)
+        } else {
+            (thread_count as usize).min(SCREEN_HEIGHT)
+        };
+
+        let bands = (0..thread_count)
+            .map(|i| {
+                let lines = (i * SCREEN_HEIGHT / thread_count) as u8
+                    ..((i + 1) * SCREEN_HEIGHT / thread_count) as u8;
+                Band {
+                    processing_scanline: AtomicU8::new(lines.end),
+                    lines,
+                }
+            })
+            .collect();
+        let scaled_frame_len = if scale == 1 {
+            0
+        } else {
+            SCREEN_WIDTH * SCREEN_HEIGHT * (scale as usize * scale as usize)
+        };
         let shared_data = Arc::new(unsafe {
             SharedData {
                 rendering_data: transmute(zeroed_box::<RenderingData>()),
                 scanline_buffer: transmute(zeroed_box::<[Scanline<u32, 256>; SCREEN_HEIGHT]>()),
-                processing_scanline: AtomicU8::new(SCREEN_HEIGHT as u8),
+                scale,
+                scaled_buffer: Box::from_raw(Box::into_raw(
+                    vec![0_u32; scaled_frame_len].into_boxed_slice(),
+                ) as *mut UnsafeCell<[u32]>),
+                bands,
                 stopped: AtomicBool::new(false),
             }
         });
+
+        let threads = (0..thread_count)
+            .map(|i| {
+                let shared_data = Arc::clone(&shared_data);
+                thread::Builder::new()
+                    .name(format!("3D rendering {}", i))
+                    .spawn(move || run_band(&shared_data, &shared_data.bands[i]))
+                    .expect("Couldn't spawn 3D rendering thread")
+            })
+            .collect();
+
         Renderer {
             next_scanline: 0,
-            shared_data: shared_data.clone(),
-            thread: Some(
-                thread::Builder::new()
-                    .name("3D rendering".to_string())
-                    .spawn(move || {
-                        let mut raw_renderer = RawRenderer::new();
-                        loop {
-                            loop {
-                                if shared_data.stopped.load(Ordering::Relaxed) {
-                                    return;
-                                }
-                                if shared_data
-                                    .processing_scanline
-                                    .compare_exchange(
-                                        u8::MAX,
-                                        0,
-                                        Ordering::Acquire,
-                                        Ordering::Acquire,
-                                    )
-                                    .is_ok()
-                                {
-                                    break;
-                                } else {
-                                    thread::park();
-                                }
-                            }
-                            let rendering_data = unsafe { &*shared_data.rendering_data.get() };
-                            raw_renderer.start_frame(rendering_data);
-                            for y in 0..192 {
-                                let scanline =
-                                    &mut unsafe { &mut *shared_data.scanline_buffer.get() }
-                                        [y as usize];
-                                raw_renderer.render_line(y, scanline, rendering_data);
-                                if shared_data
-                                    .processing_scanline
-                                    .compare_exchange(
-                                        y,
-                                        y + 1,
-                                        Ordering::Release,
-                                        Ordering::Relaxed,
-                                    )
-                                    .is_err()
-                                {
-                                    return;
-                                }
-                            }
-                        }
-                    })
-                    .expect("Couldn't spawn 3D rendering thread"),
-            ),
+            shared_data,
+            threads,
+            scaled_frame: ScaledFrame {
+                scale,
+                data: Arc::new(Mutex::new(vec![0; scaled_frame_len].into_boxed_slice())),
+            },
         }
     }
 }
 
 impl Default for Renderer {
     fn default() -> Self {
-        Self::new()
+        Self::new(0, 1)
+    }
+}
+
+/// Composes the framebuffer at the scaled frame's resolution into `output`, taking the pixels
+/// marked in `mask` from the high-resolution 3D frame and scaling up all other ones.
+pub fn compose_scaled_framebuffer(
+    fb: &Framebuffer,
+    mask: &Framebuffer3dMask,
+    scaled_frame: &ScaledFrame,
+    output: &mut Vec<u32>,
+) {
+    let scale = scaled_frame.scale as usize;
+    let scaled_width = SCREEN_WIDTH * scale;
+    output.resize(scaled_width * SCREEN_HEIGHT * 2 * scale, 0);
+    let frame_3d = scaled_frame.data.lock();
+
+    for (screen_i, (screen, screen_mask)) in fb.0.iter().zip(&mask.0).enumerate() {
+        for (y, (line, line_mask)) in screen
+            .chunks_exact(SCREEN_WIDTH)
+            .zip(screen_mask)
+            .enumerate()
+        {
+            let output_base = (screen_i * SCREEN_HEIGHT + y) * scale * scaled_width;
+            let frame_3d_base = y * scale * scaled_width;
+            for (x, &pixel) in line.iter().enumerate() {
+                let is_3d = line_mask[x >> 6] & 1 << (x & 63) != 0;
+                for sub_y in 0..scale {
+                    let row_start = sub_y * scaled_width + x * scale;
+                    let output_row =
+                        &mut output[output_base + row_start..output_base + row_start + scale];
+                    if is_3d {
+                        for (output, &pixel_3d) in output_row.iter_mut().zip(
+                            &frame_3d[frame_3d_base + row_start..frame_3d_base + row_start + scale],
+                        ) {
+                            *output = rgb_18_to_rgba_32(pixel_3d);
+                        }
+                    } else {
+                        output_row.fill(pixel);
+                    }
+                }
+            }
+        }
     }
 }
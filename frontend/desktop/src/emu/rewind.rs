@@ -0,0 +1,90 @@
+use dust_core::emu::savestate::{compress_delta, decompress_delta_into};
+use std::collections::VecDeque;
+
+// Snapshots are stored as the XOR of each one with the one that followed it, which is mostly
+// zeros and is compressed with the same zero run encoding as savestates.
+
+pub struct Rewind {
+    interval: u32,
+    budget: usize,
+    frames_since_snapshot: u32,
+    latest: Option<Vec<u8>>,
+    // Ordered from oldest to newest; applying the newest delta to `latest` yields the snapshot
+    // that preceded it
+    deltas: VecDeque<Box<[u8]>>,
+    deltas_size: usize,
+}
+
+impl Rewind {
+    pub fn new(interval: u32, budget: usize) -> Self {
+        Rewind {
+            interval: interval.max(1),
+            budget,
+            frames_since_snapshot: 0,
+            latest: None,
+            deltas: VecDeque::new(),
+            deltas_size: 0,
+        }
+    }
+
+    #[inline]
+    pub fn enabled(&self) -> bool {
+        self.budget != 0
+    }
+
+    pub fn clear(&mut self) {
+        self.frames_since_snapshot = 0;
+        self.latest = None;
+        self.deltas.clear();
+        self.deltas_size = 0;
+    }
+
+    /// Returns whether a snapshot should be taken after the current frame.
+    pub fn frame_finished(&mut self) -> bool {
+        if !self.enabled() {
+            return false;
+        }
+        self.frames_since_snapshot += 1;
+        if self.frames_since_snapshot < self.interval {
+            return false;
+        }
+        self.frames_since_snapshot = 0;
+        true
+    }
+
+    pub fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(latest) = &self.latest {
+            if latest.len() == snapshot.len() {
+                let delta = compress_delta(latest, &snapshot).into_boxed_slice();
+                self.deltas_size += delta.len();
+                self.deltas.push_back(delta);
+            } else {
+                // The state's layout changed, older snapshots can't be reconstructed anymore
+                self.deltas.clear();
+                self.deltas_size = 0;
+            }
+        }
+        while self.deltas_size + snapshot.len() > self.budget {
+            match self.deltas.pop_front() {
+                Some(delta) => self.deltas_size -= delta.len(),
+                None => break,
+            }
+        }
+        self.latest = Some(snapshot);
+    }
+
+    /// Steps back by one snapshot, returning the state to load, or the oldest available one if
+    /// there is nothing older left.
+    pub fn pop(&mut self) -> Option<&[u8]> {
+        let latest = self.latest.as_mut()?;
+        // If the emulator advanced past the latest snapshot, going back to it is already a step
+        if self.frames_since_snapshot == 0 {
+            if let Some(delta) = self.deltas.pop_back() {
+                self.deltas_size -= delta.len();
+                decompress_delta_into(&delta, latest).expect("Invalid rewind delta");
+            }
+        }
+        self.frames_since_snapshot = 0;
+        Some(latest)
+    }
+}
@@ -1,21 +1,61 @@
 use chrono::{Datelike, Duration, Local, NaiveDate, NaiveDateTime, NaiveTime, Timelike};
 use dust_core::rtc::{self, Date, Time};
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+// 355 dots * 6 cycles * 263 lines at 33.513982 MHz
+const FRAME_CYCLES: u64 = 355 * 6 * 263;
+const CLOCK_RATE: u64 = 33_513_982;
+
+enum Clock {
+    Wall {
+        time_offset: Duration,
+    },
+    // Used while recording or playing back movies, so that the time only depends on the number of
+    // emulated frames
+    Emulated {
+        start: NaiveDateTime,
+        frames: Arc<AtomicU64>,
+    },
+}
 
 pub struct Backend {
-    time_offset: Duration,
+    clock: Clock,
 }
 
 impl Backend {
     pub fn new(time_offset_secondss: i64) -> Self {
         Backend {
-            time_offset: Duration::seconds(time_offset_secondss),
+            clock: Clock::Wall {
+                time_offset: Duration::seconds(time_offset_secondss),
+            },
+        }
+    }
+
+    pub fn new_emulated(start: NaiveDateTime, frames: Arc<AtomicU64>) -> Self {
+        Backend {
+            clock: Clock::Emulated { start, frames },
+        }
+    }
+
+    fn now(&self) -> NaiveDateTime {
+        match &self.clock {
+            Clock::Wall { time_offset } => Local::now().naive_local() + *time_offset,
+            Clock::Emulated { start, frames } => {
+                *start
+                    + Duration::seconds(
+                        (frames.load(Ordering::Relaxed) * FRAME_CYCLES / CLOCK_RATE) as i64,
+                    )
+            }
         }
     }
 }
 
 impl rtc::Backend for Backend {
     fn get_time(&mut self) -> Time {
-        let date_time = Local::now() + self.time_offset;
+        let date_time = self.now();
         Time {
             hour: date_time.hour() as u8,
             minute: date_time.minute() as u8,
@@ -24,7 +64,7 @@ impl rtc::Backend for Backend {
     }
 
     fn get_date_time(&mut self) -> (Date, Time) {
-        let date_time = Local::now() + self.time_offset;
+        let date_time = self.now();
         (
             Date {
                 years_since_2000: (date_time.year() - 2000) as u8,
@@ -55,6 +95,11 @@ impl rtc::Backend for Backend {
                 Some(time) => time,
                 None => return,
             };
-        self.time_offset = NaiveDateTime::new(date, time) - Local::now().naive_local();
+        let new_date_time = NaiveDateTime::new(date, time);
+        let diff = new_date_time - self.now();
+        match &mut self.clock {
+            Clock::Wall { time_offset } => *time_offset = *time_offset + diff,
+            Clock::Emulated { start, .. } => *start = *start + diff,
+        }
     }
 }
@@ -0,0 +1,449 @@
+//! Export of the polygons submitted for a frame as a glTF 2.0 scene.
+//!
+//! Only post-transform data is available at that point, so vertices are exported in screen space:
+//! X and Y are pixel coordinates (with Y pointing up), while Z is derived from the polygons' depth
+//! values, pointing away from the screen. Every distinct texture (and palette) is decoded to a
+//! separate PNG file next to the `.gltf` file, along with a `.bin` file holding the geometry.
+
+use dust_core::{
+    gpu::engine_3d::{Frame3dCapture, TextureParams},
+    utils::Bytes,
+};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File},
+    io::{self, BufWriter},
+    path::Path,
+};
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Png(png::EncodingError),
+    Json(serde_json::Error),
+}
+
+impl From<io::Error> for Error {
+    #[inline]
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl From<png::EncodingError> for Error {
+    #[inline]
+    fn from(err: png::EncodingError) -> Self {
+        Error::Png(err)
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    #[inline]
+    fn from(err: serde_json::Error) -> Self {
+        Error::Json(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Png(err) => write!(f, "PNG encoding error: {}", err),
+            Error::Json(err) => write!(f, "JSON error: {}", err),
+        }
+    }
+}
+
+fn rgb_5_to_rgba_8(color: u16, alpha: u8) -> [u8; 4] {
+    let expand = |value: u16| (value << 3 | value >> 2) as u8;
+    [
+        expand(color & 0x1F),
+        expand(color >> 5 & 0x1F),
+        expand(color >> 10 & 0x1F),
+        alpha << 3 | alpha >> 2,
+    ]
+}
+
+fn blend_rgb_5(color_0: u16, color_1: u16, weight_0: u16, weight_1: u16) -> u16 {
+    let mut result = 0;
+    for shift in [0, 5, 10] {
+        let component =
+            ((color_0 >> shift & 0x1F) * weight_0 + (color_1 >> shift & 0x1F) * weight_1) >> 3;
+        result |= component << shift;
+    }
+    result
+}
+
+/// Decodes a whole texture to RGBA8, ignoring repeat and flip settings.
+pub fn decode_texture(
+    texture: &Bytes<0x8_0000>,
+    tex_pal: &Bytes<0x1_8000>,
+    params: TextureParams,
+    palette_base: u16,
+) -> Vec<u8> {
+    let width_shift = params.size_shift_s() + 3;
+    let width = 1_usize << width_shift;
+    let height = 8_usize << params.size_shift_t();
+    let format = params.format();
+    let tex_base = (params.vram_off() as usize) << 3;
+    let pal_base = if format == 2 {
+        (palette_base as usize) << 3
+    } else {
+        (palette_base as usize) << 4
+    };
+    let pal_color = |pal_base: usize, index: usize| {
+        tex_pal.read_le::<u16>((pal_base + (index << 1)) & 0x1_FFFE)
+    };
+    let indexed_alpha = |index: usize| {
+        if params.use_color_0_as_transparent() && index == 0 {
+            0
+        } else {
+            0x1F
+        }
+    };
+
+    let mut data = Vec::with_capacity(width * height * 4);
+    for v in 0..height {
+        for u in 0..width {
+            let i = v << width_shift | u;
+            let (color, alpha) = match format {
+                1 => {
+                    let pixel = texture[(tex_base + i) & 0x7_FFFF];
+                    let raw_alpha = pixel >> 5;
+                    (
+                        pal_color(pal_base, pixel as usize & 0x1F),
+                        raw_alpha << 2 | raw_alpha >> 1,
+                    )
+                }
+
+                2 => {
+                    let index =
+                        (texture[(tex_base + (i >> 2)) & 0x7_FFFF] >> ((i & 3) << 1) & 3) as usize;
+                    (pal_color(pal_base, index), indexed_alpha(index))
+                }
+
+                3 => {
+                    let index = (texture[(tex_base + (i >> 1)) & 0x7_FFFF] >> ((i & 1) << 2) & 0xF)
+                        as usize;
+                    (pal_color(pal_base, index), indexed_alpha(index))
+                }
+
+                4 => {
+                    let index = texture[(tex_base + i) & 0x7_FFFF] as usize;
+                    (pal_color(pal_base, index), indexed_alpha(index))
+                }
+
+                5 => {
+                    let texel_block_addr = tex_base + ((v >> 2) << width_shift | (u & !3));
+                    let texel_value =
+                        texture[(texel_block_addr | (v & 3)) & 0x7_FFFF] >> ((u & 3) << 1) & 3;
+                    let pal_data_addr = 0x2_0000
+                        | (texel_block_addr >> 1 & 0xFFFE)
+                        | (texel_block_addr >> 2 & 0x1_0000);
+                    let pal_data = texture.read_le::<u16>(pal_data_addr);
+                    let pal_base = pal_base + (pal_data << 2) as usize;
+                    let mode = pal_data >> 14;
+                    let color = |index| pal_color(pal_base, index);
+                    match (texel_value, mode) {
+                        (0, _) => (color(0), 0x1F),
+                        (1, _) => (color(1), 0x1F),
+                        (2, 0 | 2) => (color(2), 0x1F),
+                        (2, 1) => (blend_rgb_5(color(0), color(1), 4, 4), 0x1F),
+                        (2, _) => (blend_rgb_5(color(0), color(1), 5, 3), 0x1F),
+                        (_, 0 | 1) => (0, 0),
+                        (_, 2) => (color(3), 0x1F),
+                        _ => (blend_rgb_5(color(0), color(1), 3, 5), 0x1F),
+                    }
+                }
+
+                6 => {
+                    let pixel = texture[(tex_base + i) & 0x7_FFFF];
+                    (pal_color(pal_base, pixel as usize & 7), pixel >> 3)
+                }
+
+                _ => {
+                    let color = texture.read_le::<u16>((tex_base + (i << 1)) & 0x7_FFFE);
+                    (color, if color & 1 << 15 != 0 { 0x1F } else { 0 })
+                }
+            };
+            data.extend_from_slice(&rgb_5_to_rgba_8(color, alpha));
+        }
+    }
+    data
+}
+
+fn write_png(path: &Path, width: u32, height: u32, data: &[u8]) -> Result<(), Error> {
+    let mut encoder = png::Encoder::new(BufWriter::new(File::create(path)?), width, height);
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(data)?;
+    Ok(())
+}
+
+// glTF sampler wrap modes
+const CLAMP_TO_EDGE: u32 = 33071;
+const MIRRORED_REPEAT: u32 = 33648;
+const REPEAT: u32 = 10497;
+
+fn wrap_mode(repeat: bool, flip: bool) -> u32 {
+    match (repeat, flip) {
+        (false, _) => CLAMP_TO_EDGE,
+        (true, false) => REPEAT,
+        (true, true) => MIRRORED_REPEAT,
+    }
+}
+
+#[derive(Default)]
+struct Primitive {
+    indices: Vec<u32>,
+}
+
+/// Writes `scene` to `path` as a `.gltf` file, with the geometry and textures stored in separate
+/// files next to it.
+pub fn export(scene: &Frame3dCapture, path: &Path) -> Result<(), Error> {
+    let dir = path.parent().unwrap_or_else(|| Path::new(""));
+    let stem = path.file_stem().map_or_else(
+        || "scene".to_string(),
+        |stem| stem.to_string_lossy().into_owned(),
+    );
+
+    let mut positions = Vec::new();
+    let mut tex_coords = Vec::new();
+    let mut colors = Vec::new();
+    let mut min_pos = [f32::INFINITY; 3];
+    let mut max_pos = [f32::NEG_INFINITY; 3];
+
+    // Textures are keyed by the parameters affecting their contents, while materials are also
+    // keyed by wrapping mode and polygon alpha
+    let mut textures: HashMap<(u32, u16), usize> = HashMap::new();
+    let mut images = Vec::new();
+    let mut samplers: HashMap<(u32, u32), usize> = HashMap::new();
+    let mut gltf_textures: HashMap<(usize, usize), usize> = HashMap::new();
+    let mut materials: HashMap<(Option<usize>, u8), usize> = HashMap::new();
+    let mut material_values = Vec::new();
+    let mut primitives: Vec<Primitive> = Vec::new();
+
+    for poly in &scene.poly_ram {
+        let tex_params = poly.tex_params;
+        let textured = scene.control.texture_mapping_enabled() && tex_params.format() != 0;
+        let tex_size = [
+            (8 << tex_params.size_shift_s()) as f32,
+            (8 << tex_params.size_shift_t()) as f32,
+        ];
+
+        let texture = if textured {
+            let palette_base = if matches!(tex_params.format(), 0 | 7) {
+                0
+            } else {
+                poly.tex_palette_base
+            };
+            let texture_key = (tex_params.0 & 0x3FF0_FFFF, palette_base);
+            let image_i = match textures.get(&texture_key) {
+                Some(&i) => i,
+                None => {
+                    let i = images.len();
+                    let file_name = format!("{}_tex{}.png", stem, i);
+                    write_png(
+                        &dir.join(&file_name),
+                        tex_size[0] as u32,
+                        tex_size[1] as u32,
+                        &decode_texture(&scene.texture, &scene.tex_pal, tex_params, palette_base),
+                    )?;
+                    images.push(json!({ "uri": file_name }));
+                    textures.insert(texture_key, i);
+                    i
+                }
+            };
+            let sampler_key = (
+                wrap_mode(tex_params.repeat_s(), tex_params.flip_s()),
+                wrap_mode(tex_params.repeat_t(), tex_params.flip_t()),
+            );
+            let samplers_len = samplers.len();
+            let sampler_i = *samplers.entry(sampler_key).or_insert(samplers_len);
+            let gltf_textures_len = gltf_textures.len();
+            Some(
+                *gltf_textures
+                    .entry((image_i, sampler_i))
+                    .or_insert(gltf_textures_len),
+            )
+        } else {
+            None
+        };
+
+        let alpha = match poly.attrs.alpha() {
+            0 => 0x1F,
+            alpha => alpha,
+        };
+        let material_i = *materials.entry((texture, alpha)).or_insert_with(|| {
+            let mut pbr = json!({
+                "baseColorFactor": [1.0, 1.0, 1.0, alpha as f32 / 31.0],
+                "metallicFactor": 0.0,
+                "roughnessFactor": 1.0,
+            });
+            if let Some(texture) = texture {
+                pbr["baseColorTexture"] = json!({ "index": texture });
+            }
+            let alpha_mode = if alpha == 0x1F && texture.is_none() {
+                "OPAQUE"
+            } else {
+                "BLEND"
+            };
+            material_values.push(json!({
+                "pbrMetallicRoughness": pbr,
+                "alphaMode": alpha_mode,
+                "doubleSided": true,
+            }));
+            primitives.push(Primitive::default());
+            material_values.len() - 1
+        });
+
+        let first_vert_i = (positions.len() / 3) as u32;
+        let verts_len = poly.vertices_len.get() as usize;
+        for (&vert_addr, &depth) in poly.vertices[..verts_len].iter().zip(&poly.depth_values) {
+            let vert = &scene.vert_ram[vert_addr.get() as usize];
+            let depth = depth as f32
+                / if scene.w_buffering {
+                    // W values are 20.12 fixed-point
+                    16.0
+                } else {
+                    // Z values span 24 bits, scale them to the screen's order of magnitude
+                    65536.0
+                };
+            let pos = [
+                vert.coords[0] as f32 + vert.coords_fract[0] as f32 / 256.0,
+                192.0 - (vert.coords[1] as f32 + vert.coords_fract[1] as f32 / 256.0),
+                -depth,
+            ];
+            for ((min, max), coord) in min_pos.iter_mut().zip(&mut max_pos).zip(pos) {
+                *min = min.min(coord);
+                *max = max.max(coord);
+            }
+            positions.extend_from_slice(&pos);
+            tex_coords.extend_from_slice(&[
+                vert.uv[0] as f32 / (16.0 * tex_size[0]),
+                vert.uv[1] as f32 / (16.0 * tex_size[1]),
+            ]);
+            let color = vert.color.to_array();
+            colors.extend_from_slice(&[color[0], color[1], color[2]].map(|c| c as f32 / 511.0));
+        }
+        // Polygons are always convex, so they can be split into a triangle fan
+        let indices = &mut primitives[material_i].indices;
+        for i in 1..verts_len.saturating_sub(1) as u32 {
+            indices.extend_from_slice(&[first_vert_i, first_vert_i + i, first_vert_i + i + 1]);
+        }
+    }
+
+    let mut buffer = Vec::new();
+    let mut buffer_views = Vec::new();
+    let mut accessors = Vec::new();
+    let mut push_floats = |values: &[f32], components: usize, attr_type: &str, bounds: Value| {
+        let offset = buffer.len();
+        for value in values {
+            buffer.extend_from_slice(&value.to_le_bytes());
+        }
+        buffer_views.push(json!({
+            "buffer": 0,
+            "byteOffset": offset,
+            "byteLength": values.len() * 4,
+            "target": 34962,
+        }));
+        let mut accessor = json!({
+            "bufferView": buffer_views.len() - 1,
+            "componentType": 5126,
+            "count": values.len() / components,
+            "type": attr_type,
+        });
+        if let Value::Object(bounds) = bounds {
+            accessor.as_object_mut().unwrap().extend(bounds);
+        }
+        accessors.push(accessor);
+        accessors.len() - 1
+    };
+
+    let mut mesh_primitives = Vec::new();
+    if !positions.is_empty() {
+        let position_i = push_floats(
+            &positions,
+            3,
+            "VEC3",
+            json!({ "min": min_pos, "max": max_pos }),
+        );
+        let tex_coord_i = push_floats(&tex_coords, 2, "VEC2", Value::Null);
+        let color_i = push_floats(&colors, 3, "VEC3", Value::Null);
+
+        for (material_i, primitive) in primitives.iter().enumerate() {
+            if primitive.indices.is_empty() {
+                continue;
+            }
+            let offset = buffer.len();
+            for index in &primitive.indices {
+                buffer.extend_from_slice(&index.to_le_bytes());
+            }
+            buffer_views.push(json!({
+                "buffer": 0,
+                "byteOffset": offset,
+                "byteLength": primitive.indices.len() * 4,
+                "target": 34963,
+            }));
+            accessors.push(json!({
+                "bufferView": buffer_views.len() - 1,
+                "componentType": 5125,
+                "count": primitive.indices.len(),
+                "type": "SCALAR",
+            }));
+            let mut attributes = json!({ "POSITION": position_i, "COLOR_0": color_i });
+            if material_values[material_i]["pbrMetallicRoughness"]
+                .get("baseColorTexture")
+                .is_some()
+            {
+                attributes["TEXCOORD_0"] = json!(tex_coord_i);
+            }
+            mesh_primitives.push(json!({
+                "attributes": attributes,
+                "indices": accessors.len() - 1,
+                "material": material_i,
+            }));
+        }
+    }
+
+    let mut sampler_values = vec![Value::Null; samplers.len()];
+    for ((wrap_s, wrap_t), i) in samplers {
+        sampler_values[i] = json!({
+            "magFilter": 9728,
+            "minFilter": 9728,
+            "wrapS": wrap_s,
+            "wrapT": wrap_t,
+        });
+    }
+    let mut texture_values = vec![Value::Null; gltf_textures.len()];
+    for ((image_i, sampler_i), i) in gltf_textures {
+        texture_values[i] = json!({ "source": image_i, "sampler": sampler_i });
+    }
+
+    let bin_file_name = format!("{}.bin", stem);
+    fs::write(dir.join(&bin_file_name), &buffer)?;
+
+    let mut root = json!({
+        "asset": { "version": "2.0", "generator": "Dust" },
+        "scene": 0,
+        "scenes": [{ "nodes": [0] }],
+        "nodes": [{ "name": "3D frame" }],
+        "buffers": [{ "uri": bin_file_name, "byteLength": buffer.len() }],
+        "bufferViews": buffer_views,
+        "accessors": accessors,
+    });
+    if !mesh_primitives.is_empty() {
+        root["nodes"][0]["mesh"] = json!(0);
+        root["meshes"] = json!([{ "primitives": mesh_primitives }]);
+        root["materials"] = json!(material_values);
+    }
+    if !images.is_empty() {
+        root["images"] = json!(images);
+        root["samplers"] = json!(sampler_values);
+        root["textures"] = json!(texture_values);
+    }
+    serde_json::to_writer_pretty(BufWriter::new(File::create(path)?), &root)?;
+    Ok(())
+}
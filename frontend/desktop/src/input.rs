@@ -20,6 +20,15 @@ pub enum Action {
     ToggleFullscreenRender,
     ToggleAudioSync,
     ToggleFramerateLimit,
+    SaveState,
+    LoadState,
+    NextStateSlot,
+    PrevStateSlot,
+    Rewind,
+    RecordMovie,
+    PlayMovie,
+    StopMovie,
+    BlowMic,
 }
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq)]
@@ -157,6 +166,10 @@ impl State {
         }
     }
 
+    pub fn hotkey_held(&self, action: Action) -> bool {
+        self.pressed_hotkeys.contains(&action)
+    }
+
     pub fn drain_changes(&mut self, emu_playing: bool) -> (Vec<Action>, Option<Changes>) {
         let mut actions = Vec::new();
         for (&action, trigger) in &self.keymap.contents.hotkeys {
@@ -95,6 +95,15 @@ static ACTIONS: &[(Action, &str)] = &[
     ),
     (Action::ToggleAudioSync, "Toggle audio sync"),
     (Action::ToggleFramerateLimit, "Toggle framerate limit"),
+    (Action::SaveState, "Save state to current slot"),
+    (Action::LoadState, "Load state from current slot"),
+    (Action::NextStateSlot, "Select next savestate slot"),
+    (Action::PrevStateSlot, "Select previous savestate slot"),
+    (Action::Rewind, "Rewind (hold)"),
+    (Action::RecordMovie, "Record movie from current state"),
+    (Action::PlayMovie, "Play movie"),
+    (Action::StopMovie, "Stop movie"),
+    (Action::BlowMic, "Blow into mic (hold)"),
 ];
 
 fn heading(ui: &Ui, text: &str, indent: f32, margin: f32) {
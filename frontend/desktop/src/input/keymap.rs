@@ -35,6 +35,15 @@ static ACTION_IDENTS: &[(Action, &str)] = &[
     (Action::ToggleFullscreenRender, "toggle-fullscreen-render"),
     (Action::ToggleAudioSync, "toggle-audio-sync"),
     (Action::ToggleFramerateLimit, "toggle-framerate-limit"),
+    (Action::SaveState, "save-state"),
+    (Action::LoadState, "load-state"),
+    (Action::NextStateSlot, "next-state-slot"),
+    (Action::PrevStateSlot, "prev-state-slot"),
+    (Action::Rewind, "rewind"),
+    (Action::RecordMovie, "record-movie"),
+    (Action::PlayMovie, "play-movie"),
+    (Action::StopMovie, "stop-movie"),
+    (Action::BlowMic, "blow-mic"),
 ];
 
 #[derive(Clone, Debug)]
@@ -80,6 +89,15 @@ fn default_hotkey_map() -> FxHashMap<Action, Option<Trigger>> {
         (Action::ToggleFullscreenRender, None),
         (Action::ToggleAudioSync, None),
         (Action::ToggleFramerateLimit, None),
+        (Action::SaveState, None),
+        (Action::LoadState, None),
+        (Action::NextStateSlot, None),
+        (Action::PrevStateSlot, None),
+        (Action::Rewind, None),
+        (Action::RecordMovie, None),
+        (Action::PlayMovie, None),
+        (Action::StopMovie, None),
+        (Action::BlowMic, None),
     ]
     .into_iter()
     .collect()
@@ -25,6 +25,7 @@ mod config;
 mod debug_views;
 mod game_db;
 pub mod input;
+mod savestates;
 mod triple_buffer;
 
 mod emu;
@@ -36,6 +37,9 @@ use std::panic;
 #[repr(C)]
 struct FrameData {
     fb: Box<Framebuffer>,
+    // Upscaled framebuffer, only filled in when the 3D renderer's resolution scale isn't 1
+    scaled_fb: Vec<u32>,
+    fb_scale: u8,
     fps: f32,
     #[cfg(feature = "debug-views")]
     debug: debug_views::FrameData,
@@ -45,6 +49,8 @@ impl Default for FrameData {
     fn default() -> Self {
         FrameData {
             fb: zeroed_box(),
+            scaled_fb: Vec::new(),
+            fb_scale: 1,
             fps: 0.0,
             #[cfg(feature = "debug-views")]
             debug: debug_views::FrameData::new(),
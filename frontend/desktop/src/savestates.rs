@@ -0,0 +1,121 @@
+use chrono::{DateTime, Local};
+use dust_core::{
+    cpu::interpreter::Interpreter,
+    emu::{savestate, Emu},
+    gpu::{Framebuffer, SCREEN_HEIGHT, SCREEN_WIDTH},
+    utils::zeroed_box,
+};
+use std::{
+    fmt, fs,
+    io::{self, Read},
+    path::{Path, PathBuf},
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+pub const SLOTS: u8 = 10;
+
+// Both screens at half resolution, stacked vertically like in the framebuffer
+pub const THUMBNAIL_WIDTH: usize = SCREEN_WIDTH / 2;
+pub const THUMBNAIL_HEIGHT: usize = SCREEN_HEIGHT;
+pub type Thumbnail = [u32; THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT];
+
+const THUMBNAIL_LEN: usize = THUMBNAIL_WIDTH * THUMBNAIL_HEIGHT * 4;
+const HEADER_LEN: usize = 8 + THUMBNAIL_LEN;
+
+pub struct SlotInfo {
+    pub timestamp: DateTime<Local>,
+    pub thumbnail: Box<Thumbnail>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Io(io::Error),
+    Truncated,
+    State(savestate::Error),
+}
+
+impl From<io::Error> for Error {
+    #[inline]
+    fn from(err: io::Error) -> Self {
+        Error::Io(err)
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "I/O error: {}", err),
+            Error::Truncated => f.write_str("truncated savestate file"),
+            Error::State(err) => write!(f, "{}", err),
+        }
+    }
+}
+
+pub fn slot_path(dir: &Path, slot: u8) -> PathBuf {
+    dir.join(format!("slot_{}.state", slot + 1))
+}
+
+pub fn thumbnail(fb: &Framebuffer) -> Box<Thumbnail> {
+    let mut thumbnail = zeroed_box::<Thumbnail>();
+    for (screen_i, screen) in fb.0.iter().enumerate() {
+        for y in 0..SCREEN_HEIGHT / 2 {
+            let dst_line_base = (screen_i * SCREEN_HEIGHT / 2 + y) * THUMBNAIL_WIDTH;
+            let src_line_base = y * 2 * SCREEN_WIDTH;
+            for x in 0..THUMBNAIL_WIDTH {
+                thumbnail[dst_line_base + x] = screen[src_line_base + x * 2];
+            }
+        }
+    }
+    thumbnail
+}
+
+pub fn read_info(path: &Path) -> Option<SlotInfo> {
+    let mut file = fs::File::open(path).ok()?;
+    let mut header = vec![0; HEADER_LEN];
+    file.read_exact(&mut header).ok()?;
+    let timestamp = DateTime::<Local>::from(
+        UNIX_EPOCH + Duration::from_secs(u64::from_le_bytes(header[..8].try_into().unwrap())),
+    );
+    let mut thumbnail = zeroed_box::<Thumbnail>();
+    for (pixel, bytes) in thumbnail.iter_mut().zip(header[8..].array_chunks::<4>()) {
+        *pixel = u32::from_le_bytes(*bytes);
+    }
+    Some(SlotInfo {
+        timestamp,
+        thumbnail,
+    })
+}
+
+pub fn write(path: &Path, thumbnail: &Thumbnail, state: &[u8]) -> io::Result<()> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)?;
+    }
+    let timestamp = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let mut contents = Vec::with_capacity(HEADER_LEN + state.len());
+    contents.extend_from_slice(&timestamp.to_le_bytes());
+    for pixel in thumbnail {
+        contents.extend_from_slice(&pixel.to_le_bytes());
+    }
+    contents.extend_from_slice(state);
+    fs::write(path, contents)
+}
+
+pub fn read_state(path: &Path) -> Result<Vec<u8>, Error> {
+    let mut contents = fs::read(path)?;
+    if contents.len() < HEADER_LEN {
+        return Err(Error::Truncated);
+    }
+    contents.drain(..HEADER_LEN);
+    Ok(contents)
+}
+
+pub fn store(emu: &mut Emu<Interpreter>) -> Vec<u8> {
+    emu.save_state(true)
+}
+
+pub fn load(emu: &mut Emu<Interpreter>, state: &[u8]) -> Result<(), Error> {
+    emu.load_state(state).map_err(Error::State)
+}
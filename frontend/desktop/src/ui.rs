@@ -11,7 +11,7 @@ use super::debug_views;
 use super::{
     audio,
     config::{self, CommonLaunchConfig, Config},
-    emu, game_db, input, triple_buffer,
+    emu, game_db, input, savestates, triple_buffer,
     utils::{config_base, scale_to_fit_rotated},
     FrameData,
 };
@@ -20,7 +20,7 @@ use dust_core::audio::ChannelInterpMethod as AudioChannelInterpMethod;
 use dust_core::{
     ds_slot,
     gpu::{SCREEN_HEIGHT, SCREEN_WIDTH},
-    utils::{zeroed_box, BoxedByteSlice},
+    utils::BoxedByteSlice,
 };
 use parking_lot::RwLock;
 use rfd::FileDialog;
@@ -33,6 +33,7 @@ use std::{
     fmt::Write,
     fs::{self, File},
     io::{self, Read},
+    mem,
     path::{Path, PathBuf},
     slice,
     sync::{
@@ -138,6 +139,8 @@ struct EmuState {
     playing: bool,
     game_config: Option<Config<config::Game>>,
     game_title: String,
+    savestate_dir: Option<PathBuf>,
+    movie_dir: Option<PathBuf>,
     message_tx: crossbeam_channel::Sender<emu::Message>,
     thread: thread::JoinHandle<triple_buffer::Sender<FrameData>>,
     shared_state: Arc<emu::SharedState>,
@@ -180,6 +183,12 @@ struct UiState {
     fps_fixed: Option<u64>,
     fb_texture_id: imgui::TextureId,
 
+    savestate_slot: u8,
+    savestate_slots: Vec<Option<savestates::SlotInfo>>,
+    savestates_menu_open: bool,
+    savestate_thumbnail_texture_id: imgui::TextureId,
+    savestate_thumbnail_slot: Option<u8>,
+
     #[cfg(feature = "debug-views")]
     debug_views: debug_views::UiState,
 
@@ -353,6 +362,101 @@ impl UiState {
             UiState::update_limit_framerate
         );
     }
+
+    fn save_state(&mut self, slot: u8) {
+        if let Some(emu) = &self.emu_state {
+            if let Some(dir) = &emu.savestate_dir {
+                emu.send_message(emu::Message::SaveState(savestates::slot_path(dir, slot)));
+            }
+        }
+    }
+
+    fn load_state(&mut self, slot: u8) {
+        if let Some(emu) = &self.emu_state {
+            if let Some(dir) = &emu.savestate_dir {
+                emu.send_message(emu::Message::LoadState(savestates::slot_path(dir, slot)));
+            }
+        }
+    }
+
+    fn delete_state(&mut self, slot: u8) {
+        if let Some(dir) = self
+            .emu_state
+            .as_ref()
+            .and_then(|emu| emu.savestate_dir.as_ref())
+        {
+            let _ = fs::remove_file(savestates::slot_path(dir, slot));
+        }
+        self.savestate_slots[slot as usize] = None;
+        if self.savestate_thumbnail_slot == Some(slot) {
+            self.savestate_thumbnail_slot = None;
+        }
+    }
+
+    fn record_movie(&mut self, from_power_on: bool) {
+        if let Some(emu) = &self.emu_state {
+            if let Some(dir) = &emu.movie_dir {
+                let file_name =
+                    format!("{}.dmov", chrono::Local::now().format("%Y-%m-%d_%H-%M-%S"));
+                emu.send_message(emu::Message::StartMovieRecording {
+                    path: dir.join(file_name),
+                    from_power_on,
+                });
+            }
+        }
+    }
+
+    fn play_movie(&mut self) {
+        if let Some(emu) = &self.emu_state {
+            let mut dialog = FileDialog::new().add_filter("Dust movie", &["dmov"]);
+            if let Some(dir) = &emu.movie_dir {
+                dialog = dialog.set_directory(dir);
+            }
+            if let Some(path) = dialog.pick_file() {
+                emu.send_message(emu::Message::StartMoviePlayback(path));
+            }
+        }
+    }
+
+    fn stop_movie(&mut self) {
+        if let Some(emu) = &self.emu_state {
+            emu.send_message(emu::Message::StopMovie);
+        }
+    }
+
+    #[cfg(feature = "debug-views")]
+    fn capture_gx_frame(&mut self) {
+        if let Some(emu) = &self.emu_state {
+            if let Some(path) = FileDialog::new()
+                .add_filter("Dust GX capture", &["dgx"])
+                .save_file()
+            {
+                emu.send_message(emu::Message::CaptureGxFrame(path));
+            }
+        }
+    }
+
+    #[cfg(feature = "debug-views")]
+    fn export_3d_scene(&mut self) {
+        if let Some(emu) = &self.emu_state {
+            if let Some(path) = FileDialog::new().add_filter("glTF", &["gltf"]).save_file() {
+                emu.send_message(emu::Message::ExportScene(path));
+            }
+        }
+    }
+
+    fn refresh_savestate_slots(&mut self) {
+        let dir = self
+            .emu_state
+            .as_ref()
+            .and_then(|emu| emu.savestate_dir.as_ref());
+        self.savestate_slots = (0..savestates::SLOTS)
+            .map(|slot| {
+                dir.and_then(|dir| savestates::read_info(&savestates::slot_path(dir, slot)))
+            })
+            .collect();
+        self.savestate_thumbnail_slot = None;
+    }
 }
 
 impl UiState {
@@ -456,6 +560,16 @@ impl UiState {
 
         let playing = !config.pause_on_launch;
 
+        let savestate_dir = ds_slot_rom.as_ref().map(|_| {
+            self.global_config
+                .contents
+                .savestate_dir_path
+                .join(&game_title)
+        });
+        let movie_dir = ds_slot_rom
+            .as_ref()
+            .map(|_| self.global_config.contents.movie_dir_path.join(&game_title));
+
         #[cfg(feature = "log")]
         let logger = self.logger.clone();
 
@@ -512,6 +626,9 @@ impl UiState {
                 config.autosave_interval_ms.value / 1000.0,
             )),
             stopped: AtomicBool::new(false),
+            rewinding: AtomicBool::new(false),
+            movie_active: AtomicBool::new(false),
+            mic_blowing: AtomicBool::new(false),
             #[cfg(feature = "gdb-server")]
             gdb_server_active: AtomicBool::new(false),
         });
@@ -543,6 +660,8 @@ impl UiState {
             playing,
             game_config,
             game_title,
+            savestate_dir,
+            movie_dir,
             message_tx,
             thread,
             shared_state,
@@ -662,17 +781,40 @@ impl UiState {
 }
 
 fn clear_fb_texture(id: imgui::TextureId, window: &mut window::Window) {
-    let mut data = zeroed_box::<[u8; SCREEN_WIDTH * SCREEN_HEIGHT * 8]>();
+    let texture = window.gfx.imgui.texture_mut(id);
+    let size = texture.texture_desc().size;
+    let mut data = vec![0; size.width as usize * size.height as usize * 4];
     for i in (0..data.len()).step_by(4) {
         data[i + 3] = 0xFF;
     }
-    window.gfx.imgui.texture_mut(id).set_data(
+    texture.set_data(
         &window.gfx.device_state.queue,
-        &data[..],
+        &data,
         imgui_wgpu::TextureRange::default(),
     );
 }
 
+fn resize_fb_texture(id: imgui::TextureId, window: &mut window::Window, scale: u8) {
+    let imgui = &mut window.gfx.imgui;
+    let texture = imgui.textures.get_mut(&id).unwrap();
+    let format = texture.texture_desc().format;
+    texture.rebuild_with_texture_desc(
+        &window.gfx.device_state.device,
+        &imgui.texture_bind_group_layout,
+        imgui_wgpu::TextureDescriptor {
+            label: Some("framebuffer texture".to_string()),
+            size: wgpu::Extent3d {
+                width: SCREEN_WIDTH as u32 * scale as u32,
+                height: SCREEN_HEIGHT as u32 * 2 * scale as u32,
+                depth_or_array_layers: 1,
+            },
+            format,
+            ..Default::default()
+        },
+        imgui.output_format,
+    );
+}
+
 pub fn main() {
     let config_home = config_base();
 
@@ -779,6 +921,20 @@ pub fn main() {
         FrameData::default(),
     ]);
 
+    let fb_texture_format = if window_builder
+        .window
+        .gfx
+        .device_state
+        .surf_config
+        .format
+        .describe()
+        .srgb
+    {
+        wgpu::TextureFormat::Rgba8UnormSrgb
+    } else {
+        wgpu::TextureFormat::Rgba8Unorm
+    };
+
     let fb_texture_id = {
         let texture = window_builder.window.gfx.imgui.create_texture(
             &window_builder.window.gfx.device_state.device,
@@ -794,21 +950,7 @@ pub fn main() {
                     height: SCREEN_HEIGHT as u32 * 2,
                     depth_or_array_layers: 1,
                 },
-                format: Some(
-                    if window_builder
-                        .window
-                        .gfx
-                        .device_state
-                        .surf_config
-                        .format
-                        .describe()
-                        .srgb
-                    {
-                        wgpu::TextureFormat::Rgba8UnormSrgb
-                    } else {
-                        wgpu::TextureFormat::Rgba8Unorm
-                    },
-                ),
+                format: Some(fb_texture_format),
                 ..Default::default()
             },
         );
@@ -816,6 +958,28 @@ pub fn main() {
     };
     clear_fb_texture(fb_texture_id, &mut window_builder.window);
 
+    let savestate_thumbnail_texture_id = {
+        let texture = window_builder.window.gfx.imgui.create_texture(
+            &window_builder.window.gfx.device_state.device,
+            &wgpu::SamplerDescriptor {
+                label: Some("savestate thumbnail sampler"),
+                min_filter: wgpu::FilterMode::Linear,
+                ..Default::default()
+            },
+            imgui_wgpu::TextureDescriptor {
+                label: Some("savestate thumbnail texture".to_string()),
+                size: wgpu::Extent3d {
+                    width: savestates::THUMBNAIL_WIDTH as u32,
+                    height: savestates::THUMBNAIL_HEIGHT as u32,
+                    depth_or_array_layers: 1,
+                },
+                format: Some(fb_texture_format),
+                ..Default::default()
+            },
+        );
+        window_builder.window.gfx.imgui.add_texture(texture)
+    };
+
     let mut state = UiState {
         game_db,
 
@@ -847,6 +1011,12 @@ pub fn main() {
         fps_fixed: None,
         fb_texture_id,
 
+        savestate_slot: 0,
+        savestate_slots: Vec::new(),
+        savestates_menu_open: false,
+        savestate_thumbnail_texture_id,
+        savestate_thumbnail_slot: None,
+
         #[cfg(feature = "debug-views")]
         debug_views: debug_views::UiState::new(),
 
@@ -917,12 +1087,30 @@ pub fn main() {
                         .debug_views
                         .update_from_frame_data(&frame.debug, window);
 
+                    let fb_width = window
+                        .gfx
+                        .imgui
+                        .texture(state.fb_texture_id)
+                        .texture_desc()
+                        .size
+                        .width;
+                    if fb_width != SCREEN_WIDTH as u32 * frame.fb_scale as u32 {
+                        resize_fb_texture(state.fb_texture_id, window, frame.fb_scale);
+                    }
+
                     let fb_texture = window.gfx.imgui.texture_mut(state.fb_texture_id);
                     let data = unsafe {
-                        slice::from_raw_parts(
-                            frame.fb.0.as_ptr() as *const u8,
-                            SCREEN_WIDTH * SCREEN_HEIGHT * 8,
-                        )
+                        if frame.fb_scale == 1 {
+                            slice::from_raw_parts(
+                                frame.fb.0.as_ptr() as *const u8,
+                                SCREEN_WIDTH * SCREEN_HEIGHT * 8,
+                            )
+                        } else {
+                            slice::from_raw_parts(
+                                frame.scaled_fb.as_ptr() as *const u8,
+                                frame.scaled_fb.len() * 4,
+                            )
+                        }
                     };
                     fb_texture.set_data(
                         &window.gfx.device_state.queue,
@@ -962,9 +1150,34 @@ pub fn main() {
                     input::Action::ToggleFramerateLimit => {
                         state.toggle_framerate_limit(!state.current_config.limit_framerate.value)
                     }
+                    input::Action::SaveState => state.save_state(state.savestate_slot),
+                    input::Action::LoadState => state.load_state(state.savestate_slot),
+                    input::Action::NextStateSlot => {
+                        state.savestate_slot = (state.savestate_slot + 1) % savestates::SLOTS;
+                    }
+                    input::Action::PrevStateSlot => {
+                        state.savestate_slot =
+                            (state.savestate_slot + savestates::SLOTS - 1) % savestates::SLOTS;
+                    }
+                    input::Action::Rewind => {}
+                    input::Action::RecordMovie => state.record_movie(false),
+                    input::Action::PlayMovie => state.play_movie(),
+                    input::Action::StopMovie => state.stop_movie(),
+                    input::Action::BlowMic => {}
                 }
             }
 
+            if let Some(emu) = &state.emu_state {
+                emu.shared_state.rewinding.store(
+                    state.input.hotkey_held(input::Action::Rewind),
+                    Ordering::Relaxed,
+                );
+                emu.shared_state.mic_blowing.store(
+                    state.input.hotkey_held(input::Action::BlowMic),
+                    Ordering::Relaxed,
+                );
+            }
+
             if let Some(changes) = emu_input_changes {
                 if let Some(emu) = &mut state.emu_state {
                     if emu.playing {
@@ -1045,6 +1258,117 @@ pub fn main() {
                         }
                     });
 
+                    let mut savestates_menu_open = false;
+                    ui.menu_with_enabled(
+                        "Savestates",
+                        state
+                            .emu_state
+                            .as_ref()
+                            .map_or(false, |emu| emu.savestate_dir.is_some()),
+                        || {
+                            savestates_menu_open = true;
+                            if !state.savestates_menu_open {
+                                state.refresh_savestate_slots();
+                            }
+
+                            let mut cur_slot = state.savestate_slot as u32 + 1;
+                            if ui
+                                .slider_config("Current slot", 1, savestates::SLOTS as u32)
+                                .build(&mut cur_slot)
+                            {
+                                state.savestate_slot = cur_slot as u8 - 1;
+                            }
+
+                            ui.separator();
+
+                            for slot in 0..savestates::SLOTS {
+                                let (label, exists) = match &state.savestate_slots[slot as usize] {
+                                    Some(info) => (
+                                        format!(
+                                            "Slot {} - {}",
+                                            slot + 1,
+                                            info.timestamp.format("%Y-%m-%d %H:%M:%S")
+                                        ),
+                                        true,
+                                    ),
+                                    None => (format!("Slot {} - Empty", slot + 1), false),
+                                };
+                                ui.menu(&label, || {
+                                    if let Some(info) = &state.savestate_slots[slot as usize] {
+                                        if state.savestate_thumbnail_slot != Some(slot) {
+                                            state.savestate_thumbnail_slot = Some(slot);
+                                            let data = unsafe {
+                                                slice::from_raw_parts(
+                                                    info.thumbnail.as_ptr() as *const u8,
+                                                    mem::size_of::<savestates::Thumbnail>(),
+                                                )
+                                            };
+                                            window
+                                                .gfx
+                                                .imgui
+                                                .texture_mut(state.savestate_thumbnail_texture_id)
+                                                .set_data(
+                                                    &window.gfx.device_state.queue,
+                                                    data,
+                                                    imgui_wgpu::TextureRange::default(),
+                                                );
+                                        }
+                                        imgui::Image::new(
+                                            state.savestate_thumbnail_texture_id,
+                                            [
+                                                savestates::THUMBNAIL_WIDTH as f32,
+                                                savestates::THUMBNAIL_HEIGHT as f32,
+                                            ],
+                                        )
+                                        .build(ui);
+                                    }
+
+                                    if ui.menu_item("Save") {
+                                        state.save_state(slot);
+                                    }
+
+                                    if ui.menu_item_config("Load").enabled(exists).build() {
+                                        state.load_state(slot);
+                                    }
+
+                                    if ui.menu_item_config("Delete").enabled(exists).build() {
+                                        state.delete_state(slot);
+                                    }
+                                });
+                            }
+                        },
+                    );
+                    state.savestates_menu_open = savestates_menu_open;
+
+                    ui.menu_with_enabled(
+                        "Movie",
+                        state
+                            .emu_state
+                            .as_ref()
+                            .map_or(false, |emu| emu.movie_dir.is_some()),
+                        || {
+                            let movie_active = state.emu_state.as_ref().map_or(false, |emu| {
+                                emu.shared_state.movie_active.load(Ordering::Relaxed)
+                            });
+
+                            if ui.menu_item("Record from power-on") {
+                                state.record_movie(true);
+                            }
+
+                            if ui.menu_item("Record from current state") {
+                                state.record_movie(false);
+                            }
+
+                            if ui.menu_item("Play...") {
+                                state.play_movie();
+                            }
+
+                            if ui.menu_item_config("Stop").enabled(movie_active).build() {
+                                state.stop_movie();
+                            }
+                        },
+                    );
+
                     ui.menu("Config", || {
                         ui.menu("Audio volume", || {
                             let mut volume = state.current_config.audio_volume.value * 100.0;
@@ -1325,6 +1649,21 @@ pub fn main() {
                                 if separator_needed {
                                     ui.separator();
                                 }
+                                if ui
+                                    .menu_item_config("Capture 3D frame...")
+                                    .enabled(state.emu_state.is_some())
+                                    .build()
+                                {
+                                    state.capture_gx_frame();
+                                }
+                                if ui
+                                    .menu_item_config("Export 3D scene...")
+                                    .enabled(state.emu_state.is_some())
+                                    .build()
+                                {
+                                    state.export_3d_scene();
+                                }
+                                ui.separator();
                                 state.debug_views.render_menu(ui, window);
                             }
                         });
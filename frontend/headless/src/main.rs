@@ -0,0 +1,415 @@
+#![feature(try_blocks)]
+
+mod renderer_3d;
+mod script;
+
+use dust_core::{
+    audio::DummyBackend as DummyAudioBackend,
+    cpu::{arm7, arm9, interpreter::Interpreter},
+    ds_slot,
+    emu::{Emu, RunOutput},
+    flash::Flash,
+    gpu::{
+        engine_3d::{capture::Capture, Renderer as _},
+        rgb_18_to_rgba_32, SCREEN_HEIGHT, SCREEN_WIDTH,
+    },
+    mic::DummyBackend as DummyMicBackend,
+    rtc::DummyBackend as DummyRtcBackend,
+    spi::firmware,
+    utils::{zeroed_box, BoxedByteSlice, Bytes},
+    Model, SaveContents,
+};
+use sha2::{Digest, Sha256};
+use std::{
+    env, fmt,
+    fs::{self, File},
+    io::BufWriter,
+    path::PathBuf,
+    process,
+};
+
+static USAGE: &str = "\
+Usage: dust-headless <ROM> [options]
+       dust-headless --replay-gx <CAPTURE> [--png <PATH>]
+
+Options:
+    --frames <N>          Number of frames to run (default: 60)
+    --input <PATH>        Input script to play back
+    --png <PATH>          Write the final framebuffer to a PNG file
+    --capture-gx <PATH>   Capture the 3D geometry command stream of the last complete frame
+    --replay-gx <PATH>    Render a captured 3D frame instead of running a ROM
+    --arm7-bios <PATH>    ARM7 BIOS image (the HLE BIOS is used if missing)
+    --arm9-bios <PATH>    ARM9 BIOS image (the HLE BIOS is used if missing)
+    --firmware <PATH>     Firmware image (a generated one is used if missing)
+    --model <MODEL>       ds, lite, ique, ique-lite or dsi (default: lite)
+    --boot-firmware       Boot through the firmware instead of booting the ROM directly
+
+Prints the SHA-256 hash of the final framebuffer (both screens, top first, as RGBA8) to stdout,
+or of the rendered 3D frame when replaying a capture.";
+
+struct Args {
+    rom_path: PathBuf,
+    frames: u64,
+    input_path: Option<PathBuf>,
+    png_path: Option<PathBuf>,
+    capture_gx_path: Option<PathBuf>,
+    arm7_bios_path: Option<PathBuf>,
+    arm9_bios_path: Option<PathBuf>,
+    firmware_path: Option<PathBuf>,
+    model: Model,
+    direct_boot: bool,
+}
+
+struct ArgsError(String);
+
+impl fmt::Display for ArgsError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+enum Mode {
+    Help,
+    Run(Args),
+    ReplayGx {
+        capture_path: PathBuf,
+        png_path: Option<PathBuf>,
+    },
+}
+
+fn parse_args() -> Result<Mode, ArgsError> {
+    let mut args = env::args_os().skip(1);
+
+    let mut rom_path = None;
+    let mut frames = 60;
+    let mut input_path = None;
+    let mut png_path = None;
+    let mut capture_gx_path = None;
+    let mut replay_gx_path = None;
+    let mut arm7_bios_path = None;
+    let mut arm9_bios_path = None;
+    let mut firmware_path = None;
+    let mut model = Model::Lite;
+    let mut direct_boot = true;
+
+    macro_rules! value {
+        ($name: expr) => {
+            args.next()
+                .ok_or_else(|| ArgsError(format!("Missing value for {}", $name)))?
+        };
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.to_str() {
+            Some("--frames") => {
+                frames = value!("--frames")
+                    .to_str()
+                    .and_then(|value| value.parse().ok())
+                    .ok_or_else(|| ArgsError("Invalid frame count".to_string()))?;
+            }
+            Some("--input") => input_path = Some(PathBuf::from(value!("--input"))),
+            Some("--png") => png_path = Some(PathBuf::from(value!("--png"))),
+            Some("--capture-gx") => capture_gx_path = Some(PathBuf::from(value!("--capture-gx"))),
+            Some("--replay-gx") => replay_gx_path = Some(PathBuf::from(value!("--replay-gx"))),
+            Some("--arm7-bios") => arm7_bios_path = Some(PathBuf::from(value!("--arm7-bios"))),
+            Some("--arm9-bios") => arm9_bios_path = Some(PathBuf::from(value!("--arm9-bios"))),
+            Some("--firmware") => firmware_path = Some(PathBuf::from(value!("--firmware"))),
+            Some("--model") => {
+                model = match value!("--model").to_str() {
+                    Some("ds") => Model::Ds,
+                    Some("lite") => Model::Lite,
+                    Some("ique") => Model::Ique,
+                    Some("ique-lite") => Model::IqueLite,
+                    Some("dsi") => Model::Dsi,
+                    _ => return Err(ArgsError("Invalid model".to_string())),
+                };
+            }
+            Some("--boot-firmware") => direct_boot = false,
+            Some("-h" | "--help") => return Ok(Mode::Help),
+            Some(option) if option.starts_with("--") => {
+                return Err(ArgsError(format!("Unknown option: {}", option)));
+            }
+            _ => {
+                if rom_path.is_some() {
+                    return Err(ArgsError("Only one ROM can be specified".to_string()));
+                }
+                rom_path = Some(PathBuf::from(arg));
+            }
+        }
+    }
+
+    if let Some(capture_path) = replay_gx_path {
+        if rom_path.is_some() {
+            return Err(ArgsError(
+                "A ROM can't be specified when replaying a GX capture".to_string(),
+            ));
+        }
+        return Ok(Mode::ReplayGx {
+            capture_path,
+            png_path,
+        });
+    }
+
+    Ok(Mode::Run(Args {
+        rom_path: rom_path.ok_or_else(|| ArgsError("No ROM specified".to_string()))?,
+        frames,
+        input_path,
+        png_path,
+        capture_gx_path,
+        arm7_bios_path,
+        arm9_bios_path,
+        firmware_path,
+        model,
+        direct_boot,
+    }))
+}
+
+fn fail(message: fmt::Arguments) -> ! {
+    eprintln!("{}", message);
+    process::exit(1);
+}
+
+fn read_file(path: &PathBuf, description: &str) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|err| {
+        fail(format_args!(
+            "Couldn't read {} at `{}`: {}",
+            description,
+            path.display(),
+            err
+        ))
+    })
+}
+
+fn read_bios<const LEN: usize>(
+    path: &Option<PathBuf>,
+    description: &str,
+) -> Option<Box<Bytes<LEN>>> {
+    path.as_ref().map(|path| {
+        let contents = read_file(path, description);
+        if contents.len() != LEN {
+            fail(format_args!(
+                "Invalid {} size: expected {} B, got {} B",
+                description,
+                LEN,
+                contents.len()
+            ));
+        }
+        let mut bios = zeroed_box::<Bytes<LEN>>();
+        bios.copy_from_slice(&contents);
+        bios
+    })
+}
+
+#[cfg(feature = "log")]
+fn build_logger() -> slog::Logger {
+    use slog::Drain;
+    let decorator = slog_term::TermDecorator::new().stderr().build();
+    let drain = slog_term::CompactFormat::new(decorator).build().fuse();
+    let drain = slog_async::Async::new(drain).build().fuse();
+    slog::Logger::root(drain, slog::o!())
+}
+
+fn build_emu(args: &Args) -> Emu<Interpreter> {
+    #[cfg(feature = "log")]
+    let logger = build_logger();
+
+    let arm7_bios = read_bios::<{ arm7::BIOS_SIZE }>(&args.arm7_bios_path, "ARM7 BIOS");
+    let arm9_bios = read_bios::<{ arm9::BIOS_SIZE }>(&args.arm9_bios_path, "ARM9 BIOS");
+    let firmware = match &args.firmware_path {
+        Some(path) => {
+            let contents = read_file(path, "firmware");
+            let mut firmware = BoxedByteSlice::new_zeroed(contents.len());
+            firmware.copy_from_slice(&contents);
+            firmware
+        }
+        None => firmware::default(args.model),
+    };
+    let rom = {
+        let contents = read_file(&args.rom_path, "ROM");
+        let mut rom = BoxedByteSlice::new_zeroed(contents.len().next_power_of_two());
+        rom[..contents.len()].copy_from_slice(&contents);
+        rom
+    };
+
+    let ds_slot_rom = ds_slot::rom::normal::Normal::new(
+        rom,
+        arm7_bios.as_deref(),
+        #[cfg(feature = "log")]
+        logger.new(slog::o!("ds_rom" => "normal")),
+    )
+    .unwrap_or_else(|_| fail(format_args!("Couldn't load ROM")))
+    .into();
+
+    let mut emu_builder = dust_core::emu::Builder::new(
+        Flash::new(
+            SaveContents::Existing(firmware),
+            firmware::id_for_model(args.model),
+            #[cfg(feature = "log")]
+            logger.new(slog::o!("fw" => "")),
+        )
+        .unwrap_or_else(|_| fail(format_args!("Couldn't build firmware"))),
+        ds_slot_rom,
+        ds_slot::spi::Empty::new(
+            #[cfg(feature = "log")]
+            logger.new(slog::o!("ds_spi" => "empty")),
+        )
+        .into(),
+        Box::new(DummyAudioBackend),
+        Box::new(DummyMicBackend),
+        Box::new(DummyRtcBackend),
+        Box::new(renderer_3d::Renderer::new()),
+        #[cfg(feature = "log")]
+        logger.clone(),
+    );
+
+    let hle_bios_enabled = arm7_bios.is_none() || arm9_bios.is_none();
+    emu_builder.arm7_bios = arm7_bios;
+    emu_builder.arm9_bios = arm9_bios;
+    emu_builder.model = args.model;
+    emu_builder.direct_boot = args.direct_boot || hle_bios_enabled;
+
+    emu_builder
+        .build(Interpreter)
+        .unwrap_or_else(|err| fail(format_args!("Couldn't build emulator: {}", err)))
+}
+
+fn framebuffer_rgba(emu: &Emu<Interpreter>) -> Vec<u8> {
+    let mut data = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 8);
+    for screen in &emu.gpu.framebuffer.0 {
+        for pixel in screen {
+            data.extend_from_slice(&pixel.to_le_bytes());
+        }
+    }
+    data
+}
+
+fn replay_gx(capture_path: &PathBuf) -> Vec<u8> {
+    let capture = Capture::from_bytes(&read_file(capture_path, "GX capture"))
+        .unwrap_or_else(|err| fail(format_args!("Couldn't load GX capture: {}", err)));
+    let mut renderer = capture
+        .replay(
+            Box::new(renderer_3d::Renderer::new()),
+            #[cfg(feature = "log")]
+            build_logger(),
+        )
+        .unwrap_or_else(|err| fail(format_args!("Couldn't replay GX capture: {}", err)));
+
+    renderer.start_frame();
+    let mut data = Vec::with_capacity(SCREEN_WIDTH * SCREEN_HEIGHT * 4);
+    for _ in 0..SCREEN_HEIGHT {
+        for pixel in &renderer.read_scanline().0 {
+            data.extend_from_slice(&rgb_18_to_rgba_32(*pixel).to_le_bytes());
+        }
+    }
+    data
+}
+
+fn write_png(path: &PathBuf, data: &[u8], height: u32) -> Result<(), png::EncodingError> {
+    let mut encoder = png::Encoder::new(
+        BufWriter::new(File::create(path)?),
+        SCREEN_WIDTH as u32,
+        height,
+    );
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.write_header()?.write_image_data(data)
+}
+
+fn output(data: &[u8], height: u32, png_path: Option<&PathBuf>) {
+    if let Some(path) = png_path {
+        if let Err(err) = write_png(path, data, height) {
+            fail(format_args!(
+                "Couldn't write PNG to `{}`: {}",
+                path.display(),
+                err
+            ));
+        }
+    }
+
+    let hash = Sha256::digest(data);
+    println!(
+        "{}",
+        hash.iter()
+            .fold(String::with_capacity(64), |mut acc, byte| {
+                use std::fmt::Write;
+                let _ = write!(acc, "{:02x}", byte);
+                acc
+            })
+    );
+}
+
+fn main() {
+    let args = match parse_args() {
+        Ok(Mode::Help) => {
+            println!("{}", USAGE);
+            return;
+        }
+        Ok(Mode::Run(args)) => args,
+        Ok(Mode::ReplayGx {
+            capture_path,
+            png_path,
+        }) => {
+            let data = replay_gx(&capture_path);
+            output(&data, SCREEN_HEIGHT as u32, png_path.as_ref());
+            return;
+        }
+        Err(err) => fail(format_args!("{}\n\n{}", err, USAGE)),
+    };
+
+    let events = match &args.input_path {
+        Some(path) => {
+            let script = String::from_utf8(read_file(path, "input script")).unwrap_or_else(|_| {
+                fail(format_args!(
+                    "Input script at `{}` is not valid UTF-8",
+                    path.display()
+                ))
+            });
+            script::parse(&script)
+                .unwrap_or_else(|err| fail(format_args!("Invalid input script: {}", err)))
+        }
+        None => Vec::new(),
+    };
+
+    let mut emu = build_emu(&args);
+
+    let mut events = events.into_iter().peekable();
+    for frame in 0..args.frames {
+        // Start capturing at the end of the second-to-last frame, so that the capture covers the
+        // last complete one
+        if args.capture_gx_path.is_some() && frame + 2 == args.frames {
+            emu.gpu.engine_3d.request_capture();
+        }
+
+        while let Some(event) = events.next_if(|event| event.frame <= frame) {
+            match event.command {
+                script::Command::Press(keys) => emu.press_keys(keys),
+                script::Command::Release(keys) => emu.release_keys(keys),
+                script::Command::Touch(pos) => emu.set_touch_pos(pos),
+                script::Command::EndTouch => emu.end_touch(),
+            }
+        }
+
+        if emu.run() == RunOutput::Shutdown {
+            eprintln!("Emulator shut down after {} frames", frame + 1);
+            break;
+        }
+    }
+
+    if let Some(path) = &args.capture_gx_path {
+        let capture = emu
+            .gpu
+            .engine_3d
+            .take_capture()
+            .unwrap_or_else(|| fail(format_args!("No complete 3D frame was captured")));
+        if let Err(err) = fs::write(path, capture.to_bytes()) {
+            fail(format_args!(
+                "Couldn't write GX capture to `{}`: {}",
+                path.display(),
+                err
+            ));
+        }
+    }
+
+    let data = framebuffer_rgba(&emu);
+    output(&data, SCREEN_HEIGHT as u32 * 2, args.png_path.as_ref());
+}
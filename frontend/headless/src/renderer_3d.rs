@@ -0,0 +1,81 @@
+use dust_core::{
+    gpu::{
+        engine_3d::{
+            Polygon, Renderer as RendererTrait, RenderingState as CoreRenderingState, ScreenVertex,
+        },
+        Scanline, SCREEN_HEIGHT,
+    },
+    utils::{zeroed_box, Bytes},
+};
+use dust_soft_3d::{RawRenderer, RenderingData};
+use std::mem::transmute;
+
+// Renders whole frames synchronously on swap, so that the output doesn't depend on thread timing
+pub struct Renderer {
+    next_scanline: u8,
+    raw_renderer: RawRenderer,
+    rendering_data: Box<RenderingData>,
+    scanline_buffer: Box<[Scanline<u32, 256>; SCREEN_HEIGHT]>,
+}
+
+impl Renderer {
+    pub fn new() -> Self {
+        Renderer {
+            next_scanline: 0,
+            raw_renderer: RawRenderer::new(),
+            rendering_data: unsafe { transmute(zeroed_box::<RenderingData>()) },
+            scanline_buffer: unsafe {
+                transmute(zeroed_box::<[Scanline<u32, 256>; SCREEN_HEIGHT]>())
+            },
+        }
+    }
+
+    fn render_frame(&mut self) {
+        self.raw_renderer.start_frame(&self.rendering_data);
+        for (y, scanline) in self.scanline_buffer.iter_mut().enumerate() {
+            self.raw_renderer
+                .render_line(y as u8, scanline, &self.rendering_data);
+        }
+    }
+}
+
+impl RendererTrait for Renderer {
+    fn swap_buffers(
+        &mut self,
+        texture: &Bytes<0x8_0000>,
+        tex_pal: &Bytes<0x1_8000>,
+        vert_ram: &[ScreenVertex],
+        poly_ram: &[Polygon],
+        state: &CoreRenderingState,
+        w_buffering: bool,
+    ) {
+        self.rendering_data
+            .prepare(texture, tex_pal, vert_ram, poly_ram, state, w_buffering);
+        self.render_frame();
+    }
+
+    fn repeat_last_frame(
+        &mut self,
+        texture: &Bytes<0x8_0000>,
+        tex_pal: &Bytes<0x1_8000>,
+        state: &CoreRenderingState,
+    ) {
+        self.rendering_data
+            .repeat_last_frame(texture, tex_pal, state);
+        self.render_frame();
+    }
+
+    fn start_frame(&mut self) {
+        self.next_scanline = 0;
+    }
+
+    fn read_scanline(&mut self) -> &Scanline<u32, 256> {
+        let result = &self.scanline_buffer[self.next_scanline as usize];
+        self.next_scanline += 1;
+        result
+    }
+
+    fn skip_scanline(&mut self) {
+        self.next_scanline += 1;
+    }
+}
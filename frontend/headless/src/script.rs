@@ -0,0 +1,109 @@
+use dust_core::emu::input::Keys;
+use std::fmt;
+
+static KEY_IDENTS: &[(Keys, &str)] = &[
+    (Keys::A, "a"),
+    (Keys::B, "b"),
+    (Keys::X, "x"),
+    (Keys::Y, "y"),
+    (Keys::L, "l"),
+    (Keys::R, "r"),
+    (Keys::START, "start"),
+    (Keys::SELECT, "select"),
+    (Keys::RIGHT, "right"),
+    (Keys::LEFT, "left"),
+    (Keys::UP, "up"),
+    (Keys::DOWN, "down"),
+    (Keys::DEBUG, "debug"),
+];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Command {
+    Press(Keys),
+    Release(Keys),
+    Touch([u16; 2]),
+    EndTouch,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Event {
+    pub frame: u64,
+    pub command: Command,
+}
+
+#[derive(Debug)]
+pub struct ParseError {
+    pub line: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+fn parse_keys(idents: &str) -> Result<Keys, String> {
+    let mut keys = Keys::empty();
+    for ident in idents.split(',') {
+        match KEY_IDENTS.iter().find(|(_, name)| *name == ident) {
+            Some((key, _)) => keys |= *key,
+            None => return Err(format!("unknown key `{}`", ident)),
+        }
+    }
+    Ok(keys)
+}
+
+fn parse_coord(value: Option<&str>, max: u16) -> Result<u16, String> {
+    let value = value.ok_or_else(|| "missing touch coordinate".to_string())?;
+    match value.parse::<u16>() {
+        Ok(value) if value <= max => Ok(value),
+        _ => Err(format!("invalid touch coordinate `{}`", value)),
+    }
+}
+
+/// Parses an input script, where each non-empty line not starting with `#` has the form:
+/// - `<frame> press <key>[,<key>...]`
+/// - `<frame> release <key>[,<key>...]`
+/// - `<frame> touch <x> <y>` (in touchscreen ADC units, `0..=4095` and `0..=3072`)
+/// - `<frame> end-touch`
+///
+/// Events are applied right before running the given frame (with the first frame being 0); the
+/// returned list is sorted by frame, preserving the script's order within each frame.
+pub fn parse(script: &str) -> Result<Vec<Event>, ParseError> {
+    let mut events = Vec::new();
+    for (i, line) in script.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let result: Result<Event, String> = try {
+            let mut parts = line.split_whitespace();
+            let frame = parts
+                .next()
+                .and_then(|frame| frame.parse::<u64>().ok())
+                .ok_or_else(|| "invalid frame number".to_string())?;
+            let command = match parts.next() {
+                Some("press") => Command::Press(parse_keys(parts.next().unwrap_or_default())?),
+                Some("release") => Command::Release(parse_keys(parts.next().unwrap_or_default())?),
+                Some("touch") => Command::Touch([
+                    parse_coord(parts.next(), 4095)?,
+                    parse_coord(parts.next(), 3072)?,
+                ]),
+                Some("end-touch") => Command::EndTouch,
+                Some(command) => Err(format!("unknown command `{}`", command))?,
+                None => Err("missing command".to_string())?,
+            };
+            if parts.next().is_some() {
+                Err("trailing arguments".to_string())?;
+            }
+            Event { frame, command }
+        };
+        events.push(result.map_err(|message| ParseError {
+            line: i + 1,
+            message,
+        })?);
+    }
+    events.sort_by_key(|event| event.frame);
+    Ok(events)
+}
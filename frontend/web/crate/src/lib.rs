@@ -111,6 +111,7 @@ impl EmuState {
             match emu.ds_slot.rom {
                 DsSlotRom::Empty(device) => DsSlotRom::Empty(device.reset()),
                 DsSlotRom::Normal(device) => DsSlotRom::Normal(device.reset()),
+                DsSlotRom::Nand(device) => DsSlotRom::Nand(device.reset()),
             },
             match emu.ds_slot.spi {
                 DsSlotSpi::Empty(device) => DsSlotSpi::Empty(device.reset()),
@@ -119,6 +120,7 @@ impl EmuState {
                 DsSlotSpi::Flash(device) => DsSlotSpi::Flash(device.reset()),
             },
             emu.audio.backend,
+            emu.spi.tsc.mic.backend,
             emu.rtc.backend,
             emu.gpu.engine_3d.renderer,
             #[cfg(feature = "log")]
@@ -135,11 +137,11 @@ impl EmuState {
     }
 
     pub fn load_save(&mut self, ram_arr: Uint8Array) {
-        ram_arr.copy_to(&mut self.emu.as_mut().unwrap().ds_slot.spi.contents_mut()[..])
+        ram_arr.copy_to(&mut self.emu.as_mut().unwrap().ds_slot.save_contents_mut()[..])
     }
 
     pub fn export_save(&self) -> Uint8Array {
-        Uint8Array::from(&self.emu.as_ref().unwrap().ds_slot.spi.contents()[..])
+        Uint8Array::from(&self.emu.as_ref().unwrap().ds_slot.save_contents()[..])
     }
 
     pub fn update_input(&mut self, pressed: u32, released: u32) {
@@ -365,6 +367,7 @@ pub fn create_emu_state(
         ds_slot_rom,
         ds_slot_spi,
         Box::new(audio::Backend::new(audio_callback)),
+        Box::new(dust_core::mic::DummyBackend),
         Box::new(dust_core::rtc::DummyBackend),
         Box::new(renderer_3d::EmuState::new()),
         #[cfg(feature = "log")]
@@ -1,7 +1,7 @@
 #![feature(portable_simd, const_mut_refs, const_trait_impl)]
 
 mod render;
-pub use render::Renderer as RawRenderer;
+pub use render::{Renderer as RawRenderer, MAX_SCALE};
 
 use dust_core::{
     gpu::{
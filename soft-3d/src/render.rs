@@ -1,5 +1,5 @@
 mod utils;
-use utils::{dec_poly_vert_index, inc_poly_vert_index, Edge, InterpLineData};
+use utils::{dec_poly_vert_index, inc_poly_vert_index, scaled_coords, Edge, InterpLineData};
 
 use super::RenderingData;
 use dust_core::{
@@ -7,9 +7,9 @@ use dust_core::{
         engine_3d::{
             Color, InterpColor, PolyAddr, PolyVertIndex, PolygonAttrs, TexCoords, TextureParams,
         },
-        Scanline,
+        Scanline, SCREEN_HEIGHT,
     },
-    utils::{zeroed_box, Zero},
+    utils::Zero,
 };
 
 type DepthTestFn = fn(u32, u32, PixelAttrs) -> bool;
@@ -21,9 +21,9 @@ struct RenderingPolygon {
     attrs: PolygonAttrs,
     tex_params: TextureParams,
     tex_palette_base: u16,
-    top_y: u8,
-    bot_y: u8,
-    height: u8,
+    top_y: u16,
+    bot_y: u16,
+    height: u16,
     edges: [Edge; 2],
     l_vert_i: PolyVertIndex,
     r_vert_i: PolyVertIndex,
@@ -31,6 +31,8 @@ struct RenderingPolygon {
     alpha: u8,
     id: u8,
     is_front_facing: bool,
+    is_shadow_mask: bool,
+    is_shadow: bool,
     depth_test: DepthTestFn,
     process_pixel: ProcessPixelFn,
 }
@@ -44,6 +46,8 @@ proc_bitfield::bitfield! {
         pub right_edge: bool @ 2,
         pub left_edge: bool @ 3,
 
+        pub coverage: u8 @ 8..=12,
+
         pub translucent: bool @ 13,
         pub back_facing: bool @ 14,
 
@@ -60,7 +64,10 @@ impl PixelAttrs {
     }
 
     fn from_translucent_poly_attrs(poly: &RenderingPolygon, opaque: PixelAttrs) -> Self {
-        PixelAttrs(opaque.0 & 0x3F00_8000)
+        // Translucent pixels keep the edge flags of the opaque pixel below them, and only keep fog
+        // enabled if both the new and the previous pixel use it
+        PixelAttrs(opaque.0 & 0x3F00_800F)
+            .with_fog_enabled(opaque.fog_enabled() && poly.attrs.fog_enabled())
             .with_translucent(true)
             .with_back_facing(!poly.is_front_facing)
             .with_translucent_id(poly.id | 0x40)
@@ -360,25 +367,73 @@ static PROCESS_PIXEL_TEXTURES_DISABLED: [ProcessPixelFn; 4] = [
     process_pixel::<0, 3>,
 ];
 
+fn pack_color(color: Color) -> u32 {
+    let [r, g, b, a] = color.to_array();
+    r as u32 | (g as u32) << 6 | (b as u32) << 12 | (a as u32) << 18
+}
+
+pub const MAX_SCALE: u8 = 4;
+
 pub struct Renderer {
-    color_buffer: Box<[Color; 256]>,
-    depth_buffer: Box<[u32; 256]>,
-    attr_buffer: Box<[PixelAttrs; 256]>,
+    // Integer factor the internal resolution is scaled by; all buffers are `256 * scale` pixels
+    // wide and `SCREEN_HEIGHT * scale` pixels tall, stored in row-major order
+    scale: u8,
+    color_buffer: Box<[Color]>,
+    // Color of the pixels right below the topmost opaque ones, used for antialiasing
+    below_color_buffer: Box<[Color]>,
+    depth_buffer: Box<[u32]>,
+    attr_buffer: Box<[PixelAttrs]>,
     polys: Vec<RenderingPolygon>,
+    next_rasterized_line: u8,
 }
 
 impl Renderer {
     pub fn new() -> Self {
+        Self::with_scale(1)
+    }
+
+    /// Creates a renderer that rasterizes at `scale` times the native resolution, making use of
+    /// the vertices' sub-pixel precision.
+    ///
+    /// # Panics
+    /// Panics if `scale` is 0 or greater than [`MAX_SCALE`].
+    pub fn with_scale(scale: u8) -> Self {
+        assert!(
+            (1..=MAX_SCALE).contains(&scale),
+            "Invalid 3D rendering scale factor: {}",
+            scale
+        );
+        let len = 256 * SCREEN_HEIGHT * (scale as usize * scale as usize);
         Renderer {
-            color_buffer: Box::new([Color::splat(0); 256]),
-            depth_buffer: zeroed_box(),
-            attr_buffer: zeroed_box(),
+            scale,
+            color_buffer: vec![Color::splat(0); len].into_boxed_slice(),
+            below_color_buffer: vec![Color::splat(0); len].into_boxed_slice(),
+            depth_buffer: vec![0; len].into_boxed_slice(),
+            attr_buffer: vec![PixelAttrs(0); len].into_boxed_slice(),
             polys: Vec::with_capacity(2048),
+            next_rasterized_line: 0,
         }
     }
 
+    #[inline]
+    pub fn scale(&self) -> u8 {
+        self.scale
+    }
+
+    #[inline]
+    fn width(&self) -> usize {
+        256 * self.scale as usize
+    }
+
+    #[inline]
+    fn height(&self) -> usize {
+        SCREEN_HEIGHT * self.scale as usize
+    }
+
     pub fn start_frame(&mut self, rendering_data: &RenderingData) {
         self.polys.clear();
+        self.next_rasterized_line = 0;
+        let scale = self.scale;
 
         for poly_addr in 0..rendering_data.poly_ram_level {
             let poly_addr = unsafe { PolyAddr::new_unchecked(poly_addr) };
@@ -414,12 +469,15 @@ impl Renderer {
                 |a, b, _b_attrs| a < b
             };
 
-            let process_pixel = if poly.attrs.mode() == 3 {
-                // TODO: Shadow polygons
-                process_pixel::<0, 0>
-            } else {
+            // Shadow masks (polygon ID 0) only update the stencil buffer, while shadow polygons
+            // are drawn like modulated ones wherever the stencil buffer is set
+            let is_shadow_mask = poly.attrs.mode() == 3 && poly.attrs.id() == 0;
+            let is_shadow = poly.attrs.mode() == 3 && !is_shadow_mask;
+
+            let process_pixel = {
                 let mode = match poly.attrs.mode() {
                     2 => 2 + rendering_data.control.highlight_shading_enabled() as u8,
+                    3 => 0,
                     mode => mode,
                 };
                 if rendering_data.control.texture_mapping_enabled() {
@@ -429,8 +487,19 @@ impl Renderer {
                 }
             };
 
-            let top_y = poly.top_y;
-            let bot_y = poly.bot_y;
+            let (top_y, bot_y) = if scale == 1 {
+                (poly.top_y as u16, poly.bot_y as u16)
+            } else {
+                poly.vertices[..poly.vertices_len.get() as usize]
+                    .iter()
+                    .fold((u16::MAX, 0), |(top_y, bot_y), vert_addr| {
+                        let y = scaled_coords(
+                            &rendering_data.vert_ram[vert_addr.get() as usize],
+                            scale,
+                        )[1];
+                        (top_y.min(y), bot_y.max(y))
+                    })
+            };
 
             if top_y == bot_y {
                 let mut top_i = PolyVertIndex::new(0);
@@ -445,12 +514,13 @@ impl Renderer {
                 ] {
                     let vert_addr = poly.vertices[i.get() as usize];
                     let vert = &rendering_data.vert_ram[vert_addr.get() as usize];
-                    if vert.coords[0] < top_vert.coords[0] {
+                    let x = scaled_coords(vert, scale)[0];
+                    if x < scaled_coords(top_vert, scale)[0] {
                         top_i = i;
                         top_vert_addr = vert_addr;
                         top_vert = vert;
                     }
-                    if vert.coords[0] > bot_vert.coords[0] {
+                    if x > scaled_coords(bot_vert, scale)[0] {
                         bot_i = i;
                         bot_vert_addr = vert_addr;
                         bot_vert = vert;
@@ -462,15 +532,18 @@ impl Renderer {
                     attrs: poly.attrs,
                     tex_params: poly.tex_params,
                     tex_palette_base: poly.tex_palette_base,
-                    top_y: poly.top_y,
-                    bot_y: poly.bot_y,
+                    top_y,
+                    bot_y,
                     height: 1,
                     alpha: poly.attrs.alpha(),
                     id: poly.attrs.id(),
                     is_front_facing: poly.is_front_facing,
+                    is_shadow_mask,
+                    is_shadow,
                     edges: [
                         Edge::new(
                             poly,
+                            scale,
                             top_i,
                             top_vert_addr,
                             top_vert,
@@ -480,6 +553,7 @@ impl Renderer {
                         ),
                         Edge::new(
                             poly,
+                            scale,
                             bot_i,
                             bot_vert_addr,
                             bot_vert,
@@ -503,11 +577,12 @@ impl Renderer {
                         let i = PolyVertIndex::new(i as u8);
                         let vert_addr = poly.vertices[i.get() as usize];
                         let vert = &rendering_data.vert_ram[vert_addr.get() as usize];
-                        if vert.coords[1] as u8 == top_y && top_vert.is_none() {
+                        let y = scaled_coords(vert, scale)[1];
+                        if y == top_y && top_vert.is_none() {
                             top_i = i;
                             top_vert = Some((vert_addr, vert));
                         }
-                        if vert.coords[1] as u8 == bot_y {
+                        if y == bot_y {
                             bot_i = i;
                         }
                     }
@@ -533,15 +608,18 @@ impl Renderer {
                     attrs: poly.attrs,
                     tex_params: poly.tex_params,
                     tex_palette_base: poly.tex_palette_base,
-                    top_y: poly.top_y,
-                    bot_y: poly.bot_y,
-                    height: poly.bot_y - poly.top_y,
+                    top_y,
+                    bot_y,
+                    height: bot_y - top_y,
                     alpha: poly.attrs.alpha(),
                     id: poly.attrs.id(),
                     is_front_facing: poly.is_front_facing,
+                    is_shadow_mask,
+                    is_shadow,
                     edges: [
                         Edge::new(
                             poly,
+                            scale,
                             top_i,
                             top_vert_addr,
                             top_vert,
@@ -551,6 +629,7 @@ impl Renderer {
                         ),
                         Edge::new(
                             poly,
+                            scale,
                             top_i,
                             top_vert_addr,
                             top_vert,
@@ -569,54 +648,197 @@ impl Renderer {
         }
     }
 
-    pub fn render_line(
-        &mut self,
-        y: u8,
-        scanline: &mut Scanline<u32, 256>,
-        rendering_data: &RenderingData,
-    ) {
-        scanline.0.fill(0);
+    fn fog_density(rendering_data: &RenderingData, depth: u32) -> u16 {
+        // The density table is extended with a copy of the first entry before it and of the last
+        // one after it, so that the interpolation below always has two entries to work with
+        let density_entry =
+            |i: u32| rendering_data.fog_densities[(i.max(1) - 1).min(0x1F) as usize] as u32;
+
+        let fog_offset = (rendering_data.fog_offset as u32) << 9;
+        let (index, fract) = if depth < fog_offset {
+            (0, 0)
+        } else {
+            // The depth difference is shifted right by 2 and then left by the fog shift; the
+            // result can overflow for big shift values, making fog wrap around, like on hardware
+            let rel_depth = ((depth - fog_offset) >> 2) << rendering_data.control.fog_depth_shift();
+            let index = rel_depth >> 17;
+            if index >= 0x20 {
+                (0x20, 0)
+            } else {
+                (index, rel_depth & 0x1_FFFF)
+            }
+        };
+        let density =
+            (density_entry(index) * (0x2_0000 - fract) + density_entry(index + 1) * fract) >> 17;
+        // The maximum density of 127 is bumped to 128, so that it results in the fog color alone
+        if density >= 127 {
+            128
+        } else {
+            density as u16
+        }
+    }
+
+    fn apply_edge_marking(&mut self, y: usize, rendering_data: &RenderingData) {
+        let width = self.width();
+        let height = self.height();
+        let line_base = y * width;
+
+        // Pixels outside of the screen are treated as part of the rear plane
+        let clear_depth = expand_depth(rendering_data.clear_depth);
+        let neighbor = |y: Option<usize>, x: Option<usize>| match y.zip(x) {
+            Some((y, x)) if y < height && x < width => (
+                self.depth_buffer[y * width + x],
+                self.attr_buffer[y * width + x].opaque_id(),
+            ),
+            _ => (clear_depth, rendering_data.clear_poly_id),
+        };
+
+        let mut marked = [false; 256 * MAX_SCALE as usize];
+        for (x, marked) in marked[..width].iter_mut().enumerate() {
+            let attrs = self.attr_buffer[line_base + x];
+            if attrs.edge_mask() == 0 {
+                continue;
+            }
+            let depth = self.depth_buffer[line_base + x];
+            let id = attrs.opaque_id();
+            *marked = [
+                (y.checked_sub(1), Some(x)),
+                (Some(y + 1), Some(x)),
+                (Some(y), x.checked_sub(1)),
+                (Some(y), Some(x + 1)),
+            ]
+            .into_iter()
+            .any(|(y, x)| {
+                let (other_depth, other_id) = neighbor(y, x);
+                other_id != id && depth < other_depth
+            });
+        }
+
+        for i in (line_base..line_base + width).filter(|&i| marked[i - line_base]) {
+            let attrs = &mut self.attr_buffer[i];
+            let mut color =
+                rgb_5_to_6(rendering_data.edge_colors[attrs.opaque_id() as usize >> 3].cast());
+            color[3] = self.color_buffer[i][3] as u16;
+            self.color_buffer[i] = color.cast();
+            // Edge-marked pixels are always antialiased with half coverage
+            *attrs = attrs.with_coverage(0x10);
+        }
+    }
+
+    fn apply_fog(&mut self, y: usize, rendering_data: &RenderingData) {
+        let fog_color = rgb_5_to_6(rendering_data.fog_color.cast());
+        let only_alpha = rendering_data.control.fog_only_alpha();
+        let width = self.width();
+        for i in y * width..(y + 1) * width {
+            if !self.attr_buffer[i].fog_enabled() {
+                continue;
+            }
+            let density = Self::fog_density(rendering_data, self.depth_buffer[i]);
+            let color = self.color_buffer[i].cast::<u16>();
+            let fogged_color = (fog_color * InterpColor::splat(density)
+                + color * InterpColor::splat(0x80 - density))
+                >> InterpColor::splat(7);
+            self.color_buffer[i] = if only_alpha {
+                let mut color = color;
+                color[3] = fogged_color[3];
+                color
+            } else {
+                fogged_color
+            }
+            .cast();
+        }
+    }
+
+    fn apply_antialiasing(&mut self, y: usize) {
+        let width = self.width();
+        for i in y * width..(y + 1) * width {
+            let attrs = self.attr_buffer[i];
+            // Only the left and right edges of opaque polygons are antialiased
+            if attrs.translucent() || attrs.edge_mask() & 0xC == 0 || attrs.coverage() == 0x1F {
+                continue;
+            }
+            let coverage = attrs.coverage() as u16;
+            let color = self.color_buffer[i].cast::<u16>();
+            let below_color = self.below_color_buffer[i].cast::<u16>();
+            self.color_buffer[i] = if below_color[3] == 0 {
+                // If there's nothing visible below the edge, only its alpha is blended
+                let mut color = color;
+                color[3] = (color[3] * (coverage + 1)) >> 5;
+                color
+            } else {
+                (color * InterpColor::splat(coverage + 1)
+                    + below_color * InterpColor::splat(0x1F - coverage))
+                    >> InterpColor::splat(5)
+            }
+            .cast();
+        }
+    }
+
+    fn rasterize_line(&mut self, y: u16, rendering_data: &RenderingData) {
+        let scale = self.scale;
+        let width = self.width();
+        let max_x = width as u16 - 1;
+        let line_range = y as usize * width..(y as usize + 1) * width;
+        let color_buffer = &mut self.color_buffer[line_range.clone()];
+        let below_color_buffer = &mut self.below_color_buffer[line_range.clone()];
+        let depth_buffer = &mut self.depth_buffer[line_range.clone()];
+        let attr_buffer = &mut self.attr_buffer[line_range];
+        let antialiasing_enabled = rendering_data.control.antialiasing_enabled();
 
         if rendering_data.control.rear_plane_bitmap_enabled() {
-            let line_base = (y.wrapping_add(rendering_data.clear_image_offset[1]) as usize) << 9;
-            let mut x_in_image = rendering_data.clear_image_offset[0];
+            // The rear plane bitmap is always sampled at the native resolution
+            let line_base = (((y / scale as u16) as u8)
+                .wrapping_add(rendering_data.clear_image_offset[1])
+                as usize)
+                << 9;
+            let x_in_image = |x: usize| {
+                rendering_data.clear_image_offset[0].wrapping_add((x / scale as usize) as u8)
+                    as usize
+            };
 
             let color_line_base = 0x4_0000 | line_base;
-            for x in 0..256 {
+            for x in 0..width {
                 let raw_color = rendering_data
                     .texture
-                    .read_le(color_line_base | (x_in_image as usize) << 1);
-                self.color_buffer[x] = rgb_5_to_6(decode_rgb_5(
+                    .read_le(color_line_base | x_in_image(x) << 1);
+                color_buffer[x] = rgb_5_to_6(decode_rgb_5(
                     raw_color,
                     if raw_color >> 15 != 0 { 31 } else { 0 },
                 ))
                 .cast();
-                x_in_image = x_in_image.wrapping_add(1);
             }
 
             let depth_line_base = 0x4_0000 | line_base;
             let pixel_attrs = PixelAttrs(0).with_opaque_id(rendering_data.clear_poly_id);
-            for x in 0..256 {
+            for x in 0..width {
                 let raw_depth = rendering_data
                     .texture
-                    .read_le(depth_line_base | (x_in_image as usize) << 1);
-                self.depth_buffer[x] = expand_depth(raw_depth);
-                self.attr_buffer[x] = pixel_attrs.with_fog_enabled(raw_depth >> 15 != 0);
-                x_in_image = x_in_image.wrapping_add(1);
+                    .read_le(depth_line_base | x_in_image(x) << 1);
+                depth_buffer[x] = expand_depth(raw_depth);
+                attr_buffer[x] = pixel_attrs.with_fog_enabled(raw_depth >> 15 != 0);
             }
         } else {
-            self.color_buffer
-                .fill(rgb_5_to_6(rendering_data.clear_color.cast()).cast());
-            self.depth_buffer
-                .fill(expand_depth(rendering_data.clear_depth));
-            self.attr_buffer.fill(
+            color_buffer.fill(rgb_5_to_6(rendering_data.clear_color.cast()).cast());
+            depth_buffer.fill(expand_depth(rendering_data.clear_depth));
+            attr_buffer.fill(
                 PixelAttrs(0)
                     .with_opaque_id(rendering_data.clear_poly_id)
                     .with_fog_enabled(rendering_data.rear_plane_fog_enabled),
             );
         }
+        below_color_buffer.copy_from_slice(color_buffer);
+
+        let mut stencil_buffer = [false; 256 * MAX_SCALE as usize];
+        let mut prev_poly_is_shadow_mask = false;
 
         for poly in self.polys.iter_mut() {
+            // The stencil buffer is cleared at the start of every group of consecutive shadow
+            // masks, even if they don't cover the current line
+            if poly.is_shadow_mask && !prev_poly_is_shadow_mask {
+                stencil_buffer.fill(false);
+            }
+            prev_poly_is_shadow_mask = poly.is_shadow_mask;
+
             if y.wrapping_sub(poly.top_y) >= poly.height {
                 continue;
             }
@@ -640,11 +862,12 @@ impl Renderer {
                                 let new_end_vert_addr = raw_poly.vertices[i.get() as usize];
                                 let new_end_vert =
                                     &rendering_data.vert_ram[new_end_vert_addr.get() as usize];
-                                let new_b_y = new_end_vert.coords[1] as u8;
+                                let new_b_y = scaled_coords(new_end_vert, scale)[1];
 
                                 if new_b_y > y || i == poly.bot_i {
                                     $edge = Edge::new(
                                         &raw_poly,
+                                        scale,
                                         *$vert_i,
                                         start_vert_addr,
                                         start_vert,
@@ -668,7 +891,7 @@ impl Renderer {
             }
 
             let mut edges = [&poly.edges[0], &poly.edges[1]];
-            let mut ranges = edges.map(|edge| edge.line_x_range(y));
+            let mut ranges = edges.map(|edge| edge.line_x_range(y, max_x));
 
             if ranges[1].1 <= ranges[0].0 {
                 edges.swap(0, 1);
@@ -680,9 +903,8 @@ impl Renderer {
             let x_span_len = x_span_end + 1 - x_span_start;
             let wireframe = poly.alpha == 0;
 
-            let fill_all_edges = wireframe
-                || rendering_data.control.antialiasing_enabled()
-                || rendering_data.control.edge_marking_enabled();
+            let fill_all_edges =
+                wireframe || antialiasing_enabled || rendering_data.control.edge_marking_enabled();
             let fill_edges = [
                 fill_all_edges || edges[0].is_negative() || !edges[0].is_x_major(),
                 fill_all_edges
@@ -690,7 +912,10 @@ impl Renderer {
                     || edges[1].x_incr() == 0,
             ];
 
-            let edge_mask = (y == poly.top_y) as u8 | (y == poly.bot_y - 1) as u8;
+            let edge_mask = PixelAttrs(0)
+                .with_top_edge(y == poly.top_y)
+                .with_bottom_edge(y == poly.top_y + (poly.height - 1))
+                .edge_mask();
 
             let [(l_vert_color, l_uv, l_depth, l_w), (r_vert_color, r_uv, r_depth, r_w)] =
                 [(edges[0], x_span_start), (edges[1], x_span_end)].map(|(edge, x)| {
@@ -708,13 +933,33 @@ impl Renderer {
 
             for i in 0..2 {
                 if fill_edges[i] {
+                    let edge_attrs = PixelAttrs::from_opaque_poly_attrs(poly)
+                        .with_edge_mask(edge_mask)
+                        .with_left_edge(i == 0)
+                        .with_right_edge(i == 1);
                     for x in ranges[i].0..=ranges[i].1 {
+                        let coverage = if antialiasing_enabled {
+                            edges[i].coverage(y, x, max_x, i == 1)
+                        } else {
+                            0x1F
+                        };
                         let interp = x_interp.set_x(x - x_span_start, x_span_len);
                         let x = x as usize;
                         let depth = interp.depth(l_depth, r_depth, rendering_data.w_buffering)
                             as u32
                             & 0x00FF_FFFF;
-                        if (poly.depth_test)(depth, self.depth_buffer[x], self.attr_buffer[x]) {
+                        if poly.is_shadow_mask {
+                            if !(poly.depth_test)(depth, depth_buffer[x], attr_buffer[x]) {
+                                stencil_buffer[x] = true;
+                            }
+                            continue;
+                        }
+                        if poly.is_shadow
+                            && (!stencil_buffer[x] || attr_buffer[x].opaque_id() == poly.id)
+                        {
+                            continue;
+                        }
+                        if (poly.depth_test)(depth, depth_buffer[x], attr_buffer[x]) {
                             let vert_color = interp.color(l_vert_color, r_vert_color);
                             let uv = interp.uv(l_uv, r_uv);
                             let mut color =
@@ -722,14 +967,15 @@ impl Renderer {
                             let alpha = color[3];
                             if alpha > rendering_data.alpha_test_ref as u16 {
                                 if alpha == 0x1F {
-                                    self.color_buffer[x] = color.cast();
-                                    self.depth_buffer[x] = depth;
-                                    self.attr_buffer[x] = PixelAttrs::from_opaque_poly_attrs(poly);
+                                    below_color_buffer[x] = color_buffer[x];
+                                    color_buffer[x] = color.cast();
+                                    depth_buffer[x] = depth;
+                                    attr_buffer[x] = edge_attrs.with_coverage(coverage);
                                 } else {
-                                    let prev_attrs = self.attr_buffer[x];
+                                    let prev_attrs = attr_buffer[x];
                                     if prev_attrs.translucent_id() != poly.id | 0x40 {
                                         if rendering_data.control.alpha_blending_enabled() {
-                                            let prev_color = self.color_buffer[x].cast();
+                                            let prev_color = color_buffer[x].cast();
                                             let prev_alpha = prev_color[3];
                                             if prev_alpha != 0 {
                                                 color = ((color * InterpColor::splat(alpha + 1))
@@ -739,14 +985,13 @@ impl Renderer {
                                                 color[3] = alpha.max(prev_alpha);
                                             }
                                         }
-                                        self.color_buffer[x] = color.cast();
+                                        color_buffer[x] = color.cast();
                                         if poly.attrs.update_depth_for_translucent() {
-                                            self.depth_buffer[x] = depth;
+                                            depth_buffer[x] = depth;
                                         }
-                                        self.attr_buffer[x] =
-                                            PixelAttrs::from_translucent_poly_attrs(
-                                                poly, prev_attrs,
-                                            );
+                                        attr_buffer[x] = PixelAttrs::from_translucent_poly_attrs(
+                                            poly, prev_attrs,
+                                        );
                                     }
                                 }
                             }
@@ -756,26 +1001,44 @@ impl Renderer {
             }
 
             if !wireframe || edge_mask != 0 {
+                let inner_attrs = PixelAttrs::from_opaque_poly_attrs(poly)
+                    .with_edge_mask(edge_mask)
+                    .with_coverage(0x1F);
                 for x in ranges[0].1 + 1..ranges[1].0 {
                     let interp = x_interp.set_x(x - x_span_start, x_span_len);
                     let x = x as usize;
                     let depth = interp.depth(l_depth, r_depth, rendering_data.w_buffering) as u32
                         & 0x00FF_FFFF;
-                    if (poly.depth_test)(depth, self.depth_buffer[x], self.attr_buffer[x]) {
+                    if poly.is_shadow_mask {
+                        // Set the stencil buffer wherever the shadow volume is behind other
+                        // geometry
+                        if !(poly.depth_test)(depth, depth_buffer[x], attr_buffer[x]) {
+                            stencil_buffer[x] = true;
+                        }
+                        continue;
+                    }
+                    // Shadows aren't drawn over opaque pixels with the same polygon ID, so that
+                    // objects don't cast shadows on themselves
+                    if poly.is_shadow
+                        && (!stencil_buffer[x] || attr_buffer[x].opaque_id() == poly.id)
+                    {
+                        continue;
+                    }
+                    if (poly.depth_test)(depth, depth_buffer[x], attr_buffer[x]) {
                         let vert_color = interp.color(l_vert_color, r_vert_color);
                         let uv = interp.uv(l_uv, r_uv);
                         let mut color = (poly.process_pixel)(rendering_data, poly, uv, vert_color);
                         let alpha = color[3];
                         if alpha > rendering_data.alpha_test_ref as u16 {
                             if alpha == 0x1F {
-                                self.color_buffer[x] = color.cast();
-                                self.depth_buffer[x] = depth;
-                                self.attr_buffer[x] = PixelAttrs::from_opaque_poly_attrs(poly);
+                                color_buffer[x] = color.cast();
+                                depth_buffer[x] = depth;
+                                attr_buffer[x] = inner_attrs;
                             } else {
-                                let prev_attrs = self.attr_buffer[x];
+                                let prev_attrs = attr_buffer[x];
                                 if prev_attrs.translucent_id() != poly.id | 0x40 {
                                     if rendering_data.control.alpha_blending_enabled() {
-                                        let prev_color = self.color_buffer[x].cast();
+                                        let prev_color = color_buffer[x].cast();
                                         let prev_alpha = prev_color[3];
                                         if prev_alpha != 0 {
                                             color = ((color * InterpColor::splat(alpha + 1))
@@ -784,11 +1047,11 @@ impl Renderer {
                                             color[3] = alpha.max(prev_alpha);
                                         }
                                     }
-                                    self.color_buffer[x] = color.cast();
+                                    color_buffer[x] = color.cast();
                                     if poly.attrs.update_depth_for_translucent() {
-                                        self.depth_buffer[x] = depth;
+                                        depth_buffer[x] = depth;
                                     }
-                                    self.attr_buffer[x] =
+                                    attr_buffer[x] =
                                         PixelAttrs::from_translucent_poly_attrs(poly, prev_attrs);
                                 }
                             }
@@ -797,10 +1060,64 @@ impl Renderer {
                 }
             }
         }
+    }
+
+    /// Renders line `y` to `scanline`; after [`start_frame`](Self::start_frame), lines have to be
+    /// requested in increasing order, but they don't need to start from 0 or to be contiguous, so
+    /// that separate renderers can each process a band of the screen.
+    ///
+    /// When rendering at a higher resolution, `scanline` receives the top-left sample of each
+    /// pixel, and the full-resolution output can be retrieved through
+    /// [`read_scaled_line`](Self::read_scaled_line).
+    pub fn render_line(
+        &mut self,
+        y: u8,
+        scanline: &mut Scanline<u32, 256>,
+        rendering_data: &RenderingData,
+    ) {
+        let scale = self.scale as usize;
+
+        // Edge marking needs the lines right above and below the current one to be rasterized too
+        let rasterize_start = self.next_rasterized_line.max(y.saturating_sub(1));
+        let rasterize_end = (y as usize + 2).min(SCREEN_HEIGHT) as u8;
+        for line in rasterize_start..rasterize_end {
+            for scaled_line in line as usize * scale..(line as usize + 1) * scale {
+                self.rasterize_line(scaled_line as u16, rendering_data);
+            }
+        }
+        self.next_rasterized_line = self.next_rasterized_line.max(rasterize_end);
 
+        for scaled_y in y as usize * scale..(y as usize + 1) * scale {
+            if rendering_data.control.edge_marking_enabled() {
+                self.apply_edge_marking(scaled_y, rendering_data);
+            }
+
+            if rendering_data.control.fog_enabled() {
+                self.apply_fog(scaled_y, rendering_data);
+            }
+
+            if rendering_data.control.antialiasing_enabled() {
+                self.apply_antialiasing(scaled_y);
+            }
+        }
+
+        let line_base = y as usize * scale * self.width();
         for x in 0..256 {
-            let [r, g, b, a] = self.color_buffer[x].to_array();
-            scanline.0[x] = r as u32 | (g as u32) << 6 | (b as u32) << 12 | (a as u32) << 18
+            scanline.0[x] = pack_color(self.color_buffer[line_base + x * scale]);
+        }
+    }
+
+    /// Writes the `scale` full-resolution lines corresponding to line `y` to `output`, in the same
+    /// format as [`render_line`](Self::render_line); line `y` needs to have been rendered in the
+    /// current frame already.
+    pub fn read_scaled_line(&self, y: u8, output: &mut [u32]) {
+        let width = self.width();
+        let line_base = y as usize * self.scale as usize * width;
+        for (output, &color) in output
+            .iter_mut()
+            .zip(&self.color_buffer[line_base..line_base + width * self.scale as usize])
+        {
+            *output = pack_color(color);
         }
     }
 }
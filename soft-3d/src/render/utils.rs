@@ -20,15 +20,26 @@ pub fn dec_poly_vert_index(i: PolyVertIndex, verts: PolyVertsLen) -> PolyVertInd
     }
 }
 
+/// Returns the coordinates of `vert` at `scale` times the native resolution, taking its
+/// fractional part into account.
+pub fn scaled_coords(vert: &ScreenVertex, scale: u8) -> [u16; 2] {
+    if scale == 1 {
+        return vert.coords.to_array();
+    }
+    [0, 1].map(|i| {
+        (((vert.coords[i] as u32) << 8 | vert.coords_fract[i] as u32) * scale as u32 >> 8) as u16
+    })
+}
+
 #[derive(Clone, Copy, Debug)]
 pub struct Edge {
     a_addr: VertexAddr,
-    a_y: u8,
+    a_y: u16,
     a_z: i32,
     a_w: u16,
 
     b_addr: VertexAddr,
-    b_y: u8,
+    b_y: u16,
     b_z: i32,
     b_w: u16,
 
@@ -44,8 +55,10 @@ pub struct Edge {
 }
 
 impl Edge {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         poly: &Polygon,
+        scale: u8,
         a_i: PolyVertIndex,
         a_addr: VertexAddr,
         a: &ScreenVertex,
@@ -58,12 +71,10 @@ impl Edge {
         let a_w = poly.w_values[a_i.get() as usize];
         let b_w = poly.w_values[b_i.get() as usize];
 
-        let a_x = a.coords[0];
-        let b_x = b.coords[0];
-        let a_y = a.coords[1] as u8;
-        let b_y = b.coords[1] as u8;
+        let [a_x, a_y] = scaled_coords(a, scale);
+        let [b_x, b_y] = scaled_coords(b, scale);
         let x_diff = b_x as i16 - a_x as i16;
-        let y_len = (b_y - a_y) as u16;
+        let y_len = b_y - a_y;
 
         let mut x_ref = (a_x as i32) << 18;
 
@@ -107,11 +118,7 @@ impl Edge {
             is_x_major,
             is_negative,
 
-            interp_ref: if is_x_major {
-                a_x.min(b_x) as u16
-            } else {
-                a_y as u16
-            },
+            interp_ref: if is_x_major { a_x.min(b_x) } else { a_y },
             interp_len: if is_x_major { x_len } else { y_len },
             interp_data: InterpLineData::new(a_w, b_w),
         }
@@ -133,7 +140,7 @@ impl Edge {
         self.b_addr
     }
 
-    pub fn b_y(&self) -> u8 {
+    pub fn b_y(&self) -> u16 {
         self.b_y
     }
 
@@ -157,25 +164,26 @@ impl Edge {
         self.is_x_major
     }
 
-    pub fn line_x_range(&self, y: u8) -> (u16, u16) {
+    pub fn line_x_range(&self, y: u16, max_x: u16) -> (u16, u16) {
         let line_x_disp = self.x_incr * (y - self.a_y) as i32;
         let start_frac_x = if self.is_negative {
             self.x_ref - line_x_disp
         } else {
             self.x_ref + line_x_disp
         };
-        let start_x = (start_frac_x >> 18).clamp(0, 255) as u16;
+        let start_x = (start_frac_x >> 18).clamp(0, max_x as i32) as u16;
         if self.is_x_major {
             if self.is_negative {
                 (
                     (((start_frac_x + (0x1FF - (start_frac_x & 0x1FF)) - self.x_incr) >> 18) + 1)
-                        .clamp(0, 255) as u16,
+                        .clamp(0, max_x as i32) as u16,
                     start_x,
                 )
             } else {
                 (
                     start_x,
-                    (((((start_frac_x & !0x1FF) + self.x_incr) >> 18) - 1).clamp(0, 255) as u16),
+                    (((((start_frac_x & !0x1FF) + self.x_incr) >> 18) - 1).clamp(0, max_x as i32)
+                        as u16),
                 )
             }
         } else {
@@ -183,7 +191,43 @@ impl Edge {
         }
     }
 
-    pub fn edge_interp(&self, y: u8, x: u16) -> InterpData<true> {
+    /// Returns the 5-bit coverage of pixel `x` on line `y` by the polygon the edge belongs to,
+    /// used for antialiasing.
+    pub fn coverage(&self, y: u16, x: u16, max_x: u16, is_right: bool) -> u8 {
+        if self.x_incr == 0 {
+            return 0x1F;
+        }
+        let coverage = if self.is_x_major {
+            // Coverage changes linearly along the pixels the edge spans on the current line,
+            // increasing towards the inside of the polygon
+            let (start_x, end_x) = self.line_x_range(y, max_x);
+            let len = (end_x - start_x + 1) as u32;
+            let i = x.clamp(start_x, end_x) - start_x;
+            ((((i as u32) << 1 | 1) << 5) / (len << 1)).min(0x1F) as u8
+        } else {
+            // Use the position of the edge in the middle of the line, with full coverage if it
+            // crosses into the next pixel
+            let line_x_disp = self.x_incr * (y - self.a_y) as i32;
+            let (start_frac_x, mid_frac_x) = if self.is_negative {
+                let start_frac_x = self.x_ref - line_x_disp;
+                (start_frac_x, start_frac_x - (self.x_incr >> 1))
+            } else {
+                let start_frac_x = self.x_ref + line_x_disp;
+                (start_frac_x, start_frac_x + (self.x_incr >> 1))
+            };
+            if mid_frac_x >> 18 != start_frac_x >> 18 {
+                return 0x1F;
+            }
+            0x1F - (mid_frac_x >> 13 & 0x1F) as u8
+        };
+        if is_right {
+            0x1F - coverage
+        } else {
+            coverage
+        }
+    }
+
+    pub fn edge_interp(&self, y: u16, x: u16) -> InterpData<true> {
         self.interp_data.set_x(
             if self.is_x_major {
                 let rel = x - self.interp_ref;
@@ -193,7 +237,7 @@ impl Edge {
                     rel
                 }
             } else {
-                y as u16 - self.interp_ref
+                y - self.interp_ref
             },
             self.interp_len,
         )